@@ -0,0 +1,581 @@
+//! Importing Anki decks from .apkg files.
+//!
+//! An .apkg is a zip file with the collection as an sqlite database, the media files named
+//! "0", "1", ... and a `media` json file mapping those numbers to the real file names.
+//! Every note becomes a card and decks become nested categories. The review log of the note's
+//! first card becomes the card's history, a second card is the reverse direction, and for cloze
+//! notes every card is the deletion with the same number.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rusqlite::{Connection, OpenFlags};
+use serde::Deserialize;
+use zip::ZipArchive;
+
+use crate::card::{Card, CardCache, Grade, IsSuspended, Meta, Review, Reviews, SavedCard, Side};
+use crate::categories::Category;
+use crate::cloze;
+use crate::media::{AudioSource, MediaStore};
+
+#[derive(Debug)]
+pub enum AnkiError {
+    Io(std::io::Error),
+    Zip(zip::result::ZipError),
+    Sqlite(rusqlite::Error),
+    Json(serde_json::Error),
+    UnsupportedFormat(String),
+}
+
+impl fmt::Display for AnkiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Zip(e) => write!(f, "not a valid apkg file: {e}"),
+            Self::Sqlite(e) => write!(f, "could not read the anki collection: {e}"),
+            Self::Json(e) => write!(f, "could not read the anki metadata: {e}"),
+            Self::UnsupportedFormat(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+impl std::error::Error for AnkiError {}
+
+impl From<std::io::Error> for AnkiError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<zip::result::ZipError> for AnkiError {
+    fn from(e: zip::result::ZipError) -> Self {
+        Self::Zip(e)
+    }
+}
+
+impl From<rusqlite::Error> for AnkiError {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::Sqlite(e)
+    }
+}
+
+impl From<serde_json::Error> for AnkiError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+#[derive(Deserialize)]
+struct DeckJson {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ModelJson {
+    // 0 is a normal note type, 1 is cloze.
+    #[serde(default, rename = "type")]
+    kind: i64,
+}
+
+/// Again is a lapse, the rest are passes.
+fn ease_to_grade(ease: i64) -> Option<Grade> {
+    match ease {
+        1 => Some(Grade::None),
+        2 | 3 => Some(Grade::Some),
+        4 => Some(Grade::Perfect),
+        _ => None,
+    }
+}
+
+fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find(';').filter(|end| *end < 10) else {
+            out.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "nbsp" => Some(' '),
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|num| num.parse().ok()))
+                .and_then(char::from_u32),
+        };
+
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Anki fields are html, we only keep the text and turn line breaks into newlines.
+fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut tag = String::new();
+    let mut in_tag = false;
+
+    for c in html.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                tag.clear();
+            }
+            '>' if in_tag => {
+                in_tag = false;
+                let name = tag
+                    .split(|c: char| c.is_whitespace() || c == '/')
+                    .find(|s| !s.is_empty())
+                    .unwrap_or_default()
+                    .to_lowercase();
+                let opening = !tag.starts_with('/');
+                let breaks =
+                    name == "br" || (opening && matches!(name.as_str(), "div" | "p" | "li"));
+                if breaks && !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+            }
+            c if in_tag => tag.push(c),
+            c => text.push(c),
+        }
+    }
+
+    decode_entities(&text).trim().to_string()
+}
+
+/// Removes the `[sound:file]` tags from a field, returning the first sound file.
+fn extract_sound(field: &str) -> (String, Option<String>) {
+    let mut text = String::new();
+    let mut sound = None;
+    let mut rest = field;
+
+    while let Some(start) = rest.find("[sound:") {
+        text.push_str(&rest[..start]);
+        let after = &rest[start + "[sound:".len()..];
+        let Some(end) = after.find(']') else {
+            text.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        if sound.is_none() {
+            sound = Some(after[..end].to_string());
+        }
+        rest = &after[end + 1..];
+    }
+    text.push_str(rest);
+    (text, sound)
+}
+
+fn side(field: &str) -> Side {
+    let (text, sound) = extract_sound(field);
+    Side {
+        text: html_to_text(&text),
+        audio: AudioSource::new(sound, None),
+        ..Default::default()
+    }
+}
+
+fn deck_category(base: &Category, deck: &str) -> Category {
+    let mut category = base.clone();
+    for part in deck.split("::") {
+        // Folders starting with '_' are hidden from speki, and slashes would nest them.
+        let part = part.replace('/', "-");
+        let part = part.trim().trim_start_matches('_');
+        category.0.push(if part.is_empty() {
+            "unnamed".to_string()
+        } else {
+            part.to_string()
+        });
+    }
+    category
+}
+
+struct AnkiCard {
+    id: i64,
+    // Which of the note's templates it is, or the deletion number minus one for clozes.
+    ord: u32,
+    deck: i64,
+    suspended: bool,
+}
+
+/// The parsed contents of an apkg, nothing is written until `save` is called.
+pub struct AnkiImport {
+    path: PathBuf,
+    pub cards: Vec<(Category, Card)>,
+    pub reviews: usize,
+    // (name inside the zip, real file name)
+    media: Vec<(String, String)>,
+}
+
+impl AnkiImport {
+    pub fn read(path: &Path, base: &Category) -> Result<Self, AnkiError> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+
+        let collection = ["collection.anki21", "collection.anki2"]
+            .into_iter()
+            .find(|name| archive.index_for_name(name).is_some());
+
+        let Some(collection) = collection else {
+            let msg = if archive.index_for_name("collection.anki21b").is_some() {
+                "this apkg uses the newest anki format, export it again with 'support older anki versions' checked"
+            } else {
+                "no anki collection found in the file"
+            };
+            return Err(AnkiError::UnsupportedFormat(msg.to_string()));
+        };
+
+        // sqlite needs a real file to open.
+        let db_path =
+            std::env::temp_dir().join(format!("speki-anki-{}.sqlite", uuid::Uuid::new_v4()));
+        std::io::copy(
+            &mut archive.by_name(collection)?,
+            &mut File::create(&db_path)?,
+        )?;
+        let collection = Self::read_collection(&db_path, base);
+        let _ = std::fs::remove_file(&db_path);
+        let (cards, reviews) = collection?;
+
+        let media = match archive.by_name("media") {
+            Ok(file) => {
+                let map: HashMap<String, String> = serde_json::from_reader(file)?;
+                let mut media: Vec<_> = map.into_iter().collect();
+                media.sort();
+                media
+            }
+            Err(_) => vec![],
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            cards,
+            reviews,
+            media,
+        })
+    }
+
+    /// Returns the cards and how many reviews they have in total.
+    fn read_collection(
+        db_path: &Path,
+        base: &Category,
+    ) -> Result<(Vec<(Category, Card)>, usize), AnkiError> {
+        let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+        let (decks, models): (String, String) =
+            conn.query_row("SELECT decks, models FROM col", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+        let decks: HashMap<String, DeckJson> = serde_json::from_str(&decks)?;
+        let models: HashMap<String, ModelJson> = serde_json::from_str(&models)?;
+
+        let mut reviews: HashMap<i64, Vec<Review>> = HashMap::new();
+        let mut stmt = conn.prepare("SELECT id, cid, ease, time, type FROM revlog ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
+        })?;
+        for row in rows {
+            let (id, card_id, ease, time, kind) = row?;
+            // type 4 is a manual reschedule, not an actual review.
+            let Some(grade) = ease_to_grade(ease).filter(|_| kind != 4) else {
+                continue;
+            };
+            reviews.entry(card_id).or_default().push(Review {
+                timestamp: Duration::from_millis(id.max(0) as u64),
+                grade,
+                time_spent: Duration::from_millis(time.max(0) as u64),
+                ..Default::default()
+            });
+        }
+
+        // A note can have several cards, e.g. reversed ones or one per cloze deletion.
+        let mut note_cards: HashMap<i64, Vec<AnkiCard>> = HashMap::new();
+        let mut stmt =
+            conn.prepare("SELECT id, nid, did, ord, queue FROM cards ORDER BY nid, ord")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, u32>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
+        })?;
+        for row in rows {
+            let (id, note_id, deck, ord, queue) = row?;
+            note_cards.entry(note_id).or_default().push(AnkiCard {
+                id,
+                ord,
+                deck,
+                suspended: queue == -1,
+            });
+        }
+
+        let mut cards = vec![];
+        let mut review_qty = 0;
+        let mut stmt = conn.prepare("SELECT id, mid, tags, flds FROM notes ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        for row in rows {
+            let (note_id, model_id, tags, fields) = row?;
+            let Some(anki_cards) = note_cards.get(&note_id) else {
+                continue;
+            };
+            let first = &anki_cards[0];
+
+            let fields: Vec<&str> = fields.split('\x1f').collect();
+            let is_cloze = models
+                .get(&model_id.to_string())
+                .map(|model| model.kind == 1)
+                .unwrap_or(false);
+
+            let (front, back) = if is_cloze {
+                // Same syntax as ours, so the deletions are kept as they are.
+                (
+                    side(fields[0]),
+                    side(fields.get(1).copied().unwrap_or_default()),
+                )
+            } else {
+                let back = fields[1..]
+                    .iter()
+                    .filter(|field| !field.trim().is_empty())
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join("<br>");
+                (side(fields[0]), side(&back))
+            };
+
+            let deck = decks
+                .get(&first.deck.to_string())
+                .map(|deck| deck.name.as_str())
+                .unwrap_or("Default");
+
+            let meta = Meta {
+                tags: tags
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect::<BTreeSet<_>>(),
+                // We can only suspend the whole card, so it's kept for as long as any part is reviewed.
+                suspended: IsSuspended::from(anki_cards.iter().all(|card| card.suspended)),
+                ..Default::default()
+            };
+
+            let mut card = Card::new(front, back, meta);
+            for anki_card in anki_cards {
+                let history = reviews.remove(&anki_card.id).unwrap_or_default();
+                let qty = history.len();
+                let history = Reviews::from(history);
+                match (is_cloze, anki_card.ord) {
+                    (true, ord) => {
+                        card.clozes.insert(cloze::key(ord + 1), history);
+                    }
+                    (false, 0) => card.history = history,
+                    (false, 1) => {
+                        card.reversible = true;
+                        card.reverse_history = history;
+                    }
+                    // Note types with more templates than that have no side to review them from.
+                    (false, _) => continue,
+                }
+                review_qty += qty;
+            }
+            card.clozes.retain(|_, history| !history.is_empty());
+            cards.push((deck_category(base, deck), card));
+        }
+
+        Ok((cards, review_qty))
+    }
+
+    pub fn summary(&self) -> String {
+        let categories: BTreeSet<String> = self
+            .cards
+            .iter()
+            .map(|(category, _)| category.print_full())
+            .collect();
+
+        let mut s = format!(
+            "notes: {}, reviews: {}, media files: {}\n",
+            self.cards.len(),
+            self.reviews,
+            self.media.len()
+        );
+        for category in categories {
+            s.push_str(&format!("  {category}\n"));
+        }
+        s
+    }
+
+    /// Copies the media files into the store and saves the cards, pointing at the stored files.
+    pub fn save(self, cache: &mut CardCache) -> Result<Vec<SavedCard>, AnkiError> {
+        let mut archive = ZipArchive::new(File::open(&self.path)?)?;
+        let store = MediaStore::open();
+
+        let mut stored = HashMap::new();
+        for (entry, name) in &self.media {
+            let Ok(mut file) = archive.by_name(entry) else {
+                continue;
+            };
+            let mut content = vec![];
+            file.read_to_end(&mut content)?;
+            let extension = Path::new(name).extension().and_then(|ext| ext.to_str());
+            stored.insert(name.clone(), store.add_bytes(&content, extension)?);
+        }
+
+        Ok(self
+            .cards
+            .into_iter()
+            .map(|(category, mut card)| {
+                for side in [&mut card.front, &mut card.back] {
+                    let name = side.audio.local_file().and_then(|name| stored.get(name));
+                    if let Some(name) = name.cloned() {
+                        side.audio.set_local_file(name);
+                    }
+                }
+                card.save_new_card(&category, cache)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn make_apkg(path: &Path) {
+        let db_path = path.with_extension("sqlite");
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE col (decks TEXT, models TEXT);
+            CREATE TABLE notes (id INTEGER, mid INTEGER, tags TEXT, flds TEXT);
+            CREATE TABLE cards (id INTEGER, nid INTEGER, did INTEGER, ord INTEGER, queue INTEGER);
+            CREATE TABLE revlog (id INTEGER, cid INTEGER, ease INTEGER, time INTEGER, type INTEGER);
+            INSERT INTO col VALUES (
+                '{"1": {"name": "Default"}, "2": {"name": "Spanish::Verbs"}}',
+                '{"10": {"type": 0}, "11": {"type": 1}}'
+            );
+            INSERT INTO notes VALUES (100, 10, ' verbs spanish ', 'hablar [sound:hablar.mp3]' || char(31) || 'to <b>speak</b><br>&amp; talk');
+            INSERT INTO notes VALUES (101, 11, '', 'the {{c1::sun::star}} is hot' || char(31) || '');
+            INSERT INTO cards VALUES (1000, 100, 2, 0, 2);
+            INSERT INTO cards VALUES (1001, 100, 2, 1, -1);
+            INSERT INTO cards VALUES (1002, 101, 1, 0, -1);
+            INSERT INTO revlog VALUES (1687124756000, 1000, 3, 4500, 0);
+            INSERT INTO revlog VALUES (1687158818000, 1000, 1, 8000, 1);
+            INSERT INTO revlog VALUES (1687248985000, 1000, 0, 0, 4);
+            INSERT INTO revlog VALUES (1687248985000, 1001, 4, 0, 1);
+            INSERT INTO revlog VALUES (1687248985000, 1002, 1, 0, 1);
+            "#,
+        )
+        .unwrap();
+        drop(conn);
+
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        let options = SimpleFileOptions::default();
+        zip.start_file("collection.anki2", options).unwrap();
+        zip.write_all(&std::fs::read(&db_path).unwrap()).unwrap();
+        zip.start_file("media", options).unwrap();
+        zip.write_all(br#"{"0": "hablar.mp3"}"#).unwrap();
+        zip.start_file("0", options).unwrap();
+        zip.write_all(b"not really audio").unwrap();
+        zip.finish().unwrap();
+        std::fs::remove_file(db_path).unwrap();
+    }
+
+    #[test]
+    fn test_read_apkg() {
+        let path = std::env::temp_dir().join(format!("speki-test-{}.apkg", uuid::Uuid::new_v4()));
+        make_apkg(&path);
+        let import = AnkiImport::read(&path, &Category::root()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(import.cards.len(), 2);
+        assert_eq!(
+            import.media,
+            vec![("0".to_string(), "hablar.mp3".to_string())]
+        );
+
+        let (category, card) = &import.cards[0];
+        assert_eq!(category, &Category::from_joined("Spanish/Verbs"));
+        assert_eq!(card.front.text, "hablar");
+        assert_eq!(
+            card.front.audio,
+            AudioSource::new(Some("hablar.mp3".into()), None)
+        );
+        assert_eq!(card.back.text, "to speak\n& talk");
+        assert_eq!(card.meta.tags.len(), 2);
+        // One of its cards isn't suspended, so there's still something to review.
+        assert!(!card.meta.suspended.is_suspended());
+        assert_eq!(import.reviews, 4);
+        assert!(card.history.stability(&crate::scheduler::Classic).is_some());
+        assert!(card.reversible);
+        assert!(!card.reverse_history.is_empty());
+
+        let (category, card) = &import.cards[1];
+        assert_eq!(category, &Category::from_joined("Default"));
+        assert_eq!(card.front.text, "the {{c1::sun::star}} is hot");
+        assert_eq!(card.back.text, "");
+        assert!(card.meta.suspended.is_suspended());
+        assert!(card.history.is_empty());
+        assert_eq!(card.clozes.keys().collect::<Vec<_>>(), vec!["c1"]);
+    }
+
+    #[test]
+    fn test_html_to_text() {
+        assert_eq!(
+            html_to_text("<div>one</div><div>two&nbsp;&lt;3&#33;</div>"),
+            "one\ntwo <3!"
+        );
+        assert_eq!(
+            decode_entities("fish & chips &bogus;"),
+            "fish & chips &bogus;"
+        );
+    }
+
+    #[test]
+    fn test_deck_category() {
+        let base = Category::from_joined("anki");
+        assert_eq!(
+            deck_category(&base, "_Hidden::a/b"),
+            Category::from_joined("anki/Hidden/a-b")
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Checking typed answers, for decks where spelling matters.
+//!
+//! Case, extra whitespace and diacritics don't count, whatever is left is compared by edit
+//! distance, which gives the suggested grade and a diff of what was typed.
+
+use crate::card::{Grade, SavedCard};
+use crate::config::Config;
+
+/// What has to change in the typed answer to get the expected one.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Edit {
+    Same(char),
+    /// Typed `typed` where `expected` was expected.
+    Wrong {
+        typed: char,
+        expected: char,
+    },
+    /// Left out.
+    Missing(char),
+    /// Typed but not in the answer.
+    Extra(char),
+}
+
+#[derive(Debug)]
+pub struct Checked {
+    pub distance: usize,
+    pub diff: Vec<Edit>,
+    pub grade: Grade,
+}
+
+/// The letter without its accent, for the latin letters that have one.
+fn fold(c: char) -> char {
+    const FOLDS: [(&str, char); 15] = [
+        ("àáâãäåāăą", 'a'),
+        ("çćĉċč", 'c'),
+        ("ďđ", 'd'),
+        ("èéêëēĕėęě", 'e'),
+        ("ĝğġģ", 'g'),
+        ("ìíîïĩīĭįı", 'i'),
+        ("ĺļľŀł", 'l'),
+        ("ñńņňŉ", 'n'),
+        ("òóôõöøōŏő", 'o'),
+        ("ŕŗř", 'r'),
+        ("śŝşšș", 's'),
+        ("ţťŧț", 't'),
+        ("ùúûüũūŭůűų", 'u'),
+        ("ýÿŷ", 'y'),
+        ("žźż", 'z'),
+    ];
+    match FOLDS.iter().find(|(accented, _)| accented.contains(c)) {
+        Some((_, plain)) => *plain,
+        None => c,
+    }
+}
+
+pub fn normalize(s: &str) -> String {
+    let lowercase = s.to_lowercase();
+    let words: Vec<String> = lowercase
+        .split_whitespace()
+        .map(|word| word.chars().map(fold).collect())
+        .collect();
+    words.join(" ")
+}
+
+/// Levenshtein distance, walked back to get the edits that make `typed` into `expected`.
+fn diff(typed: &[char], expected: &[char]) -> (usize, Vec<Edit>) {
+    let (n, m) = (typed.len(), expected.len());
+    let mut cost = vec![vec![0; m + 1]; n + 1];
+    for (i, row) in cost.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in cost[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=n {
+        for j in 1..=m {
+            let substitution = cost[i - 1][j - 1] + usize::from(typed[i - 1] != expected[j - 1]);
+            cost[i][j] = substitution.min(cost[i - 1][j] + 1).min(cost[i][j - 1] + 1);
+        }
+    }
+
+    let mut edits = vec![];
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        if i > 0 && j > 0 {
+            let same = typed[i - 1] == expected[j - 1];
+            if cost[i][j] == cost[i - 1][j - 1] + usize::from(!same) {
+                edits.push(match same {
+                    true => Edit::Same(expected[j - 1]),
+                    false => Edit::Wrong {
+                        typed: typed[i - 1],
+                        expected: expected[j - 1],
+                    },
+                });
+                i -= 1;
+                j -= 1;
+                continue;
+            }
+        }
+        if j > 0 && (i == 0 || cost[i][j] == cost[i][j - 1] + 1) {
+            edits.push(Edit::Missing(expected[j - 1]));
+            j -= 1;
+        } else {
+            edits.push(Edit::Extra(typed[i - 1]));
+            i -= 1;
+        }
+    }
+    edits.reverse();
+    (cost[n][m], edits)
+}
+
+pub fn check(typed: &str, expected: &str) -> Checked {
+    let typed: Vec<char> = normalize(typed).chars().collect();
+    let expected: Vec<char> = normalize(expected).chars().collect();
+    let (distance, diff) = diff(&typed, &expected);
+
+    // A typo every five letters still counts as knowing it, so short answers have to be exact.
+    let grade = if distance == 0 {
+        Grade::Perfect
+    } else if typed.is_empty() {
+        Grade::None
+    } else if distance <= expected.len() / 5 {
+        Grade::Some
+    } else {
+        Grade::Late
+    };
+    Checked {
+        distance,
+        diff,
+        grade,
+    }
+}
+
+/// Whether answers to the card are typed in, from `typed_answers` in the config.
+pub fn is_typed(card: &SavedCard, config: &Config) -> bool {
+    config
+        .typed_answers
+        .iter()
+        .any(|entry| match entry.strip_prefix('#') {
+            Some(tag) => card.card_as_ref().meta.tags.contains(tag),
+            None => {
+                let category = crate::categories::Category::from_joined(entry);
+                card.category().0.starts_with(&category.0)
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("  Über   Straße\t"), "uber straße");
+        assert_eq!(normalize("Crème Brûlée"), "creme brulee");
+        assert_eq!(normalize("Žižek"), "zizek");
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check("Café", "cafe").grade, Grade::Perfect);
+        assert_eq!(check("receve", "receive").grade, Grade::Some);
+        assert_eq!(check("banana", "receive").grade, Grade::Late);
+        assert_eq!(check("", "receive").grade, Grade::None);
+        assert_eq!(check("", "a").grade, Grade::None);
+        assert_eq!(check("b", "a").grade, Grade::Late);
+        assert_eq!(check("b", "ab").grade, Grade::Late);
+    }
+
+    #[test]
+    fn test_diff() {
+        let checked = check("kat", "cats");
+        assert_eq!(checked.distance, 2);
+        assert_eq!(
+            checked.diff,
+            vec![
+                Edit::Wrong {
+                    typed: 'k',
+                    expected: 'c'
+                },
+                Edit::Same('a'),
+                Edit::Same('t'),
+                Edit::Missing('s'),
+            ]
+        );
+        let checked = check("catts", "cats");
+        assert_eq!(
+            checked
+                .diff
+                .iter()
+                .filter(|edit| matches!(edit, Edit::Extra('t')))
+                .count(),
+            1
+        );
+    }
+}
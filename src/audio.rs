@@ -0,0 +1,210 @@
+//! Playing the audio on card sides.
+//!
+//! Where the sound goes is an `AudioSink`: the `audio_player` command from the config if
+//! there is one, otherwise rodio when built with the `audio` feature. Tests use `NullSink`.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crate::config::Config;
+use crate::media::AudioSource;
+use crate::paths::get_media_path;
+
+/// What to play, a file in the media folder or, if that isn't there, where to get it.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Track {
+    File(PathBuf),
+    Url(String),
+}
+
+impl Track {
+    pub fn from_source(audio: &AudioSource) -> Option<Self> {
+        let local = audio
+            .local_file()
+            .map(|name| get_media_path().join(name))
+            .filter(|path| path.exists());
+        match (local, audio.url()) {
+            (Some(path), _) => Some(Self::File(path)),
+            (None, Some(url)) => Some(Self::Url(url.to_string())),
+            (None, None) => None,
+        }
+    }
+}
+
+impl fmt::Display for Track {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Url(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AudioError {
+    /// The player command couldn't be started.
+    Player {
+        command: String,
+        source: std::io::Error,
+    },
+    /// Only the player command can play urls.
+    #[cfg(feature = "audio")]
+    Url(String),
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Player { command, source } => write!(f, "couldn't run {command}: {source}"),
+            #[cfg(feature = "audio")]
+            Self::Url(url) => write!(
+                f,
+                "can't play {url} without downloading it, set audio_player to play it"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+pub trait AudioSink {
+    /// Starts playing the track, without waiting for it to end.
+    fn play(&mut self, track: &Track) -> Result<(), AudioError>;
+}
+
+/// Plays nothing, only remembers what it was asked to play.
+#[derive(Default)]
+pub struct NullSink {
+    pub played: Vec<Track>,
+}
+
+impl AudioSink for NullSink {
+    fn play(&mut self, track: &Track) -> Result<(), AudioError> {
+        self.played.push(track.clone());
+        Ok(())
+    }
+}
+
+/// An external player like `mpv --no-video`, the track goes after the arguments.
+pub struct CommandSink {
+    command: String,
+}
+
+impl AudioSink for CommandSink {
+    fn play(&mut self, track: &Track) -> Result<(), AudioError> {
+        let mut words = self.command.split_whitespace();
+        let program = words.next().unwrap_or_default();
+        Command::new(program)
+            .args(words)
+            .arg(track.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            // Waited on in the background so it doesn't linger as a zombie.
+            .map(|mut child| drop(std::thread::spawn(move || child.wait())))
+            .map_err(|source| AudioError::Player {
+                command: self.command.clone(),
+                source,
+            })
+    }
+}
+
+#[cfg(feature = "audio")]
+pub struct RodioSink;
+
+#[cfg(feature = "audio")]
+impl AudioSink for RodioSink {
+    fn play(&mut self, track: &Track) -> Result<(), AudioError> {
+        let path = match track {
+            Track::File(path) => path.clone(),
+            Track::Url(url) => return Err(AudioError::Url(url.clone())),
+        };
+        // The stream has to live as long as the sound plays, so it all happens on its own
+        // thread. Failing there only means no sound, nothing to tell the user about.
+        std::thread::spawn(move || -> Option<()> {
+            let (_stream, handle) = rodio::OutputStream::try_default().ok()?;
+            let sink = rodio::Sink::try_new(&handle).ok()?;
+            let file = std::io::BufReader::new(std::fs::File::open(path).ok()?);
+            sink.append(rodio::Decoder::new(file).ok()?);
+            sink.sleep_until_end();
+            Some(())
+        });
+        Ok(())
+    }
+}
+
+/// The sink the config asks for, a silent one if audio is turned off.
+pub fn sink(config: &Config) -> Box<dyn AudioSink> {
+    if !config.play_audio {
+        return Box::<NullSink>::default();
+    }
+    if let Some(command) = config.audio_player.clone() {
+        return Box::new(CommandSink { command });
+    }
+    #[cfg(feature = "audio")]
+    return Box::new(RodioSink);
+    #[cfg(not(feature = "audio"))]
+    Box::<NullSink>::default()
+}
+
+/// Plays the audio of a side, false if it doesn't have any.
+pub fn play(audio: &AudioSource, sink: &mut dyn AudioSink) -> Result<bool, AudioError> {
+    let Some(track) = Track::from_source(audio) else {
+        return Ok(false);
+    };
+    sink.play(&track)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track() {
+        let _share = crate::paths::TempShare::new();
+        let name = "bark.mp3".to_string();
+        let missing = AudioSource::new(Some(name.clone()), Some("https://x/1.mp3".into()));
+        assert_eq!(
+            Track::from_source(&missing),
+            Some(Track::Url("https://x/1.mp3".into()))
+        );
+
+        std::fs::create_dir_all(get_media_path()).unwrap();
+        let path = get_media_path().join(&name);
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(Track::from_source(&missing), Some(Track::File(path)));
+
+        assert_eq!(Track::from_source(&AudioSource::default()), None);
+    }
+
+    #[test]
+    fn test_play() {
+        let mut sink = NullSink::default();
+        let audio = AudioSource::new(None, Some("https://x/1.mp3".into()));
+        assert!(play(&audio, &mut sink).unwrap());
+        assert!(!play(&AudioSource::default(), &mut sink).unwrap());
+        assert_eq!(sink.played, vec![Track::Url("https://x/1.mp3".into())]);
+    }
+
+    #[test]
+    fn test_sink_honors_play_audio() {
+        let config = Config {
+            play_audio: false,
+            audio_player: Some("definitely-not-a-player".into()),
+            ..Default::default()
+        };
+        let audio = AudioSource::new(None, Some("https://x/1.mp3".into()));
+        // Turned off, so the player that doesn't exist is never started.
+        assert!(play(&audio, sink(&config).as_mut()).is_ok());
+
+        let config = Config {
+            play_audio: true,
+            ..config
+        };
+        let e = play(&audio, sink(&config).as_mut()).unwrap_err();
+        assert!(matches!(e, AudioError::Player { .. }));
+    }
+}
@@ -1,11 +1,9 @@
 use serde::{Deserialize, Serialize, de, Serializer};
 use toml::Value;
 
-use std::cmp::Ordering;
-use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::ffi::OsString;
 use std::fs::read_to_string;
-use std::io::BufRead;
 use std::path::{Path, PathBuf};
 
 use std::time::Duration;
@@ -15,35 +13,47 @@ use uuid::Uuid;
 use std::sync::Arc;
 
 use crate::categories::Category;
-use crate::media::AudioSource;
+use crate::cloze;
+use crate::error::{self, Error};
+use crate::index::CardIndex;
+use crate::git::{self, Change};
+use crate::journal;
+use crate::trash;
+use crate::media::{AudioSource, ImageSource};
+use crate::migrate::{self, SCHEMA_VERSION};
+use crate::search::{Query, SearchIndex};
+use crate::scheduler::{self, Classic, Scheduler};
 use crate::{common::current_time, Id};
 
 pub type RecallRate = f32;
 
 
 
-#[derive(Default)]
-pub struct CardCache(HashMap<Id, Arc<SavedCard>>);
+pub struct CardCache {
+    cards: HashMap<Id, Arc<SavedCard>>,
+    index: CardIndex,
+    /// Files in the cards folder that couldn't be loaded last time we read them all.
+    broken: Vec<Error>,
+    /// Every cached card by its words, kept in step with `cards`.
+    search: SearchIndex,
+}
 
 impl CardCache{
     /// Checks that the card in the cache is up to date, and fixes it if it's not. 
     /// There's three possibilities: 
     ///     1. its up to date, no need to do anything.
     ///     2. It's outdated, we simply deserialize the card from the same path, so that its updated.
-    ///     3. card isn't even found in the location, we search through all the cards to find it. Panicking if it's not found.
-    fn maybe_update(&mut self, id: &Id) {
-        let card_needs_update = match self.0.get(id) {
+    ///     3. card isn't even found in the location, we look up its new location in the index.
+    /// Returns false if the card couldn't be found anywhere.
+    fn maybe_update(&mut self, id: &Id) -> bool {
+        let card_needs_update = match self.cards.get(id) {
             Some(cached_card) => {
                 let path = cached_card.as_path();
-                if path.exists(){
-                    // Get the file's last_modified time
-                    let metadata = std::fs::metadata(path.as_path()).unwrap();
-                    let last_modified_time = system_time_as_unix_time( metadata.modified().unwrap());
-
+                // Get the file's last_modified time, if it's still there
+                match std::fs::metadata(path.as_path()).and_then(|metadata| metadata.modified()) {
                     // Check if the file has been modified since we cached it
-                    Some(last_modified_time > cached_card.last_modified)
-                } else {
-                    None
+                    Ok(modified) => Some(system_time_as_unix_time(modified) > cached_card.last_modified),
+                    Err(_) => None,
                 }
 
             },
@@ -52,27 +62,35 @@ impl CardCache{
 
         match card_needs_update {
             Some(true) => {
-                let path = self.0.get(id).unwrap().as_path();
-                let updated_card = SavedCard::from_path(path.as_path());
-                self.0.insert(*id, updated_card.into());
+                let path = self.cards.get(id).unwrap().as_path();
+                // If someone broke the file we keep what we had, better than losing the card.
+                if let Ok(updated_card) = SavedCard::from_path(path.as_path()) {
+                    self.cache_one(updated_card);
+                }
             }
             // if you find the card, and it's up to date, then no need to do anything.
             Some(false) => {},
             None => {
-                // Read the card from the disk
-                // expensive! it'll comb through all the cards linearly.
-                let card = SavedCard::from_id(id).unwrap();
-                self.0.insert(*id, card.into());
+                let Some(Ok(card)) = self
+                    .index
+                    .get_path(id)
+                    .map(|path| SavedCard::from_path(path.as_path()))
+                else {
+                    self.cards.remove(id);
+                    self.search.remove(id);
+                    return false;
+                };
+                self.cache_one(card);
             }
         };
+        true
     }
 
-    
     /// gets all the Ids (keys) sorted by recent modified
     pub fn all_ids(&self) -> Vec<Id> {
-        let mut pairs: Vec<_> = self.0.iter().collect();
+        let mut pairs: Vec<_> = self.cards.iter().collect();
         pairs.sort_by_key(|&(_, v)| {
-            if v.is_outdated() {
+            if v.is_outdated() && v.as_path().exists() {
                 get_last_modified(v.as_path())
             } else {
                 v.last_modified().to_owned()
@@ -83,25 +101,49 @@ impl CardCache{
     }
 
     pub fn exists(&self, id: &Id) -> bool {
-        self.0.get(id).is_some()
+        self.cards.contains_key(id)
     }
     
     pub fn insert(&mut self, card: SavedCard) {
         let id = card.id();
-        self.0.insert(*id, card.into());
+        self.index.insert(*id, card.as_path().as_path());
+        self.cache_one(card);
     }
     
     pub fn remove(&mut self, id: &Id) {
-        self.0.remove(id);
+        self.cards.remove(id);
+        self.search.remove(id);
+        self.index.remove(id);
+    }
+
+    /// The cards matching the query, best match first. See `search` for what a query can have.
+    pub fn search(&self, query: &Query) -> Vec<Id> {
+        self.search.search(query)
     }
 
+    /// The dependencies that are loaded, edges to missing or broken cards are left out.
     pub fn dependencies(&mut self, id: &Id) -> BTreeSet<Id>{
-        self.get_ref(id).dependency_ids().iter().map(|id| id.to_owned()).collect()
+        let Some(card) = self.try_get_ref(id) else {
+            return BTreeSet::new();
+        };
+        card.dependency_ids()
+            .iter()
+            .filter(|id| self.maybe_update(id))
+            .map(|id| id.to_owned())
+            .collect()
     }
 
 
+    /// The dependents that are loaded, edges to missing or broken cards are left out.
     pub fn dependents(&mut self, id: &Id) -> BTreeSet<Id>{
-        self.get_ref(id).dependent_ids().iter().map(|id| id.to_owned()).collect()
+        let Some(card) = self.try_get_ref(id) else {
+            return BTreeSet::new();
+        };
+        card.dependent_ids()
+            .iter()
+            .filter(|id| self.maybe_update(id))
+            .map(|id| id.to_owned())
+            .collect()
     }
 
 
@@ -151,33 +193,118 @@ pub fn recursive_dependents(&mut self, id: &Id) -> BTreeSet<Id> {
         (*self.get_ref(id)).clone()
     }
 
+    /// For ids you know are loaded, like those you just got from the cache. Use `try_get_ref`
+    /// for ids from card files, they can point at broken or missing cards.
     pub fn get_ref(&mut self, id: &Id) -> Arc<SavedCard> {
-        self.maybe_update(id);
-        self.0.get(id).unwrap().to_owned()
-}
+        self.try_get_ref(id)
+            .unwrap_or_else(|| panic!("card {id} isn't loaded"))
+    }
+
+    /// Like get_ref, but returns None if the card doesn't exist or didn't load.
+    pub fn try_get_ref(&mut self, id: &Id) -> Option<Arc<SavedCard>> {
+        if !self.maybe_update(id) {
+            return None;
+        }
+        self.cards.get(id).cloned()
+    }
+
+    /// Like get_owned, but returns None if the card doesn't exist anymore.
+    pub fn try_get_owned(&mut self, id: &Id) -> Option<SavedCard> {
+        if !self.maybe_update(id) {
+            return None;
+        }
+        self.cards.get(id).map(|card| (**card).clone())
+    }
 
-   pub fn new() -> Self {
-       let mut cache = Self::default();
-       cache.cache_all();
-       cache
-   } 
+    pub fn new() -> Self {
+        let mut cache = Self {
+            cards: HashMap::new(),
+            index: CardIndex::load(),
+            broken: vec![],
+            search: SearchIndex::default(),
+        };
+        cache.cache_all();
+        cache
+    }
 
     pub fn refresh(&mut self) {
+        // Written first, or dropping the old one would write it over the fresh index.
+        self.index.save();
         *self = Self::new();
     }
 
     fn cache_all(&mut self) {
-        let all_cards = SavedCard::load_all_cards();
-        for card in all_cards{
+        let (all_cards, broken) = SavedCard::load_cards_in(&Category::root());
+        self.broken = broken;
+        // We just read every card anyway, so might as well give the index a fresh start.
+        self.index
+            .replace_all(all_cards.iter().map(|card| (*card.id(), card.as_path())));
+        for card in all_cards {
             self.cache_one(card);
         }
     }
     
+    /// The files that didn't load as cards, with why.
+    pub fn broken(&self) -> &[Error] {
+        &self.broken
+    }
+
     pub fn cache_one(&mut self, card: SavedCard) {
-        self.0.insert(card.card.meta.id, card.into());
+        let card: Arc<SavedCard> = card.into();
+        self.search.insert(card.clone());
+        self.cards.insert(card.card.meta.id, card);
+    }
+
+    /// Removes dependencies and dependents that point to cards which no longer exist.
+    pub fn health_check(&mut self) -> Vec<DanglingEdge> {
+        self.refresh();
+        let mut removed = vec![];
+
+        for mut card in SavedCard::load_all_cards() {
+            let dependencies = card.dependency_ids().to_owned();
+            let dependents = card.dependent_ids().to_owned();
+
+            for d in dependencies {
+                if !self.exists(&d) {
+                    card.remove_dependency(&d, self);
+                    removed.push(DanglingEdge {
+                        card: *card.id(),
+                        missing: d,
+                        is_dependency: true,
+                    });
+                }
+            }
+
+            for d in dependents {
+                if !self.exists(&d) {
+                    card.remove_dependent(&d, self);
+                    removed.push(DanglingEdge {
+                        card: *card.id(),
+                        missing: d,
+                        is_dependency: false,
+                    });
+                }
+            }
+        }
+        self.refresh();
+        if !removed.is_empty() {
+            git::note(Change::Other(format!(
+                "health: removed {} missing references",
+                removed.len()
+            )));
+        }
+        removed
     }
 }
 
+/// A dependency or dependent pointing at a card that doesn't exist.
+#[derive(Debug, Serialize)]
+pub struct DanglingEdge {
+    pub card: Id,
+    pub missing: Id,
+    pub is_dependency: bool,
+}
+
 
 
 #[derive(Ord, PartialOrd, Eq, PartialEq, Hash, Clone, Debug)]
@@ -187,10 +314,16 @@ pub struct CardLocation {
 }
 
 impl CardLocation {
-    pub fn new(path: &Path) -> Self {
-        let file_name = path.file_name().unwrap().to_owned();
-        let category = Category::from_card_path(path);
-        Self {file_name, category}
+    pub fn new(path: &Path) -> error::Result<Self> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| Error::NotInCategory(path.to_path_buf()))?
+            .to_owned();
+        let category = Category::from_card_path(path)?;
+        Ok(Self {
+            file_name,
+            category,
+        })
     }
 
     fn as_path(&self) -> PathBuf {
@@ -247,6 +380,42 @@ pub struct CardInfo {
     pub suspended: bool,
     pub finished: bool,
     pub change: f32,
+    pub scheduler: &'static str,
+}
+
+/// Summary of the whole collection.
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    pub suspended: usize,
+    pub finished: usize,
+    pub pending: usize,
+    pub reviews: usize,
+    pub strength: i32,
+    pub resolved: usize,
+    pub total: usize,
+}
+
+impl Stats {
+    pub fn new(cache: &mut CardCache) -> Self {
+        let mut stats = Self::default();
+
+        for card in SavedCard::load_all_cards() {
+            stats.total += 1;
+            stats.pending += card.stability().is_none() as usize;
+            stats.reviews += card
+                .card
+                .review_histories()
+                .iter()
+                .map(|reviews| reviews.0.len())
+                .sum::<usize>();
+            stats.finished += card.is_finished() as usize;
+            stats.resolved += card.is_resolved(cache) as usize;
+            stats.strength +=
+                (card.strength().unwrap_or_default().as_secs_f32() / 86400.).round() as i32;
+            stats.suspended += card.is_suspended() as usize;
+        }
+        stats
+    }
 }
 
 impl CardInfo{
@@ -255,23 +424,45 @@ impl CardInfo{
             recall_rate: card.recall_rate()?,
             strength: card.strength()?.as_secs_f32() / 86400.,
             stability: card.stability()?.as_secs_f32() / 86400.,
-            change: card.card.history.expected_gain()?,
+            change: card.expected_gain()?,
             resolved: card.is_resolved(cache),
             suspended: card.is_suspended(),
             finished: card.is_finished(),
+            scheduler: card.scheduler().name(),
 
         }.into()
     }
 }
 
+impl std::fmt::Display for CardInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "recall rate: {:.0}%", self.recall_rate * 100.)?;
+        writeln!(f, "strength: {:.1} days", self.strength)?;
+        writeln!(f, "stability: {:.1} days", self.stability)?;
+        writeln!(f, "expected gain: {:.3}", self.change)?;
+        writeln!(f, "resolved: {}", self.resolved)?;
+        writeln!(f, "suspended: {}", self.suspended)?;
+        writeln!(f, "finished: {}", self.finished)?;
+        write!(f, "scheduler: {}", self.scheduler)
+    }
+}
+
 
 
 impl SavedCard {
     
     
+    /// For the commit message, see `git::note`.
+    fn note_edit(&self) {
+        git::note(Change::Edit {
+            front: self.card.front.text.clone(),
+        });
+    }
+
     pub fn set_priority(&mut self, priority: Priority) {
         self.card.meta.priority = priority;
         self.persist();
+        self.note_edit();
     } 
     
     #[allow(dead_code)]
@@ -280,7 +471,12 @@ impl SavedCard {
     }
     
     pub fn expected_gain(&self) -> Option<f32> {
-        self.card.history.expected_gain()
+        self.next_reviews().expected_gain(self.scheduler())
+    }
+
+    /// Like `expected_gain`, for one of the card's review items.
+    pub fn expected_gain_of(&self, item: ReviewItem) -> Option<f32> {
+        self.card.reviews_of(item).expected_gain(self.scheduler())
     }
     
     pub fn get_info(&self, cache: &mut CardCache) -> Option<CardInfo>{
@@ -288,8 +484,66 @@ impl SavedCard {
     }
     
     
-    pub fn reviews(& self) -> &Vec<Review> {
-        &self.card.history.0
+    /// Every review history of the card, one per deletion for cloze cards.
+    pub fn review_histories(&self) -> Vec<&Vec<Review>> {
+        self.card
+            .review_histories()
+            .into_iter()
+            .map(|reviews| &reviews.0)
+            .collect()
+    }
+
+    /// The item that was never reviewed, or else the one that's due first, which the card's
+    /// recall rate, stability and so on are about. Always the front for regular cards. Reviews
+    /// go through every item that's up, see `review_filter`.
+    pub fn next_item(&self) -> ReviewItem {
+        self.card
+            .review_items()
+            .into_iter()
+            .min_by_key(|item| self.card.reviews_of(*item).due(self.scheduler(), 0.9))
+            .unwrap_or(ReviewItem::Front)
+    }
+
+    /// The reviews that decide when the card is due, those of `next_item`.
+    fn next_reviews(&self) -> &Reviews {
+        self.card.reviews_of(self.next_item())
+    }
+
+    /// What's asked when reviewing, the back for the reverse and with the deletion hidden for
+    /// cloze cards.
+    pub fn review_front(&self, item: ReviewItem) -> String {
+        match item {
+            ReviewItem::Front => self.card.front.text.clone(),
+            ReviewItem::Reverse => self.card.back.text.clone(),
+            ReviewItem::Cloze(number) => cloze::mask(&self.card.front.text, number),
+        }
+    }
+
+    /// The answer, for cloze cards that's the whole text followed by the back as extra info.
+    pub fn review_back(&self, item: ReviewItem) -> String {
+        match item {
+            ReviewItem::Front => self.card.back.text.clone(),
+            ReviewItem::Reverse => self.card.front.text.clone(),
+            ReviewItem::Cloze(number) if self.card.back.text.is_empty() => cloze::reveal(&self.card.front.text, number),
+            ReviewItem::Cloze(number) => format!("{}\n\n{}", cloze::reveal(&self.card.front.text, number), self.card.back.text),
+        }
+    }
+
+    /// The sides `review_front` and `review_back` come from, for their images and audio.
+    pub fn review_sides(&self, item: ReviewItem) -> (&Side, &Side) {
+        match item {
+            ReviewItem::Reverse => (&self.card.back, &self.card.front),
+            _ => (&self.card.front, &self.card.back),
+        }
+    }
+
+    /// What should be typed when answers are typed in, just the deletions for cloze cards.
+    pub fn expected_answer(&self, item: ReviewItem) -> String {
+        match item {
+            ReviewItem::Front => self.card.back.text.clone(),
+            ReviewItem::Reverse => self.card.front.text.clone(),
+            ReviewItem::Cloze(number) => cloze::answers(&self.card.front.text, number),
+        }
     }
     
 
@@ -322,9 +576,8 @@ impl SavedCard {
         &self.card.front.text
     }
     
-    #[allow(dead_code)]
     pub fn is_pending(&self) -> bool {
-        self.card.history.is_empty()
+        self.next_reviews().is_empty()
     }
     
     pub fn is_suspended(&self) -> bool {
@@ -335,18 +588,23 @@ impl SavedCard {
         self.card.meta.finished
     }
     
+    /// The scheduler configured for the category of the card.
+    pub fn scheduler(&self) -> &'static dyn Scheduler {
+        scheduler::for_category(self.category())
+    }
+
     pub fn recall_rate(&self) -> Option<f32> {
-        self.card.history.recall_rate()
+        self.next_reviews().recall_rate(self.scheduler())
     }
     
     pub fn stability(&self) -> Option<Duration> {
-        self.card.history.stability()
+        self.next_reviews().stability(self.scheduler())
     }
 
     pub fn strength(&self) -> Option<Duration> {
-        self.card.history.strength()
+        self.next_reviews().strength(self.scheduler())
     }
-    
+
     pub fn time_since_last_review(&self) -> Option<Duration> {
         self.card.time_passed_since_last_review()
     }
@@ -374,17 +632,50 @@ impl SavedCard {
     pub fn set_suspended(&mut self, suspended: IsSuspended) {
         self.card.meta.suspended = suspended;
         self.persist();
+        self.note_edit();
     }
     
     pub fn set_finished(&mut self, finished: bool) {
         self.card.meta.finished = finished;
         self.persist();
+        self.note_edit();
+    }
+
+    /// The reverse history is kept when it's turned off, in case it's turned on again.
+    pub fn set_reversible(&mut self, reversible: bool) {
+        self.card.reversible = reversible;
+        self.persist();
+        self.note_edit();
+    }
+
+    pub fn is_reversible(&self) -> bool {
+        self.card.reversible
     }
     
 
+    /// Goes back to an older version of the card. The edges stay as they are now, since the
+    /// cards on the other side would have to change too.
+    pub fn revert_to(&mut self, old: Card) {
+        let meta = &self.card.meta;
+        self.card = Card {
+            meta: Meta {
+                id: meta.id,
+                dependencies: meta.dependencies.clone(),
+                dependents: meta.dependents.clone(),
+                ..old.meta
+            },
+            // It may be from before a migration, but it's written the current way.
+            schema_version: SCHEMA_VERSION,
+            ..old
+        };
+        self.persist();
+        self.note_edit();
+    }
+
     pub fn insert_tag(&mut self, tag: String)  {
         self.card.meta.tags.insert(tag);
         self.persist();
+        self.note_edit();
     }
     
 
@@ -401,7 +692,7 @@ fn has_cycle_helper(&self, cache: &mut CardCache, id: &Id, is_dependent: bool, v
         return Some(path.clone());
     }
 
-    path.push(id.clone());
+    path.push(*id);
 
     let dependencies = if is_dependent {
         cache.dependencies(id)
@@ -417,7 +708,7 @@ fn has_cycle_helper(&self, cache: &mut CardCache, id: &Id, is_dependent: bool, v
         }
     }
 
-    visited.insert(id.clone());
+    visited.insert(*id);
     path.pop();
 
     None
@@ -426,6 +717,7 @@ fn has_cycle_helper(&self, cache: &mut CardCache, id: &Id, is_dependent: bool, v
 pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<String>{
     self.card.meta.dependencies.insert(*id);
     self.persist();
+    self.note_edit();
 
     let mut other_card = cache.get_owned(id);
     other_card.card.meta.dependents.insert(self.card.meta.id);
@@ -445,8 +737,8 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
         let mut s = String::new();
         
 
-        for i in 0..veclen {
-            s.push_str(&format!("'{}'", &textvec[i]));
+        for (i, text) in textvec.iter().enumerate() {
+            s.push_str(&format!("'{}'", text));
 
             if i == 0 {
                 s.push_str(" depends on ")
@@ -474,6 +766,7 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
     pub fn set_dependent(&mut self, id: &Id, cache: &mut CardCache)  -> Option<String>{
         self.card.meta.dependents.insert(*id);
         self.persist();
+        self.note_edit();
         
         let mut other_card = cache.get_owned(id);
         other_card.card.meta.dependencies.insert(self.card.meta.id);
@@ -494,8 +787,8 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
         let mut s = String::new();
         
 
-        for i in 0..veclen {
-            s.push_str(&format!("'{}'", &textvec[i]));
+        for (i, text) in textvec.iter().enumerate() {
+            s.push_str(&format!("'{}'", text));
 
             if i == 0 {
                 s.push_str(" depends on ")
@@ -524,10 +817,9 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
     /// a = span means foo
     /// b = change span desc by..
     /// inserted: c = what is a span desc?
-    
     pub fn _insert_dependency_raw(dependent_id: &Id, dependency_id: &Id, insertion_id: &Id, cache: &mut CardCache) {
-        let mut dependent = Self::from_id(dependent_id).unwrap();
-        let _insertion = Self::from_id(insertion_id).unwrap();
+        let mut dependent = cache.get_owned(dependent_id);
+        let _insertion = cache.get_owned(insertion_id);
         
         dependent.remove_dependency(dependency_id, cache);
         //dependent.set_dependency(insertion_id);
@@ -536,24 +828,35 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
     }
 
     
-    pub fn remove_dependency(&mut self, id: &Id, _cache: &mut CardCache) {
+    /// Adds the edge on this side only, for when the other side is already taken care of.
+    pub fn insert_dependency_raw(&mut self, id: Id) {
+        self.card.meta.dependencies.insert(id);
+        self.persist();
+    }
+
+    pub fn insert_dependent_raw(&mut self, id: Id) {
+        self.card.meta.dependents.insert(id);
+        self.persist();
+    }
+
+    pub fn remove_dependency(&mut self, id: &Id, cache: &mut CardCache) {
         self.card.meta.dependencies.remove(id);
         self.persist();
         
-        if let Some(mut other_card) = Self::from_id(id) {
+        if let Some(mut other_card) = cache.try_get_owned(id) {
             other_card.card.meta.dependents.remove(self.id());
             other_card.persist();
         }
     }
     
     
-    pub fn remove_dependent(&mut self, id: &Id, _cache: &mut CardCache) {
-        self.card.meta.dependencies.remove(id);
+    pub fn remove_dependent(&mut self, id: &Id, cache: &mut CardCache) {
+        self.card.meta.dependents.remove(id);
         self.persist();
         
 
-        if let Some(mut other_card) = Self::from_id(id) {
-            other_card.card.meta.dependents.remove(self.id());
+        if let Some(mut other_card) = cache.try_get_owned(id) {
+            other_card.card.meta.dependencies.remove(self.id());
             other_card.persist();
         }
         
@@ -567,16 +870,31 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
     }
     
 
-    pub fn pending_filter(card: &Id, cache: &mut CardCache) -> bool {
-        let card = cache.get_ref(card);
-        card.card.history.is_empty()
+    /// The items of the card that were never reviewed, if the card is ready for them.
+    pub fn pending_filter(card: &Id, cache: &mut CardCache) -> Vec<ReviewItem> {
+        let Some(card) = cache.try_get_ref(card) else {
+            return vec![];
+        };
+        let items: Vec<_> = card
+            .card
+            .review_items()
+            .into_iter()
+            .filter(|item| card.card.reviews_of(*item).is_empty())
+            .collect();
+        let ready = !items.is_empty()
             && !card.is_suspended()
             && card.is_finished()
-            && card.is_confidently_resolved(cache)
+            && card.is_confidently_resolved(cache);
+        if !ready {
+            return vec![];
+        }
+        items
     }
 
     pub fn unfinished_filter(card: &Id, cache: &mut CardCache) -> bool {
-        let card = cache.get_ref(card);
+        let Some(card) = cache.try_get_ref(card) else {
+            return false;
+        };
         !card.is_finished() && !card.is_suspended() && card.is_resolved(cache)
     }
 
@@ -584,40 +902,60 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
 
 
 
-    pub fn review_filter(card: &Id, cache: &mut CardCache) -> bool {
-        let card = cache.get_ref(card);
-        match (card.stability(), card.card.history.time_since_last_review()) {
-            (Some(stability), Some(last_review_time)) => {
-                card.is_finished()
-                    && !card.is_suspended()
-                    && last_review_time > Duration::from_secs(60) // Lets not review if its less than a minute since last time
-                    && stability < last_review_time
-                    && card.is_confidently_resolved(cache)
-            }
-            (_, _) => false,
+    /// The items of the card that are due, each one is scheduled on its own history.
+    pub fn review_filter(card: &Id, cache: &mut CardCache) -> Vec<ReviewItem> {
+        let Some(card) = cache.try_get_ref(card) else {
+            return vec![];
+        };
+        let now = current_time();
+        let items: Vec<_> = card
+            .card
+            .review_items()
+            .into_iter()
+            .filter(|item| {
+                let reviews = card.card.reviews_of(*item);
+                match (
+                    reviews.due(card.scheduler(), 0.9),
+                    reviews.time_since_last_review(),
+                ) {
+                    // Lets not review if its less than a minute since last time
+                    (Some(due), Some(last_review_time)) => {
+                        last_review_time > Duration::from_secs(60) && due < now
+                    }
+                    (_, _) => false,
+                }
+            })
+            .collect();
+        let ready = !items.is_empty()
+            && card.is_finished()
+            && !card.is_suspended()
+            && card.is_confidently_resolved(cache);
+        if !ready {
+            return vec![];
         }
+        items
     }
 
     /// Checks if corresponding file has been modified after this type got deserialized from the file.
+    /// A file that's gone counts as modified.
     pub fn is_outdated(&self) -> bool {
-        let file_last_modified = {
-            let path = self.as_path();
-            let system_time = std::fs::metadata(path).unwrap().modified().unwrap();
-            system_time_as_unix_time(system_time)
-        };
-
-        let in_memory_last_modified = self.last_modified;
+        let file_last_modified =
+            match std::fs::metadata(self.as_path()).and_then(|metadata| metadata.modified()) {
+                Ok(system_time) => system_time_as_unix_time(system_time),
+                Err(_) => return true,
+            };
 
-        match in_memory_last_modified.cmp(&file_last_modified) {
-            Ordering::Less => true,
-            Ordering::Equal => false,
-            Ordering::Greater => panic!("Card in-memory shouldn't have a last_modified more recent than its corresponding file"),
-        }
+        // The file can be older than what we have in memory, when a sync puts back an older
+        // version with its original mtime. That's still a different file.
+        self.last_modified != file_last_modified
     }
 
 
     pub fn is_resolved(&self, cache: &mut CardCache) -> bool {
-        cache.recursive_dependencies(self.id()).iter().all(|id| cache.get_ref(id).is_finished())
+        cache
+            .recursive_dependencies(self.id())
+            .iter()
+            .all(|id| cache.try_get_ref(id).is_some_and(|card| card.is_finished()))
     }
 
     /// Checks that its dependencies are not only marked finished, but they're also strong memories.
@@ -632,9 +970,13 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
      //   dbg!(dbgshit);
 
         let x = cache.recursive_dependencies(self.id()).iter().all(|id| {
-            let card = cache.get_ref(id);
-            let (Some(stability), Some(recall)) = (card.stability(), card.card.history.recall_rate()) else {return false};
-            
+            let Some(card) = cache.try_get_ref(id) else {
+                return false;
+            };
+            let (Some(stability), Some(recall)) = (card.stability(), card.recall_rate()) else {
+                return false;
+            };
+
             card.card.meta.finished && stability > min_stability && recall > min_recall
         });
     //    dbg!("$$", &x, "$$");
@@ -648,14 +990,21 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
             return self;
         }
         assert!(self.as_path().exists());
+        let content = read_to_string(self.as_path()).ok();
         std::fs::remove_file(self.as_path()).unwrap();
+        journal::record(*self.id(), &self.as_path(), content, None);
         assert!(!self.as_path().exists());
-        self.into_card().save_new_card(destination, cache)
+        git::note(Change::Move {
+            front: self.card.front.text.clone(),
+            from: self.location.category.clone(),
+            to: destination.clone(),
+        });
+        self.into_card().save_to(destination, cache)
     }
 
 
     pub fn get_review_type(&self) -> ReviewType {
-        match (self.card.history.is_empty(), self.is_finished()) {
+        match (self.is_pending(), self.is_finished()) {
             (_, false) => ReviewType::Unfinished,
             (false, true) => ReviewType::Normal,
             (true, true) => ReviewType::Pending,
@@ -665,9 +1014,15 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
     pub fn delete(self, cache: &mut CardCache) {
 
         let path = self.as_path();
-        std::fs::remove_file(path).unwrap();
+        let content = read_to_string(&path).ok();
+        trash::move_to_trash(&self);
+        std::fs::remove_file(&path).unwrap();
+        git::note(Change::Delete {
+            front: self.card.front.text.clone(),
+        });
 
         let self_id = self.card.meta.id;
+        journal::record(self_id, &path, content, None);
         
         for dependency in self.card.meta.dependencies {
             let mut dependency = cache.get_owned(&dependency);
@@ -687,32 +1042,21 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
     
     
     pub fn get_cards_from_category_recursively(category: &Category) -> HashSet<SavedCard> {
+        Self::load_cards_in(category).0
+    }
+
+    /// Every card under the category, and an error for each file that isn't a valid card.
+    pub fn load_cards_in(category: &Category) -> (HashSet<SavedCard>, Vec<Error>) {
         let mut cards = HashSet::new();
+        let mut broken = vec![];
         let cats = category.get_following_categories();
         for cat in cats {
-            cards.extend(cat.get_containing_cards());
+            cards.extend(cat.get_containing_cards(&mut broken));
         }
-        cards
+        broken.sort_by(|a, b| a.path().cmp(b.path()));
+        (cards, broken)
     }
         
-    pub fn search_in_cards<'a>(input: &'a str, cards: &'a HashSet<SavedCard>, excluded_cards: &'a HashSet<Id>) -> Vec<&'a SavedCard> {
-        cards
-        .iter()
-        .filter(|card| {
-            (card.card.front.text.to_ascii_lowercase().contains(&input.to_ascii_lowercase())
-                || card.card.back.text.to_ascii_lowercase().contains(&input.to_ascii_lowercase()))
-                && !excluded_cards.contains(card.id())
-        })
-        .collect()
-}
-
-    // expensive function!
-    pub fn from_id(id: &Id) -> Option<Self> {
-        Self::load_all_cards()
-            .into_iter()
-            .find(|card| &card.card.meta.id == id)
-    }
-    
     pub fn load_all_cards() -> HashSet<SavedCard> {
         Self::get_cards_from_category_recursively(&Category::root())
     }
@@ -721,25 +1065,38 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
 
 
 
-    pub fn edit_with_vim(&self) -> Self {
+    pub fn edit_with_vim(&self) -> error::Result<Self> {
         let path = self.as_path();
+        let before = read_to_string(&path).ok();
         open_file_with_vim(path.as_path()).unwrap();
+        let after = read_to_string(&path).ok();
+        if after != before {
+            self.note_edit();
+        }
+        journal::record(*self.id(), &path, before, after);
         Self::from_path(path.as_path())
     }
 
-    pub fn from_path(path: &Path) -> Self {
-        let content = read_to_string(path).expect("Could not read the TOML file");
-        let card: Card = toml::from_str(&content).unwrap();
-        let location = CardLocation::new(path);
+    pub fn from_path(path: &Path) -> error::Result<Self> {
+        let mut content = read_to_string(path).map_err(|e| Error::io(path, e))?;
+        // Only upgraded in memory, loading shouldn't write. The file catches up the next time
+        // the card is saved, or with the migrate command.
+        if let Some(migrated) = migrate::migrate(path, &content)? {
+            content = migrated.content;
+        }
+        let card: Card = toml::from_str(&content).map_err(|e| Error::parse(path, &content, e))?;
+        let location = CardLocation::new(path)?;
 
         let last_modified = {
-            let system_time = std::fs::metadata(path).unwrap().modified().unwrap();
+            let system_time = std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .map_err(|e| Error::io(path, e))?;
             system_time_as_unix_time(system_time)
         };
 
-        Self {
+        Ok(Self {
             card, location, last_modified
-        }
+        })
     }
 
     pub fn into_card(self) -> Card {
@@ -757,7 +1114,7 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
             // When you persist, the last_modified in the card should match the ones from the file.
             // This shouldn't be possible, as this function mutates itself to get a fresh copy, so 
             // i'll panic here to alert me of the logic bug.
-            let x = format!("{:?}", self);
+            let _x = format!("{:?}", self);
            // panic!("{}", x);
         }
 
@@ -769,15 +1126,29 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
 
         let toml = toml::to_string(self.card_as_ref()).unwrap();
 
-        std::fs::write(&path, toml).unwrap();
-        *self = SavedCard::from_path(path.as_path())
+        let before = read_to_string(&path).ok();
+        std::fs::write(&path, &toml).unwrap();
+        journal::record(*self.id(), &path, before, Some(toml));
+        // We just wrote it, so this only fails if the file got yanked away in between.
+        if let Ok(saved) = SavedCard::from_path(path.as_path()) {
+            *self = saved;
+        }
     }
 
-
-    pub fn new_review(&mut self, grade: Grade) {
-        let review = Review::new(grade);
-        self.card.history.add_review(review);
+    pub fn new_review(
+        &mut self,
+        item: ReviewItem,
+        grade: Grade,
+        time_spent: Duration,
+        time_to_grade: Duration,
+    ) {
+        let review = Review::new(grade, time_spent, time_to_grade);
+        self.card.reviews_mut(item).add_review(review);
         self.persist();
+        git::note(Change::Review {
+            front: self.card.front.text.clone(),
+            category: self.location.category.clone(),
+        });
     }
 }
 
@@ -785,40 +1156,70 @@ pub fn set_dependency(&mut self, id: &Id, cache: &mut CardCache) -> Option<Strin
 
 
 
-#[derive(Ord, PartialOrd, Eq, Hash, PartialEq, Deserialize, Serialize, Debug, Default, Clone)]
+/// Something that's reviewed with its own history, a card can have several.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ReviewItem {
+    /// Front to back, the only one for regular cards.
+    Front,
+    /// Back to front, for reversible cards.
+    Reverse,
+    /// A deletion of a cloze card.
+    Cloze(u32),
+}
+
+#[derive(Ord, PartialOrd, Eq, Hash, PartialEq, Deserialize, Serialize, Debug, Clone)]
 pub struct Card {
+    /// Missing in files from before there were versions, which counts as 0.
+    #[serde(default)]
+    pub schema_version: u32,
+    /// Also reviewed from back to front, with its own history.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reversible: bool,
     pub front: Side,
     pub back: Side,
     pub meta: Meta,
     #[serde(default, skip_serializing_if = "Reviews::is_empty")]
     pub history: Reviews,
+    /// The back to front history of a reversible card.
+    #[serde(default, skip_serializing_if = "Reviews::is_empty")]
+    pub reverse_history: Reviews,
+    /// The histories of the deletions of a cloze card, by `cloze::key`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub clozes: BTreeMap<String, Reviews>,
 }
 
 
+impl Default for Card {
+    fn default() -> Self {
+        Card {
+            schema_version: SCHEMA_VERSION,
+            front: Side::default(),
+            back: Side::default(),
+            reversible: false,
+            meta: Meta::default(),
+            history: Reviews::default(),
+            reverse_history: Reviews::default(),
+            clozes: BTreeMap::new(),
+        }
+    }
+}
+
 // public
 impl Card {
     pub fn new(front: Side, back: Side, meta: Meta) -> Self {
         Card {
+            schema_version: SCHEMA_VERSION,
             front,
             back,
+            reversible: false,
             meta,
             history: Reviews::default(),
+            reverse_history: Reviews::default(),
+            clozes: BTreeMap::new(),
         }
     }
     
     
-    pub fn import_cards(filename: &Path) -> Option<Vec<Self>> {
-        let mut lines = std::io::BufReader::new(std::fs::File::open(filename).ok()?).lines();
-        let mut cards = vec![];
-
-        while let Some(Ok(question)) = lines.next() {
-            if let Some(Ok(answer)) = lines.next() {
-                cards.push(Self::new_simple(question, answer));
-            }
-        }
-        cards.into()
-    }
-
     pub fn new_simple(front: String, back: String) -> Self {
         Card {
             front: Side {
@@ -833,8 +1234,76 @@ impl Card {
         }
     }
     
+    /// Combines two versions of the same card, like when it was changed on two machines.
+    /// Reviews and the sets in meta are unioned, for everything else the side that changed
+    /// compared to `base` wins, or ours if we can't tell.
+    pub fn merge(&self, theirs: &Card, base: Option<&Card>) -> Card {
+        fn pick<T: PartialEq + Clone>(ours: &T, theirs: &T, base: Option<&T>) -> T {
+            if base == Some(ours) {
+                theirs.clone()
+            } else {
+                ours.clone()
+            }
+        }
+
+        let meta = Meta {
+            id: self.meta.id,
+            dependencies: self
+                .meta
+                .dependencies
+                .union(&theirs.meta.dependencies)
+                .cloned()
+                .collect(),
+            dependents: self
+                .meta
+                .dependents
+                .union(&theirs.meta.dependents)
+                .cloned()
+                .collect(),
+            suspended: pick(
+                &self.meta.suspended,
+                &theirs.meta.suspended,
+                base.map(|b| &b.meta.suspended),
+            ),
+            finished: pick(
+                &self.meta.finished,
+                &theirs.meta.finished,
+                base.map(|b| &b.meta.finished),
+            ),
+            priority: pick(
+                &self.meta.priority,
+                &theirs.meta.priority,
+                base.map(|b| &b.meta.priority),
+            ),
+            tags: self.meta.tags.union(&theirs.meta.tags).cloned().collect(),
+        };
+
+        let mut clozes = self.clozes.clone();
+        for (key, reviews) in &theirs.clozes {
+            let merged = clozes
+                .get(key)
+                .map_or_else(|| reviews.clone(), |ours| ours.merge(reviews));
+            clozes.insert(key.clone(), merged);
+        }
+
+        Card {
+            schema_version: SCHEMA_VERSION,
+            front: pick(&self.front, &theirs.front, base.map(|b| &b.front)),
+            back: pick(&self.back, &theirs.back, base.map(|b| &b.back)),
+            meta,
+            reversible: pick(
+                &self.reversible,
+                &theirs.reversible,
+                base.map(|b| &b.reversible),
+            ),
+            history: self.history.merge(&theirs.history),
+            reverse_history: self.reverse_history.merge(&theirs.reverse_history),
+            clozes,
+        }
+    }
+
     pub fn _review_priority(&self) -> Option<f32> {
-        let recall_rate = self.history.recall_rate()?;
+        let recall_rate = self.history.recall_rate(&Classic)?;
         let priority = &self.meta.priority;
         Some((recall_rate - 1.0) * priority.as_float())
     }
@@ -842,6 +1311,15 @@ impl Card {
 
 
     pub fn save_new_card(self, category: &Category, cache: &mut CardCache) -> SavedCard {
+        git::note(Change::Add {
+            front: self.front.text.clone(),
+            category: category.clone(),
+        });
+        self.save_to(category, cache)
+    }
+
+    /// Like `save_new_card`, but it doesn't count as adding a card. For moves and restores.
+    pub fn save_to(self, category: &Category, cache: &mut CardCache) -> SavedCard {
         let toml = toml::to_string(&self).unwrap();
         std::fs::create_dir_all(category.as_path()).unwrap();
         let max_char_len = 40;
@@ -859,20 +1337,70 @@ impl Card {
             path = category.as_path().join(file_name).with_extension("toml");
         }
 
-        std::fs::write(&path, toml).unwrap();
+        std::fs::write(&path, &toml).unwrap();
+        journal::record(self.meta.id, &path, None, Some(toml));
 
-        let full_card = SavedCard::from_path(path.as_path());
+        let location = CardLocation {
+            file_name: path.file_name().unwrap().to_owned(),
+            category: category.clone(),
+        };
+        // If we can't tell when it was written it just counts as outdated and gets reloaded.
+        let last_modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .map(system_time_as_unix_time)
+            .unwrap_or_default();
+        let full_card = SavedCard::new(self, location, last_modified);
         cache.insert(full_card.clone());
         full_card
     }
 
 
     fn time_passed_since_last_review(&self) -> Option<Duration> {
-        if current_time() < self.history.0.last()?.timestamp{
-            return Duration::default().into();
+        let last = self
+            .review_histories()
+            .iter()
+            .filter_map(|reviews| reviews.0.last())
+            .map(|review| review.timestamp)
+            .max()?;
+        Some(current_time().saturating_sub(last))
+    }
+
+    /// What gets reviewed: every deletion of a cloze card, or the front and, if it's
+    /// reversible, the back.
+    pub fn review_items(&self) -> Vec<ReviewItem> {
+        let numbers = cloze::numbers(&self.front.text);
+        if !numbers.is_empty() {
+            return numbers.into_iter().map(ReviewItem::Cloze).collect();
         }
+        match self.reversible {
+            true => vec![ReviewItem::Front, ReviewItem::Reverse],
+            false => vec![ReviewItem::Front],
+        }
+    }
+
+    pub fn reviews_of(&self, item: ReviewItem) -> &Reviews {
+        static NOT_YET: Reviews = Reviews(Vec::new());
+        match item {
+            ReviewItem::Front => &self.history,
+            ReviewItem::Reverse => &self.reverse_history,
+            ReviewItem::Cloze(number) => self.clozes.get(&cloze::key(number)).unwrap_or(&NOT_YET),
+        }
+    }
+
+    fn reviews_mut(&mut self, item: ReviewItem) -> &mut Reviews {
+        match item {
+            ReviewItem::Front => &mut self.history,
+            ReviewItem::Reverse => &mut self.reverse_history,
+            ReviewItem::Cloze(number) => self.clozes.entry(cloze::key(number)).or_default(),
+        }
+    }
 
-        Some(current_time() - self.history.0.last()?.timestamp)
+    /// Every history that gets reviewed, one per review item.
+    pub fn review_histories(&self) -> Vec<&Reviews> {
+        self.review_items()
+            .into_iter()
+            .map(|item| self.reviews_of(item))
+            .collect()
     }
 }
 
@@ -891,16 +1419,10 @@ pub enum Grade {
 }
 
 impl Grade {
-    pub fn get_factor(&self) -> f32 {
-        match self {
-            Grade::None => 0.1,
-            Grade::Late => 0.25,
-            Grade::Some => 2.,
-            Grade::Perfect => 3.,
-        }
-        //factor * Self::randomize_factor()
+    /// Whether you actually remembered the answer.
+    pub fn is_correct(&self) -> bool {
+        matches!(self, Grade::Some | Grade::Perfect)
     }
-    
 }
 
 impl std::str::FromStr for Grade {
@@ -912,6 +1434,10 @@ impl std::str::FromStr for Grade {
             "2" => Ok(Self::Late),
             "3" => Ok(Self::Some),
             "4" => Ok(Self::Perfect),
+            "none" => Ok(Self::None),
+            "late" => Ok(Self::Late),
+            "some" => Ok(Self::Some),
+            "perfect" => Ok(Self::Perfect),
             _ => Err(()),
         }
     }
@@ -927,9 +1453,9 @@ use crate::common::{
 pub struct Reviews(Vec<Review>);
 
 impl Reviews{
-    pub fn strength(&self) -> Option<Duration> {
+    pub fn strength(&self, scheduler: &dyn Scheduler) -> Option<Duration> {
         let days_passed = self.time_since_last_review()?;
-        let stability = self.stability()?;
+        let stability = self.stability(scheduler)?;
         let strength = calculate_memory_strength(0.9, days_passed, stability);
         //dbg!(days_passed.as_secs_f32() / 86400., stability.as_secs_f32() / 86400., strength);
           Duration::from_secs_f32(strength * 86400.).into()
@@ -937,26 +1463,26 @@ impl Reviews{
     }
     
     
-    pub fn next_strength(&self, grade: Grade) -> Duration {
+    pub fn next_strength(&self, grade: Grade, scheduler: &dyn Scheduler) -> Duration {
         let mut myself = self.clone();
-        let new_review = Review::new(grade);
+        let new_review = Review::new(grade, Duration::default(), Duration::default());
         myself.add_review(new_review);
-        myself.strength().unwrap_or_default()
+        myself.strength(scheduler).unwrap_or_default()
     }
 
     
     // Expected gain in memory strength after a review.
-    pub fn expected_gain(&self) -> Option<f32> {
+    pub fn expected_gain(&self, scheduler: &dyn Scheduler) -> Option<f32> {
 
-        let recall_rate = self.recall_rate()?;
+        let recall_rate = self.recall_rate(scheduler)?;
 
-        let current_strength = self.strength()?;
+        let current_strength = self.strength(scheduler)?;
 
         // The estimated strength if you fail at this point in time.
-        let fail_strength = self.next_strength(Grade::Late);
+        let fail_strength = self.next_strength(Grade::Late, scheduler);
         
         // The estimated strength if you succeed at this point in time.
-        let win_strength = self.next_strength(Grade::Some);
+        let win_strength = self.next_strength(Grade::Some, scheduler);
         
         Some(Self::pure_expected_gain(recall_rate, current_strength, fail_strength, win_strength))
         
@@ -985,57 +1511,37 @@ impl Reviews{
     pub fn add_review(&mut self, review: Review) {
         self.0.push(review);
     }
-    
-    
-pub fn new_stability(grade: &Grade, time_passed: Option<Duration>, current_stability: Duration) -> Duration {
-    let grade_factor = grade.get_factor();
-    let time_passed = time_passed.unwrap_or(Duration::from_secs(86400));
-
-    if grade_factor < 1.0 { // the grade is wrong
-        time_passed.min(current_stability).mul_f32(grade_factor)
-    } else { // the grade is correct
-        let alternative_stability = time_passed.mul_f32(grade_factor);
-        if alternative_stability > current_stability {
-             alternative_stability
-        } else {
-            let interpolation_ratio = time_passed.as_secs_f32() / current_stability.as_secs_f32() * grade_factor;
-            current_stability + Duration::from_secs_f32(current_stability.as_secs_f32() * interpolation_ratio)
-        }
-    }
-}
-
-pub fn stability(&self) -> Option<Duration> {
-    let reviews = &self.0;
-    if reviews.is_empty() {
-        return None;
-    }
-
-    let mut stability = Self::new_stability(&reviews[0].grade, None, Duration::from_secs(86400));
-    let mut prev_timestamp = reviews[0].timestamp;
 
-    for review in &reviews[1..] {
-        if prev_timestamp > review.timestamp {
-            return None;
+    /// Union of both histories, reviews at the same timestamp are the same review.
+    pub fn merge(&self, other: &Reviews) -> Reviews {
+        let mut reviews = self.0.clone();
+        for review in &other.0 {
+            if !reviews
+                .iter()
+                .any(|ours| ours.timestamp == review.timestamp)
+            {
+                reviews.push(review.clone());
+            }
         }
-        let time_passed = review.timestamp - prev_timestamp; // Calculate the time passed since the previous review
-        stability = Self::new_stability(&review.grade, Some(time_passed), stability);
-        prev_timestamp = review.timestamp; // Update the timestamp for the next iteration
+        reviews.into()
     }
-
-    Some(stability)
+    
+    
+pub fn stability(&self, scheduler: &dyn Scheduler) -> Option<Duration> {
+    scheduler.stability(&self.0)
 }
 
 
-    pub fn recall_rate(&self) -> Option<RecallRate> {
+    pub fn recall_rate(&self, scheduler: &dyn Scheduler) -> Option<RecallRate> {
         let days_passed = self.time_since_last_review()?;
-        let stability = self.stability()?;
-        Some(Self::calculate_recall_rate(&days_passed, &stability))
+        let stability = self.stability(scheduler)?;
+        Some(scheduler.recall_rate(days_passed, stability))
     }
 
-    pub fn calculate_recall_rate(days_passed: &Duration, stability: &Duration) -> RecallRate {
-        let base: f32 = 0.9;
-        let ratio = days_passed.as_secs_f32() / stability.as_secs_f32();
-        (base.ln() * ratio).exp()
+    /// Unix time when the recall rate drops to `target`.
+    pub fn due(&self, scheduler: &dyn Scheduler, target: RecallRate) -> Option<Duration> {
+        let last_review = self.0.last()?.timestamp;
+        Some(last_review + scheduler.interval(self.stability(scheduler)?, target))
     }
 
     pub fn time_since_last_review(&self) -> Option<Duration> {
@@ -1044,6 +1550,13 @@ pub fn stability(&self) -> Option<Duration> {
     
 }
 
+impl From<Vec<Review>> for Reviews {
+    fn from(mut reviews: Vec<Review>) -> Self {
+        reviews.sort_by_key(|review| review.timestamp);
+        Self(reviews)
+    }
+}
+
 impl Serialize for Reviews {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -1077,16 +1590,33 @@ pub struct Review {
 // How long you spent before attempting recall.
     #[serde(with = "serde_duration_as_secs")]
     pub time_spent: Duration,
+    // How long it took to pick a grade after seeing the answer.
+    #[serde(
+        default,
+        with = "serde_duration_as_secs",
+        skip_serializing_if = "Duration::is_zero"
+    )]
+    pub time_to_grade: Duration,
 }
 
+/// Recalling slower than this counts as struggling, even if you got it right.
+const SLOW_RECALL: Duration = Duration::from_secs(15);
+
 impl Review {
-    fn new(grade: Grade) -> Self {
+    fn new(grade: Grade, time_spent: Duration, time_to_grade: Duration) -> Self {
         Self {
             timestamp: current_time(),
             grade,
-            ..Default::default()
+            time_spent,
+            time_to_grade,
         }
     }
+
+    /// Whether it took a long time to come up with the answer. Reviews from before the
+    /// timing was recorded are never slow.
+    pub fn is_slow(&self) -> bool {
+        self.time_spent > SLOW_RECALL
+    }
     
     fn time_passed(&self) -> Duration {
         let unix = self.timestamp;
@@ -1100,15 +1630,16 @@ pub struct Side {
     pub text: String,
     #[serde(flatten)]
     pub audio: AudioSource,
-    //#[serde(deserialize_with = "deserialize_image_path")]
-    //pub image: ImagePath,
+    #[serde(flatten)]
+    pub image: ImageSource,
 }
 
 
 use serde::de::{Deserializer};
 
-#[derive(Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Clone)]
+#[derive(Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Clone, Default)]
 pub enum IsSuspended{
+    #[default]
     False,
     True,
     // Card is temporarily suspended, until contained unix time has passed.
@@ -1124,12 +1655,6 @@ impl From<bool> for IsSuspended{
     }
 }
 
-impl Default for IsSuspended {
-    fn default() -> Self {
-        Self::False
-    }
-}
-
 impl IsSuspended{
     fn verify_time(self) -> Self {
         if let Self::TrueUntil(dur) = self {
@@ -1195,6 +1720,10 @@ impl Priority {
     pub fn as_float(&self) -> f32 {
         self.to_owned().into()
     }
+
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
 }
 
 impl TryFrom<char> for Priority{
@@ -1308,32 +1837,38 @@ mod tests {
                 timestamp: Duration::from_secs(1687124756),
                 grade: Grade::None,
                 time_spent: Duration::default(),
+                ..Default::default()
             },
             Review {
                 timestamp: Duration::from_secs(1687158818),
                 grade: Grade::Some,
                 time_spent: Duration::default(),
+                ..Default::default()
             },
             Review {
                 timestamp: Duration::from_secs(1687248985),
                 grade: Grade::Some,
                 time_spent: Duration::default(),
+                ..Default::default()
             },
             Review {
                 timestamp: Duration::from_secs(1687439802),
                 grade: Grade::Some,
                 time_spent: Duration::default(),
+                ..Default::default()
             },
             Review {
                 timestamp: Duration::from_secs(1687853599),
                 grade: Grade::Late,
                 time_spent: Duration::default(),
+                ..Default::default()
             },
 
             Review {
                 timestamp: Duration::from_secs(1687853599),
                 grade: Grade::Some,
                 time_spent: Duration::default(),
+                ..Default::default()
             },
 
         ];
@@ -1341,7 +1876,7 @@ mod tests {
         //reviews.pop();
 
         let reviews = Reviews(reviews);
-        let x = reviews.stability().unwrap().as_secs_f32() / 86400.;
+        let x = reviews.stability(&Classic).unwrap().as_secs_f32() / 86400.;
         dbg!(x);
 
     }
@@ -1351,7 +1886,8 @@ mod tests {
         Review {
             timestamp: Duration::default() + Duration::from_secs_f32(passed * 86400.),
             grade: if success { Grade::Some} else {Grade::Late},
-            time_spent: Duration::default()
+            time_spent: Duration::default(),
+            ..Default::default()
         }
     }
     
@@ -1363,7 +1899,7 @@ mod tests {
             debug_review(10., false),
         ];
         let reviews = Reviews(reviews);
-        dbg!(reviews.expected_gain());
+        dbg!(reviews.expected_gain(&Classic));
         
     }
     
@@ -1395,11 +1931,235 @@ mod tests {
     fn test_strength() {
         let stability = Duration::from_secs(86400);
         let days_passed = Duration::default();
-        let recall_rate = Reviews::calculate_recall_rate(&days_passed, &stability);
+        let recall_rate = Classic.recall_rate(days_passed, stability);
         assert_eq!(recall_rate, 1.0);
 
         let days_passed = Duration::from_secs(86400);
-        let recall_rate = Reviews::calculate_recall_rate(&days_passed, &stability);
+        let recall_rate = Classic.recall_rate(days_passed, stability);
         assert_eq!(recall_rate, 0.9);
     }
+    #[test]
+    fn test_merge() {
+        let review = |secs| Review {
+            timestamp: Duration::from_secs(secs),
+            grade: Grade::Some,
+            ..Default::default()
+        };
+        let mut base = Card::new_simple("front".into(), "back".into());
+        base.history = vec![review(1)].into();
+
+        let mut ours = base.clone();
+        ours.history.add_review(review(2));
+        ours.meta.tags.insert("ours".into());
+        ours.meta.finished = false;
+
+        let mut theirs = base.clone();
+        theirs.history.add_review(review(3));
+        theirs.meta.tags.insert("theirs".into());
+        theirs.back.text = "better back".into();
+
+        let merged = ours.merge(&theirs, Some(&base));
+        assert_eq!(merged.history, vec![review(1), review(2), review(3)].into());
+        assert_eq!(merged.meta.tags.len(), 2);
+        assert!(!merged.meta.finished);
+        assert_eq!(merged.back.text, "better back");
+
+        // Without a base we keep our side.
+        assert_eq!(ours.merge(&theirs, None).back.text, "back");
+    }
+
+    #[test]
+    fn test_depends_on_broken_card() {
+        let _share = crate::paths::TempShare::new();
+        let broken = Card::new_simple("broken".into(), "card".into());
+        let broken_path = Category::root().as_path().join("broken.toml");
+        std::fs::write(&broken_path, "[front]\ntext = ").unwrap();
+
+        let mut cache = CardCache::new();
+        let mut card = Card::new_simple("depends".into(), "on it".into());
+        card.meta.dependencies.insert(broken.meta.id);
+        let card = card.save_new_card(&Category::root(), &mut cache);
+        assert_eq!(cache.broken().len(), 1);
+
+        assert!(cache.dependencies(card.id()).is_empty());
+        assert!(card.is_resolved(&mut cache));
+        assert_eq!(
+            SavedCard::pending_filter(card.id(), &mut cache),
+            vec![ReviewItem::Front]
+        );
+        assert!(SavedCard::review_filter(card.id(), &mut cache).is_empty());
+        assert!(cache.try_get_ref(&broken.meta.id).is_none());
+    }
+
+    #[test]
+    fn test_health_check_removes_dangling_dependent() {
+        let _share = crate::paths::TempShare::new();
+        let mut cache = CardCache::new();
+        let missing = Id::new_v4();
+        let mut card = Card::new_simple("needed".into(), "by a deleted card".into());
+        card.meta.dependents.insert(missing);
+        let card = card.save_new_card(&Category::root(), &mut cache);
+
+        let removed = cache.health_check();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].missing, missing);
+        assert!(!removed[0].is_dependency);
+
+        let saved = SavedCard::from_path(&card.as_path()).unwrap();
+        assert!(saved.dependent_ids().is_empty());
+        assert!(cache.health_check().is_empty());
+    }
+
+    #[test]
+    fn test_cloze() {
+        let mut card = Card::new_simple("{{c1::Paris}} is in {{c2::France}}".into(), String::new());
+        let location = CardLocation {
+            file_name: "cloze.toml".into(),
+            category: Category::default(),
+        };
+        let saved = SavedCard::new(card.clone(), location.clone(), Duration::default());
+        assert_eq!(saved.next_item(), ReviewItem::Cloze(1));
+        assert_eq!(
+            saved.review_front(ReviewItem::Cloze(1)),
+            "[...] is in France"
+        );
+        assert!(saved.is_pending());
+
+        let review = Review {
+            timestamp: current_time(),
+            grade: Grade::Perfect,
+            ..Default::default()
+        };
+        card.clozes
+            .entry(cloze::key(1))
+            .or_default()
+            .add_review(review.clone());
+        let saved = SavedCard::new(card.clone(), location, Duration::default());
+        assert_eq!(saved.next_item(), ReviewItem::Cloze(2));
+        assert!(saved.is_pending());
+        assert_eq!(
+            saved.review_back(ReviewItem::Cloze(2)),
+            "Paris is in [France]"
+        );
+        assert_eq!(saved.review_histories().len(), 2);
+
+        let mut theirs = card.clone();
+        theirs
+            .clozes
+            .entry(cloze::key(2))
+            .or_default()
+            .add_review(review);
+        let merged = card.merge(&theirs, None);
+        assert_eq!(merged.clozes.len(), 2);
+        assert!(merged.history.is_empty());
+    }
+
+    /// Reviewed long enough ago that any history with just this is due.
+    fn old_review() -> Review {
+        Review {
+            timestamp: current_time() - Duration::from_secs(86400 * 365),
+            grade: Grade::Some,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cloze_deletions_are_queued_separately() {
+        let _share = crate::paths::TempShare::new();
+        let mut cache = CardCache::new();
+        let mut card = Card::new_simple(
+            "{{c1::Paris}} is in {{c2::France}} in {{c3::Europe}}".into(),
+            String::new(),
+        );
+        card.clozes
+            .entry(cloze::key(1))
+            .or_default()
+            .add_review(old_review());
+        card.clozes
+            .entry(cloze::key(3))
+            .or_default()
+            .add_review(old_review());
+        let card = card.save_new_card(&Category::root(), &mut cache);
+
+        // The new deletion doesn't hide the due ones.
+        assert_eq!(
+            SavedCard::pending_filter(card.id(), &mut cache),
+            vec![ReviewItem::Cloze(2)]
+        );
+        assert_eq!(
+            SavedCard::review_filter(card.id(), &mut cache),
+            vec![ReviewItem::Cloze(1), ReviewItem::Cloze(3)]
+        );
+
+        // Reviewing one deletion doesn't hold back the others.
+        cache.get_owned(card.id()).new_review(
+            ReviewItem::Cloze(1),
+            Grade::Perfect,
+            Duration::default(),
+            Duration::default(),
+        );
+        assert_eq!(
+            SavedCard::review_filter(card.id(), &mut cache),
+            vec![ReviewItem::Cloze(3)]
+        );
+    }
+
+    #[test]
+    fn test_reversible() {
+        let mut card = Card {
+            reversible: true,
+            ..Card::new_simple("hund".into(), "dog".into())
+        };
+        let location = CardLocation {
+            file_name: "hund.toml".into(),
+            category: Category::default(),
+        };
+        let saved = SavedCard::new(card.clone(), location.clone(), Duration::default());
+        assert_eq!(saved.next_item(), ReviewItem::Front);
+
+        let review = Review {
+            timestamp: current_time(),
+            grade: Grade::Perfect,
+            ..Default::default()
+        };
+        card.history.add_review(review.clone());
+        card.back.text = "a dog".into();
+        let saved = SavedCard::new(card.clone(), location.clone(), Duration::default());
+        assert_eq!(saved.next_item(), ReviewItem::Reverse);
+        assert_eq!(saved.review_front(ReviewItem::Reverse), "a dog");
+        assert_eq!(saved.review_back(ReviewItem::Reverse), "hund");
+        assert!(saved.is_pending());
+
+        // Both directions reviewed, the one that's due first comes up.
+        card.reverse_history.add_review(Review {
+            grade: Grade::None,
+            ..review
+        });
+        let saved = SavedCard::new(card.clone(), location.clone(), Duration::default());
+        assert_eq!(saved.next_item(), ReviewItem::Reverse);
+        assert!(!saved.is_pending());
+
+        card.reversible = false;
+        let saved = SavedCard::new(card, location, Duration::default());
+        assert_eq!(saved.next_item(), ReviewItem::Front);
+    }
+
+    #[test]
+    fn test_both_directions_due() {
+        let _share = crate::paths::TempShare::new();
+        let mut cache = CardCache::new();
+        let mut card = Card {
+            reversible: true,
+            ..Card::new_simple("hund".into(), "dog".into())
+        };
+        card.history.add_review(old_review());
+        card.reverse_history.add_review(old_review());
+        let card = card.save_new_card(&Category::root(), &mut cache);
+
+        assert_eq!(
+            SavedCard::review_filter(card.id(), &mut cache),
+            vec![ReviewItem::Front, ReviewItem::Reverse]
+        );
+        assert!(SavedCard::pending_filter(card.id(), &mut cache).is_empty());
+    }
 }
@@ -1,9 +1,9 @@
-use crate::card::{CardCache, CardLocation, SavedCard};
-use crate::common::get_last_modified;
+use crate::card::{CardCache, ReviewItem, SavedCard};
+use crate::error::{self, Error};
 use crate::paths::{self, get_cards_path};
 use crate::Id;
 use std::collections::{BTreeSet, HashSet};
-use std::fs::{self, create_dir_all, File};
+use std::fs::{self, File};
 use std::io::{self, BufRead};
 use std::path::Path;
 use std::path::PathBuf;
@@ -12,6 +12,8 @@ use std::path::PathBuf;
 #[derive(Ord, PartialOrd, Eq, Hash, Debug, Clone, Default, PartialEq)]
 pub struct Category(pub Vec<String>);
 pub type CardFilter = Box<dyn FnMut(&Id, &mut CardCache) -> bool>;
+/// Which review items of the card pass.
+pub type ItemFilter = Box<dyn FnMut(&Id, &mut CardCache) -> Vec<ReviewItem>>;
 
 fn read_lines<P>(filename: P) -> io::Result<Vec<String>>
 where
@@ -61,9 +63,22 @@ impl Category {
     pub fn joined(&self) -> String {
         self.0.join("/")
     }
-    pub fn from_card_path(path: &Path) -> Self {
-        let without_prefix = path.strip_prefix(paths::get_cards_path()).unwrap();
-        let folder = without_prefix.parent().unwrap();
+
+    /// Inverse of `joined`, leading and trailing slashes are ignored.
+    pub fn from_joined(s: &str) -> Self {
+        Self(
+            s.split('/')
+                .filter(|c| !c.is_empty())
+                .map(String::from)
+                .collect(),
+        )
+    }
+    pub fn from_card_path(path: &Path) -> error::Result<Self> {
+        let not_in_category = || Error::NotInCategory(path.to_path_buf());
+        let without_prefix = path
+            .strip_prefix(paths::get_cards_path())
+            .map_err(|_| not_in_category())?;
+        let folder = without_prefix.parent().ok_or_else(not_in_category)?;
 
         let components: Vec<String> = Path::new(folder)
             .components()
@@ -73,52 +88,52 @@ impl Category {
         let categories = Self(components);
 
         if categories.as_path().exists() {
-            categories
+            Ok(categories)
         } else {
-            panic!();
+            Err(not_in_category())
         }
     }
 
-    pub fn get_containing_cards(&self) -> HashSet<SavedCard> {
-        let directory = self.as_path();
-        let mut cards = HashSet::new();
-
-        for entry in std::fs::read_dir(directory).unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
-
+    pub fn card_paths(&self) -> io::Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for entry in std::fs::read_dir(self.as_path())? {
+            let path = entry?.path();
             if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("toml") {
-                let card = SavedCard::from_path(path.as_path()).into_card();
-                let location = CardLocation::new(&path);
-                let last_modified = get_last_modified(path);
-
-                let full_card = SavedCard::new(card, location, last_modified);
-                cards.insert(full_card);
+                paths.push(path);
             }
         }
-        cards
+        Ok(paths)
     }
 
-    pub fn get_containing_card_ids(&self) -> HashSet<Id> {
-        let directory = self.as_path();
+    /// Loads the cards in this folder, the files that don't load go in `broken`.
+    pub fn get_containing_cards(&self, broken: &mut Vec<Error>) -> HashSet<SavedCard> {
         let mut cards = HashSet::new();
+        let paths = match self.card_paths() {
+            Ok(paths) => paths,
+            Err(e) => {
+                broken.push(Error::io(&self.as_path(), e));
+                return cards;
+            }
+        };
 
-        for entry in std::fs::read_dir(directory).unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
-
-            if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("toml") {
-                let card = SavedCard::from_path(path.as_path()).into_card();
-                let location = CardLocation::new(&path);
-                let last_modified = get_last_modified(path);
-
-                let full_card = SavedCard::new(card, location, last_modified);
-                cards.insert(full_card.id().to_owned());
+        for path in paths {
+            match SavedCard::from_path(path.as_path()) {
+                Ok(card) => {
+                    cards.insert(card);
+                }
+                Err(e) => broken.push(e),
             }
         }
         cards
     }
 
+    pub fn get_containing_card_ids(&self) -> HashSet<Id> {
+        self.get_containing_cards(&mut vec![])
+            .iter()
+            .map(|card| card.id().to_owned())
+            .collect()
+    }
+
     pub fn sort_categories(categories: &mut [Category]) {
         categories.sort_by(|a, b| {
             let a_str = a.0.join("/");
@@ -219,12 +234,24 @@ impl Category {
         self.get_cards_with_filter(Box::new(SavedCard::unfinished_filter), cache)
     }
 
-    pub fn get_pending_cards(&self, cache: &mut CardCache) -> Vec<Id> {
-        self.get_cards_with_filter(Box::new(SavedCard::pending_filter), cache)
+    fn get_items_with_filter(
+        &self,
+        mut filter: ItemFilter,
+        cache: &mut CardCache,
+    ) -> Vec<(Id, ReviewItem)> {
+        let mut items = vec![];
+        for card in self.get_containing_card_ids() {
+            items.extend(filter(&card, cache).into_iter().map(|item| (card, item)));
+        }
+        items
     }
 
-    pub fn get_review_cards(&self, cache: &mut CardCache) -> Vec<Id> {
-        self.get_cards_with_filter(Box::new(SavedCard::review_filter), cache)
+    pub fn get_pending_cards(&self, cache: &mut CardCache) -> Vec<(Id, ReviewItem)> {
+        self.get_items_with_filter(Box::new(SavedCard::pending_filter), cache)
+    }
+
+    pub fn get_review_cards(&self, cache: &mut CardCache) -> Vec<(Id, ReviewItem)> {
+        self.get_items_with_filter(Box::new(SavedCard::review_filter), cache)
     }
 }
 
@@ -257,7 +284,35 @@ mod tests {
             .join("foo")
             .join("bar")
             .join("guten tag.toml");
-        Category::from_card_path(cards_path.as_path());
+        let category = Category::from_card_path(cards_path.as_path()).unwrap();
+        assert_eq!(category, Category(vec!["foo".into(), "bar".into()]));
+
+        let missing = paths::get_cards_path().join("nowhere").join("card.toml");
+        assert!(matches!(
+            Category::from_card_path(&missing),
+            Err(Error::NotInCategory(_))
+        ));
+    }
+
+    #[test]
+    fn test_broken_cards_are_skipped() {
+        let fine = fs::read_to_string(paths::get_cards_path().join("finished.toml")).unwrap();
+        let _share = paths::TempShare::new();
+        let category = Category(vec!["deck".into()]);
+        fs::create_dir_all(category.as_path()).unwrap();
+        fs::write(category.as_path().join("fine.toml"), fine).unwrap();
+        fs::write(category.as_path().join("broken.toml"), "[front]\ntext = ").unwrap();
+
+        let mut broken = vec![];
+        let cards = category.get_containing_cards(&mut broken);
+
+        assert_eq!(cards.len(), 1);
+        assert_eq!(broken.len(), 1);
+        assert_eq!(
+            broken[0].path(),
+            category.as_path().join("broken.toml").as_path()
+        );
+        assert_eq!(broken[0].line(), Some(2));
     }
 
     /*
@@ -0,0 +1,162 @@
+//! Multiple choice reviews, picking the answer out of a few from cards around it.
+//!
+//! The wrong options are the backs of other cards in the same category or with a tag in
+//! common, so they look like they could be the answer. Recognising an answer is easier than
+//! recalling it, so a right pick is never graded perfect.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::answer::normalize;
+use crate::card::{CardCache, Grade, ReviewItem, SavedCard};
+use crate::common::randvec;
+
+/// Right answers picked quicker than this count as remembered, slower as barely.
+const QUICK_PICK: Duration = Duration::from_secs(8);
+
+/// The side of a card the options come from, None for cloze cards which have no back to
+/// pick from.
+fn answer_of(card: &SavedCard, item: ReviewItem) -> Option<&str> {
+    let card = card.card_as_ref();
+    if !card.review_items().contains(&ReviewItem::Front) {
+        return None;
+    }
+    match item {
+        ReviewItem::Front => Some(&card.back.text),
+        ReviewItem::Reverse => Some(&card.front.text),
+        ReviewItem::Cloze(_) => None,
+    }
+}
+
+/// Every wrong option there is for the card, those from the same category first and each
+/// group shuffled, so it's not always the same few that get picked.
+fn distractors(card: &SavedCard, item: ReviewItem, others: &[Arc<SavedCard>]) -> Vec<String> {
+    let Some(answer) = answer_of(card, item) else {
+        return vec![];
+    };
+    let tags = &card.card_as_ref().meta.tags;
+
+    let mut seen = HashSet::from([normalize(answer)]);
+    let mut same_category = vec![];
+    let mut same_tag = vec![];
+    for other in others {
+        let Some(text) = answer_of(other, item) else {
+            continue;
+        };
+        let related = if other.category() == card.category() {
+            &mut same_category
+        } else if !other.card_as_ref().meta.tags.is_disjoint(tags) {
+            &mut same_tag
+        } else {
+            continue;
+        };
+        if !text.trim().is_empty() && seen.insert(normalize(text)) {
+            related.push(text.to_string());
+        }
+    }
+    let mut options = randvec(same_category);
+    options.extend(randvec(same_tag));
+    options
+}
+
+/// Up to `count` options in random order, and which one is right. Empty if there's nothing
+/// to mix the answer with.
+pub fn options(
+    card: &SavedCard,
+    item: ReviewItem,
+    cache: &mut CardCache,
+    count: usize,
+) -> (Vec<String>, usize) {
+    let others: Vec<_> = cache
+        .all_ids()
+        .iter()
+        .filter_map(|id| cache.try_get_ref(id))
+        .collect();
+    let mut wrong = distractors(card, item, &others);
+    if wrong.is_empty() {
+        return (vec![], 0);
+    }
+    wrong.truncate(count.saturating_sub(1).max(1));
+    let answer = answer_of(card, item).unwrap_or_default().to_string();
+    wrong.push(answer.clone());
+    let options = randvec(wrong);
+    let right = options.iter().position(|option| *option == answer).unwrap();
+    (options, right)
+}
+
+pub fn grade(correct: bool, latency: Duration) -> Grade {
+    match (correct, latency < QUICK_PICK) {
+        (false, _) => Grade::None,
+        (true, true) => Grade::Some,
+        (true, false) => Grade::Late,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::Card;
+    use crate::categories::Category;
+
+    #[test]
+    fn test_grade() {
+        assert_eq!(grade(true, Duration::from_secs(2)), Grade::Some);
+        assert_eq!(grade(true, Duration::from_secs(30)), Grade::Late);
+        assert_eq!(grade(false, Duration::from_secs(2)), Grade::None);
+    }
+
+    #[test]
+    fn test_distractors() {
+        let _share = crate::paths::TempShare::new();
+        let tag = "animals".to_string();
+        let deck = Category(vec!["deck".into()]);
+        let elsewhere = Category(vec!["elsewhere".into()]);
+        let mut others = vec![];
+        let mut save = |category: &Category, front: &str, back: &str, tagged: bool| {
+            let mut card = Card::new_simple(front.into(), back.into());
+            if tagged {
+                card.meta.tags.insert(tag.clone());
+            }
+            std::fs::create_dir_all(category.as_path()).unwrap();
+            let path = category.as_path().join(format!("{front}.toml"));
+            std::fs::write(&path, toml::to_string(&card).unwrap()).unwrap();
+            let card = SavedCard::from_path(&path).unwrap();
+            others.push(Arc::new(card.clone()));
+            card
+        };
+
+        let card = save(&deck, "hund", "dog", true);
+        save(&deck, "katze", "cat", false);
+        save(&deck, "dogge", " Dog", false);
+        save(&elsewhere, "maus", "mouse", true);
+        save(&elsewhere, "vogel", "bird", false);
+        let found = distractors(&card, ReviewItem::Front, &others);
+        assert_eq!(found, vec!["cat".to_string(), "mouse".to_string()]);
+    }
+
+    #[test]
+    fn test_options_are_not_always_the_first() {
+        let _share = crate::paths::TempShare::new();
+        let mut cache = CardCache::new();
+        let deck = Category(vec!["deck".into()]);
+        let backs: Vec<String> = (0..20).map(|i| format!("back {i}")).collect();
+        let cards: Vec<_> = backs
+            .iter()
+            .enumerate()
+            .map(|(i, back)| {
+                Card::new_simple(format!("front {i}"), back.clone())
+                    .save_new_card(&deck, &mut cache)
+            })
+            .collect();
+
+        let mut picked = HashSet::new();
+        for _ in 0..20 {
+            let (options, right) = options(&cards[0], ReviewItem::Front, &mut cache, 4);
+            assert_eq!(options.len(), 4);
+            assert_eq!(options[right], backs[0]);
+            picked.extend(options);
+        }
+        assert!(picked.len() > 4);
+    }
+}
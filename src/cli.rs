@@ -1,12 +1,84 @@
-use std::error::Error;
+//! Headless commands for scripting speki from the shell, runs without entering raw mode.
+//! Every command prints tab separated values by default, or JSON lines with `--json`.
 
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Serialize;
+
+#[cfg(feature = "anki")]
+use crate::anki::AnkiImport;
 use crate::{
-    card::{Card, CardCache, Meta, Side},
+    card::{Card, CardCache, Grade, Meta, Review, SavedCard, Side, Stats},
     categories::Category,
-    media::AudioSource,
-    paths::{get_import_csv, get_share_path},
+    config::Config,
+    export::{export_category, ExportFormat},
+    fsrs::{self, Fsrs},
+    git,
+    import::{CsvImport, ImportOptions},
+    media::{self, AudioSource, ImageSource, MediaStore},
+    migrate,
+    paths::{get_cards_path, get_import_csv, get_media_path, get_share_path},
+    scheduler::{log_loss, Classic},
+    trash, Id,
 };
 
+const USAGE: &str = "\
+usage: spekinew [command] [--json|--tsv]
+
+without a command the interactive interface is started. commands only change the local cards,
+run sync to share them.
+
+commands:
+    add <category> <front> [back]   add a card, prints: id, path
+                                    a front with {{c1::...}} deletions makes a cloze card, where
+                                    every number is reviewed on its own and back is extra info
+        --tag <tag>                 can be repeated
+        --unfinished                mark the card as unfinished
+        --reversible                also review it from back to front
+        --audio-local <file>        audio for the front side, a file name in the media folder or a
+                                    file anywhere else, which is copied into it
+        --audio-url <url>           where to download the front side audio from
+        --front-image <file>        image for the front side, like --audio-local
+        --back-image <file>         image for the back side, like --audio-local
+    due [category]                  cards due for review, prints: id, category, recall, stability, front
+        --pending                   list pending cards instead
+    stats                           prints: total, finished, suspended, pending, resolved, reviews, strength
+    import [file]                   import cards, prints: id, category, front
+                                    .csv/.tsv files are read as columns, defaults to import.csv in the
+                                    share folder if it exists. .apkg files are imported from anki with
+                                    decks as categories. other files are alternating question/answer lines.
+        --category <category>       where the cards go, or what anki decks are nested under
+        --map <spec>                column mapping like front=1,back=Answer,tags=3, fields are front, back,
+                                    tags, priority, category, audio_local and audio_url
+        --delimiter <char>          column delimiter, 'tab' for tabs
+        --header / --no-header      whether the first row is a header, guessed if not given
+        --dry-run                   only show what would be imported
+    export [category]               every card under category, prints: id, category, front, back, tags
+        --format <format>           write the cards as csv, jsonl (with metadata and reviews) or anki
+                                    (tab separated, for anki's text importer) instead
+        --output <file>             write to a file, the format is guessed from the extension if not given
+    health                          remove references to missing cards, prints: card, missing, kind
+    optimize                        fit the fsrs scheduler to your review history and save the weights
+                                    to the config, prints: model, log-loss, reviews, weights
+        --dry-run                   only show how well the models fit
+    grade <id> <grade>              review a card, grade is 1-4 or none/late/some/perfect,
+                                    prints: id, recall, stability
+    trash                           deleted cards, most recent first, prints: id, category, deleted, front
+    restore <id>                    put a deleted card back where it was, prints: id, category, front
+    migrate                         upgrade card files from older versions, otherwise a file is only
+                                    upgraded when its card is saved, prints: path, from, to, changes
+        --dry-run                   only show what would change
+    gc                              remove media files no card uses, trashed cards included,
+                                    prints: file
+        --dry-run                   only show what would be removed
+    sync                            commit the cards and sync them with the sync target or git remote
+                                    from the config, prints: committed, pulled, pushed, merged, sent,
+                                    received. fails if some files couldn't be merged
+    help                            show this message";
+
 fn empty_str_optional(s: String) -> Option<String> {
     if s.is_empty() {
         None
@@ -14,3 +86,1083 @@ fn empty_str_optional(s: String) -> Option<String> {
         Some(s)
     }
 }
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+enum Format {
+    #[default]
+    Tsv,
+    Json,
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Add {
+        category: Category,
+        front: String,
+        back: String,
+        tags: Vec<String>,
+        unfinished: bool,
+        reversible: bool,
+        audio: AudioSource,
+        front_image: ImageSource,
+        back_image: ImageSource,
+    },
+    Due {
+        category: Category,
+        pending: bool,
+    },
+    Stats,
+    Optimize {
+        dry_run: bool,
+    },
+    Import {
+        path: PathBuf,
+        kind: ImportKind,
+        dry_run: bool,
+    },
+    Export {
+        category: Category,
+        /// None prints the usual rows.
+        export_format: Option<ExportFormat>,
+        output: Option<PathBuf>,
+    },
+    Health,
+    Grade {
+        id: Id,
+        grade: Grade,
+    },
+    Trash,
+    Restore {
+        id: Id,
+    },
+    Migrate {
+        dry_run: bool,
+    },
+    Gc {
+        dry_run: bool,
+    },
+    Sync,
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+enum ImportKind {
+    /// Alternating question and answer lines, saved into the given category.
+    Lines(Category),
+    Csv(ImportOptions),
+    /// Anki decks are nested under the given category.
+    Anki(Category),
+}
+
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("missing value for {flag}"))
+}
+
+fn check_positional(positional: &[String], min: usize, max: usize) -> Result<(), String> {
+    if positional.len() < min {
+        Err("missing arguments".to_string())
+    } else if positional.len() > max {
+        Err(format!("unexpected argument: {}", positional[max]))
+    } else {
+        Ok(())
+    }
+}
+
+fn parse(args: Vec<String>) -> Result<(Command, Format), String> {
+    let mut args = args.into_iter();
+    let mut format = Format::default();
+    let Some(command) = args.next() else {
+        return Ok((Command::Help, format));
+    };
+
+    let mut positional = vec![];
+    let mut tags = vec![];
+    let mut unfinished = false;
+    let mut reversible = false;
+    let mut pending = false;
+    let mut audio_local = String::new();
+    let mut audio_url = String::new();
+    let mut front_image = String::new();
+    let mut back_image = String::new();
+    let mut mapping = None;
+    let mut delimiter = None;
+    let mut has_header = None;
+    let mut dry_run = false;
+    let mut target_category = None;
+    let mut export_format = None;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => format = Format::Json,
+            "--tsv" => format = Format::Tsv,
+            "--tag" => tags.push(flag_value(&mut args, "--tag")?),
+            "--unfinished" => unfinished = true,
+            "--reversible" => reversible = true,
+            "--pending" => pending = true,
+            "--audio-local" => audio_local = flag_value(&mut args, "--audio-local")?,
+            "--audio-url" => audio_url = flag_value(&mut args, "--audio-url")?,
+            "--front-image" => front_image = flag_value(&mut args, "--front-image")?,
+            "--back-image" => back_image = flag_value(&mut args, "--back-image")?,
+            "--map" => mapping = Some(flag_value(&mut args, "--map")?),
+            "--delimiter" => {
+                let value = flag_value(&mut args, "--delimiter")?;
+                delimiter = match value.as_str() {
+                    "tab" | "\\t" => Some('\t'),
+                    _ if value.chars().count() == 1 => value.chars().next(),
+                    _ => return Err(format!("delimiter must be a single character: {value}")),
+                };
+            }
+            "--header" => has_header = Some(true),
+            "--no-header" => has_header = Some(false),
+            "--dry-run" => dry_run = true,
+            "--category" => {
+                target_category = Some(Category::from_joined(&flag_value(&mut args, "--category")?))
+            }
+            "--format" => export_format = Some(flag_value(&mut args, "--format")?.parse()?),
+            "--output" => output = Some(PathBuf::from(flag_value(&mut args, "--output")?)),
+            flag if flag.starts_with("--") => return Err(format!("unknown flag: {flag}")),
+            _ => positional.push(arg),
+        }
+    }
+
+    let category = |idx: usize| {
+        positional
+            .get(idx)
+            .map(|s| Category::from_joined(s))
+            .unwrap_or_default()
+    };
+
+    let command = match command.as_str() {
+        "add" => {
+            check_positional(&positional, 2, 3)?;
+            Command::Add {
+                category: category(0),
+                front: positional[1].clone(),
+                back: positional.get(2).cloned().unwrap_or_default(),
+                tags,
+                unfinished,
+                reversible,
+                audio: AudioSource::new(
+                    empty_str_optional(audio_local),
+                    empty_str_optional(audio_url),
+                ),
+                front_image: ImageSource::new(empty_str_optional(front_image)),
+                back_image: ImageSource::new(empty_str_optional(back_image)),
+            }
+        }
+        "due" => {
+            check_positional(&positional, 0, 1)?;
+            Command::Due {
+                category: category(0),
+                pending,
+            }
+        }
+        "stats" => {
+            check_positional(&positional, 0, 0)?;
+            Command::Stats
+        }
+        "optimize" => {
+            check_positional(&positional, 0, 0)?;
+            Command::Optimize { dry_run }
+        }
+        "import" => {
+            check_positional(&positional, 0, 1)?;
+            let path = positional.first().map(PathBuf::from).unwrap_or_else(|| {
+                if get_import_csv().exists() {
+                    get_import_csv()
+                } else {
+                    get_share_path().join("forimport.txt")
+                }
+            });
+
+            let extension = path.extension().and_then(|ext| ext.to_str());
+            let is_csv = matches!(extension, Some("csv" | "tsv" | "tab"))
+                || mapping.is_some()
+                || delimiter.is_some()
+                || has_header.is_some();
+
+            let kind = if matches!(extension, Some("apkg" | "colpkg")) {
+                ImportKind::Anki(target_category.unwrap_or_default())
+            } else if is_csv {
+                let mut options = ImportOptions::for_path(&path);
+                options.delimiter = delimiter.unwrap_or(options.delimiter);
+                options.mapping = mapping;
+                options.has_header = has_header;
+                options.category = target_category.unwrap_or(options.category);
+                ImportKind::Csv(options)
+            } else {
+                ImportKind::Lines(target_category.unwrap_or(Category(vec!["imports".into()])))
+            };
+
+            Command::Import {
+                path,
+                kind,
+                dry_run,
+            }
+        }
+        "export" => {
+            check_positional(&positional, 0, 1)?;
+            let export_format = match (&export_format, &output) {
+                (None, Some(output)) => {
+                    let extension = output.extension().and_then(|ext| ext.to_str());
+                    Some(match extension {
+                        Some("csv") => ExportFormat::Csv,
+                        Some("jsonl" | "json") => ExportFormat::JsonLines,
+                        Some("txt" | "tsv") => ExportFormat::AnkiTsv,
+                        _ => return Err("can't tell the export format, use --format".to_string()),
+                    })
+                }
+                _ => export_format,
+            };
+            Command::Export {
+                category: category(0),
+                export_format,
+                output,
+            }
+        }
+        "health" => {
+            check_positional(&positional, 0, 0)?;
+            Command::Health
+        }
+        "grade" => {
+            check_positional(&positional, 2, 2)?;
+            let id = positional[0]
+                .parse()
+                .map_err(|_| format!("invalid card id: {}", positional[0]))?;
+            let grade = positional[1]
+                .parse()
+                .map_err(|_| format!("invalid grade: {}", positional[1]))?;
+            Command::Grade { id, grade }
+        }
+        "trash" => {
+            check_positional(&positional, 0, 0)?;
+            Command::Trash
+        }
+        "restore" => {
+            check_positional(&positional, 1, 1)?;
+            let id = positional[0]
+                .parse()
+                .map_err(|_| format!("invalid card id: {}", positional[0]))?;
+            Command::Restore { id }
+        }
+        "migrate" => {
+            check_positional(&positional, 0, 0)?;
+            Command::Migrate { dry_run }
+        }
+        "gc" => {
+            check_positional(&positional, 0, 0)?;
+            Command::Gc { dry_run }
+        }
+        "sync" => {
+            check_positional(&positional, 0, 0)?;
+            Command::Sync
+        }
+        "help" | "--help" | "-h" => Command::Help,
+        other => return Err(format!("unknown command: {other}")),
+    };
+
+    Ok((command, format))
+}
+
+/// Tabs and newlines would break the columns, so they're escaped the same way `cut`-friendly tools do.
+fn tsv_field(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn optional_float(num: Option<f32>) -> String {
+    num.map(|num| num.to_string()).unwrap_or_default()
+}
+
+trait Row: Serialize {
+    fn tsv(&self) -> Vec<String>;
+
+    fn print(&self, format: Format) {
+        match format {
+            Format::Json => println!("{}", serde_json::to_string(self).unwrap()),
+            Format::Tsv => println!(
+                "{}",
+                self.tsv()
+                    .iter()
+                    .map(|field| tsv_field(field))
+                    .collect::<Vec<_>>()
+                    .join("\t")
+            ),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AddedRow {
+    id: Id,
+    path: PathBuf,
+}
+
+impl Row for AddedRow {
+    fn tsv(&self) -> Vec<String> {
+        vec![self.id.to_string(), self.path.display().to_string()]
+    }
+}
+
+#[derive(Serialize)]
+struct CardRow {
+    id: Id,
+    category: String,
+    recall_rate: Option<f32>,
+    stability_days: Option<f32>,
+    front: String,
+}
+
+impl CardRow {
+    fn new(card: &SavedCard) -> Self {
+        Self {
+            id: *card.id(),
+            category: card.category().print_full(),
+            recall_rate: card.recall_rate(),
+            stability_days: card.stability().map(|s| s.as_secs_f32() / 86400.),
+            front: card.front_text().to_owned(),
+        }
+    }
+}
+
+impl Row for CardRow {
+    fn tsv(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.category.clone(),
+            optional_float(self.recall_rate),
+            optional_float(self.stability_days),
+            self.front.clone(),
+        ]
+    }
+}
+
+impl Row for Stats {
+    fn tsv(&self) -> Vec<String> {
+        [
+            self.total,
+            self.finished,
+            self.suspended,
+            self.pending,
+            self.resolved,
+            self.reviews,
+        ]
+        .iter()
+        .map(|num| num.to_string())
+        .chain(std::iter::once(self.strength.to_string()))
+        .collect()
+    }
+}
+
+#[derive(Serialize)]
+struct ImportedRow {
+    id: Id,
+    category: String,
+    front: String,
+}
+
+impl From<&SavedCard> for ImportedRow {
+    fn from(card: &SavedCard) -> Self {
+        Self {
+            id: *card.id(),
+            category: card.category().print_full(),
+            front: card.front_text().to_owned(),
+        }
+    }
+}
+
+impl Row for ImportedRow {
+    fn tsv(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.category.clone(),
+            self.front.clone(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct ExportRow {
+    id: Id,
+    category: String,
+    front: String,
+    back: String,
+    tags: BTreeSet<String>,
+}
+
+impl Row for ExportRow {
+    fn tsv(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.category.clone(),
+            self.front.clone(),
+            self.back.clone(),
+            self.tags.iter().cloned().collect::<Vec<_>>().join(" "),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct HealthRow {
+    card: Id,
+    missing: Id,
+    kind: &'static str,
+}
+
+impl Row for HealthRow {
+    fn tsv(&self) -> Vec<String> {
+        vec![
+            self.card.to_string(),
+            self.missing.to_string(),
+            self.kind.to_string(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct MigratedRow {
+    path: String,
+    from: u32,
+    to: u32,
+    changes: Vec<String>,
+}
+
+impl Row for MigratedRow {
+    fn tsv(&self) -> Vec<String> {
+        vec![
+            self.path.clone(),
+            self.from.to_string(),
+            self.to.to_string(),
+            self.changes.join("; "),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct GcRow {
+    file: String,
+}
+
+impl Row for GcRow {
+    fn tsv(&self) -> Vec<String> {
+        vec![self.file.clone()]
+    }
+}
+
+#[derive(Serialize)]
+struct SyncRow {
+    committed: bool,
+    pulled: bool,
+    pushed: bool,
+    merged: usize,
+    sent: usize,
+    received: usize,
+}
+
+impl Row for SyncRow {
+    fn tsv(&self) -> Vec<String> {
+        vec![
+            self.committed.to_string(),
+            self.pulled.to_string(),
+            self.pushed.to_string(),
+            self.merged.to_string(),
+            self.sent.to_string(),
+            self.received.to_string(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct TrashRow {
+    id: Id,
+    category: String,
+    /// Unix time in seconds.
+    deleted_at: u64,
+    front: String,
+}
+
+impl Row for TrashRow {
+    fn tsv(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.category.clone(),
+            self.deleted_at.to_string(),
+            self.front.clone(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct GradedRow {
+    id: Id,
+    recall_rate: Option<f32>,
+    stability_days: Option<f32>,
+}
+
+impl Row for GradedRow {
+    fn tsv(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            optional_float(self.recall_rate),
+            optional_float(self.stability_days),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct ModelRow {
+    model: &'static str,
+    log_loss: f64,
+    reviews: usize,
+    weights: Vec<f32>,
+}
+
+impl Row for ModelRow {
+    fn tsv(&self) -> Vec<String> {
+        let weights: Vec<String> = self.weights.iter().map(|w| format!("{w:.4}")).collect();
+        vec![
+            self.model.to_string(),
+            format!("{:.4}", self.log_loss),
+            self.reviews.to_string(),
+            weights.join(","),
+        ]
+    }
+}
+
+fn execute(command: Command, format: Format, cards: &mut Cards) -> Result<(), String> {
+    match command {
+        Command::Add {
+            category,
+            front,
+            back,
+            tags,
+            unfinished,
+            reversible,
+            audio,
+            front_image,
+            back_image,
+        } => {
+            let meta = Meta {
+                finished: !unfinished,
+                tags: tags.into_iter().collect(),
+                ..Default::default()
+            };
+            let front = Side {
+                text: front,
+                audio: AudioSource::new(
+                    into_store(audio.local_file())?,
+                    audio.url().map(String::from),
+                ),
+                image: ImageSource::new(into_store(front_image.local_file())?),
+            };
+            let back = Side {
+                text: back,
+                image: ImageSource::new(into_store(back_image.local_file())?),
+                ..Default::default()
+            };
+            let card = Card {
+                reversible,
+                ..Card::new(front, back, meta)
+            };
+            let card = card.save_new_card(&category, cards.get());
+            AddedRow {
+                id: *card.id(),
+                path: card.as_path(),
+            }
+            .print(format);
+        }
+        Command::Due { category, pending } => {
+            let cache = cards.get();
+            // One row per card, even if several of its items are up.
+            let mut ids = BTreeSet::new();
+            for category in category.get_following_categories() {
+                let items = if pending {
+                    category.get_pending_cards(cache)
+                } else {
+                    category.get_review_cards(cache)
+                };
+                ids.extend(items.into_iter().map(|(id, _)| id));
+            }
+            for id in ids {
+                CardRow::new(&cache.get_ref(&id)).print(format);
+            }
+        }
+        Command::Stats => Stats::new(cards.get()).print(format),
+        Command::Optimize { dry_run } => {
+            let histories: Vec<Vec<Review>> = SavedCard::load_all_cards()
+                .iter()
+                .flat_map(|card| {
+                    card.review_histories()
+                        .into_iter()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+            let fit =
+                fsrs::optimize(&histories).ok_or("not enough reviews to fit the fsrs scheduler")?;
+
+            let histories = || histories.iter().map(Vec::as_slice);
+            let (classic_loss, reviews) = log_loss(&Classic, histories());
+            let (default_loss, _) = log_loss(&Fsrs::default(), histories());
+
+            ModelRow {
+                model: "classic",
+                log_loss: classic_loss,
+                reviews,
+                weights: vec![],
+            }
+            .print(format);
+            ModelRow {
+                model: "fsrs-default",
+                log_loss: default_loss,
+                reviews,
+                weights: fsrs::DEFAULT_WEIGHTS.to_vec(),
+            }
+            .print(format);
+            ModelRow {
+                model: "fsrs-fitted",
+                log_loss: fit.log_loss,
+                reviews: fit.reviews,
+                weights: fit.weights.to_vec(),
+            }
+            .print(format);
+
+            if !dry_run {
+                let mut config = Config::load().map_err(|e| e.to_string())?;
+                config.fsrs_weights = fit.weights.to_vec();
+                config.save().map_err(|e| e.to_string())?;
+            }
+        }
+        Command::Import {
+            path,
+            kind,
+            dry_run,
+        } => {
+            let pending = match kind {
+                ImportKind::Lines(category) => {
+                    let import = CsvImport::read_lines(&path, &category)
+                        .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+                    eprint!("{}", import.summary());
+                    import.cards
+                }
+                ImportKind::Csv(options) => {
+                    let import = CsvImport::read(&path, &options)
+                        .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+                    eprint!("{}", import.summary());
+                    import.cards
+                }
+                #[cfg(feature = "anki")]
+                ImportKind::Anki(category) => {
+                    let import = AnkiImport::read(&path, &category).map_err(|e| e.to_string())?;
+                    eprint!("{}", import.summary());
+                    if !dry_run {
+                        for card in import.save(cards.get()).map_err(|e| e.to_string())? {
+                            ImportedRow::from(&card).print(format);
+                        }
+                        return Ok(());
+                    }
+                    import.cards
+                }
+                #[cfg(not(feature = "anki"))]
+                ImportKind::Anki(_) => return Err("built without anki support".to_string()),
+            };
+
+            for (category, card) in pending {
+                if dry_run {
+                    ImportedRow {
+                        id: card.meta.id,
+                        category: category.print_full(),
+                        front: card.front.text.clone(),
+                    }
+                    .print(format);
+                } else {
+                    ImportedRow::from(&card.save_new_card(&category, cards.get())).print(format);
+                }
+            }
+        }
+        Command::Export {
+            category,
+            export_format: Some(export_format),
+            output,
+        } => match output {
+            Some(output) => {
+                let file = std::fs::File::create(&output)
+                    .map_err(|e| format!("failed to create {}: {e}", output.display()))?;
+                let mut writer = std::io::BufWriter::new(file);
+                let count = export_category(&mut writer, export_format, &category)
+                    .and_then(|count| writer.flush().map(|_| count))
+                    .map_err(|e| format!("failed to export: {e}"))?;
+                eprintln!("exported {count} cards to {}", output.display());
+            }
+            None => {
+                export_category(&mut std::io::stdout().lock(), export_format, &category)
+                    .map_err(|e| format!("failed to export: {e}"))?;
+            }
+        },
+        Command::Export { category, .. } => {
+            let mut cards: Vec<_> = SavedCard::get_cards_from_category_recursively(&category)
+                .into_iter()
+                .collect();
+            cards.sort_by(|a, b| (a.category(), a.id()).cmp(&(b.category(), b.id())));
+            for card in cards {
+                ExportRow {
+                    id: *card.id(),
+                    category: card.category().print_full(),
+                    front: card.front_text().to_owned(),
+                    back: card.back_text().to_owned(),
+                    tags: card.card_as_ref().meta.tags.clone(),
+                }
+                .print(format);
+            }
+        }
+        Command::Health => {
+            for edge in cards.get().health_check() {
+                HealthRow {
+                    card: edge.card,
+                    missing: edge.missing,
+                    kind: if edge.is_dependency {
+                        "dependency"
+                    } else {
+                        "dependent"
+                    },
+                }
+                .print(format);
+            }
+        }
+        Command::Grade { id, grade } => {
+            let mut card = cards
+                .get()
+                .try_get_owned(&id)
+                .ok_or_else(|| format!("no card with id {id}"))?;
+            let item = card.next_item();
+            card.new_review(item, grade, Duration::default(), Duration::default());
+            GradedRow {
+                id,
+                recall_rate: card.recall_rate(),
+                stability_days: card.stability().map(|s| s.as_secs_f32() / 86400.),
+            }
+            .print(format);
+        }
+        Command::Trash => {
+            for trashed in trash::list() {
+                TrashRow {
+                    id: trashed.card.meta.id,
+                    category: Category::from_joined(&trashed.info.category).print_full(),
+                    deleted_at: trashed.info.deleted_at.as_secs(),
+                    front: trashed.card.front.text,
+                }
+                .print(format);
+            }
+        }
+        Command::Restore { id } => {
+            let card = trash::restore(&id, cards.get())
+                .ok_or_else(|| format!("no card with id {id} in the trash"))?;
+            ImportedRow::from(&card).print(format);
+        }
+        // Works on the files themselves, so the cards aren't loaded for it.
+        Command::Migrate { dry_run } => migrate_cards(dry_run, format)?,
+        Command::Gc { dry_run } => {
+            let cache = cards.get();
+            // A card that didn't load can't say which media it uses.
+            if !cache.broken().is_empty() {
+                return Err("fix the broken cards first, their media would look unused".into());
+            }
+            let references = media::all_references(cache);
+            let removed = MediaStore::open()
+                .collect_garbage(&references, dry_run)
+                .map_err(|e| format!("couldn't clean up the media folder: {e}"))?;
+            for file in &removed {
+                GcRow { file: file.clone() }.print(format);
+            }
+            if !removed.is_empty() && !dry_run {
+                git::note(git::Change::Other(format!(
+                    "gc: removed {} unused media files",
+                    removed.len()
+                )));
+            }
+        }
+        Command::Sync => {
+            let report = git::sync_share().map_err(|e| format!("sync failed: {e}"))?;
+            SyncRow {
+                committed: report.committed,
+                pulled: report.pulled,
+                pushed: report.pushed,
+                merged: report.merged.len(),
+                sent: report.sent,
+                received: report.received,
+            }
+            .print(format);
+            if !report.is_clean() {
+                return Err(report.to_string());
+            }
+            if !report.kept_newest.is_empty() {
+                eprintln!("{report}");
+            }
+        }
+        Command::Help => println!("{USAGE}"),
+    }
+    Ok(())
+}
+
+/// Media flags can point at a file anywhere, which is copied into the media folder first.
+fn into_store(name: Option<&str>) -> Result<Option<String>, String> {
+    let Some(name) = name else {
+        return Ok(None);
+    };
+    let path = Path::new(name);
+    let in_store =
+        path.file_name() == Some(path.as_os_str()) && get_media_path().join(name).exists();
+    if in_store || !path.is_file() {
+        return Ok(Some(name.to_string()));
+    }
+    MediaStore::open()
+        .add(path)
+        .map(Some)
+        .map_err(|e| format!("couldn't copy {name} into the media folder: {e}"))
+}
+
+fn migrate_cards(dry_run: bool, format: Format) -> Result<(), String> {
+    let cards_path = get_cards_path();
+    let mut migrated = 0;
+    let mut failed = 0;
+    for (path, result) in migrate::migrate_all(dry_run) {
+        match result {
+            Ok(done) => {
+                migrated += 1;
+                MigratedRow {
+                    path: path
+                        .strip_prefix(&cards_path)
+                        .unwrap_or(&path)
+                        .display()
+                        .to_string(),
+                    from: done.from,
+                    to: done.to,
+                    changes: done.changes,
+                }
+                .print(format);
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("{e}");
+            }
+        }
+    }
+
+    if migrated > 0 && !dry_run {
+        git::note(git::Change::Other(format!(
+            "migrate: {migrated} cards to schema {}",
+            migrate::SCHEMA_VERSION
+        )));
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{failed} cards couldn't be migrated")),
+    }
+}
+
+/// The cards, loaded the first time a command needs them.
+#[derive(Default)]
+struct Cards(Option<CardCache>);
+
+impl Cards {
+    fn get(&mut self) -> &mut CardCache {
+        self.0.get_or_insert_with(|| {
+            let cache = CardCache::new();
+            for broken in cache.broken() {
+                eprintln!("skipping broken card {broken}");
+            }
+            cache
+        })
+    }
+}
+
+/// Runs a headless command, returns the exit code.
+pub fn run(args: Vec<String>) -> i32 {
+    let (command, format) = match parse(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return 2;
+        }
+    };
+
+    if command == Command::Help {
+        println!("{USAGE}");
+        return 0;
+    }
+
+    match execute(command, format, &mut Cards::default()) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{e}");
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_add() {
+        let (command, format) = parse(args(
+            "add /maths/calculus q a --tag foo --tag bar --json --audio-url x --reversible --back-image cat.png",
+        ))
+        .unwrap();
+        assert_eq!(format, Format::Json);
+        assert_eq!(
+            command,
+            Command::Add {
+                category: Category(vec!["maths".into(), "calculus".into()]),
+                front: "q".into(),
+                back: "a".into(),
+                tags: vec!["foo".into(), "bar".into()],
+                unfinished: false,
+                reversible: true,
+                audio: AudioSource::new(None, Some("x".into())),
+                front_image: ImageSource::default(),
+                back_image: ImageSource::new(Some("cat.png".into())),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_grade() {
+        let (command, _) = parse(args("grade 8bc35fe2-f02b-4633-8f1b-306eb4e09cd2 late")).unwrap();
+        assert_eq!(
+            command,
+            Command::Grade {
+                id: "8bc35fe2-f02b-4633-8f1b-306eb4e09cd2".parse().unwrap(),
+                grade: Grade::Late,
+            }
+        );
+        assert!(parse(args("grade 8bc35fe2-f02b-4633-8f1b-306eb4e09cd2 5")).is_err());
+    }
+
+    #[test]
+    fn test_parse_restore() {
+        let (command, _) = parse(args("restore 8bc35fe2-f02b-4633-8f1b-306eb4e09cd2")).unwrap();
+        assert_eq!(
+            command,
+            Command::Restore {
+                id: "8bc35fe2-f02b-4633-8f1b-306eb4e09cd2".parse().unwrap(),
+            }
+        );
+        assert!(parse(args("restore")).is_err());
+        assert_eq!(parse(args("trash")).unwrap().0, Command::Trash);
+    }
+
+    #[test]
+    fn test_parse_migrate() {
+        let (command, _) = parse(args("migrate --dry-run")).unwrap();
+        assert_eq!(command, Command::Migrate { dry_run: true });
+    }
+
+    #[test]
+    fn test_migrate_doesnt_load_cards() {
+        let _share = crate::paths::TempShare::new();
+        let mut cards = Cards::default();
+        execute(Command::Migrate { dry_run: true }, Format::Tsv, &mut cards).unwrap();
+        assert!(cards.0.is_none());
+    }
+
+    #[test]
+    fn test_parse_gc() {
+        let (command, _) = parse(args("gc --dry-run")).unwrap();
+        assert_eq!(command, Command::Gc { dry_run: true });
+        assert!(parse(args("gc extra")).is_err());
+        assert_eq!(parse(args("sync")).unwrap().0, Command::Sync);
+        assert!(parse(args("sync now")).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(args("add maths")).is_err());
+        assert!(parse(args("stats extra")).is_err());
+        assert!(parse(args("due --wat")).is_err());
+        assert!(parse(args("frobnicate")).is_err());
+        assert_eq!(parse(vec![]).unwrap().0, Command::Help);
+    }
+
+    #[test]
+    fn test_parse_import() {
+        let (command, _) = parse(args("import cards.txt --delimiter tab --dry-run")).unwrap();
+        let Command::Import {
+            path,
+            kind: ImportKind::Csv(options),
+            dry_run,
+        } = command
+        else {
+            panic!()
+        };
+        assert_eq!(path, PathBuf::from("cards.txt"));
+        assert_eq!(options.delimiter, '\t');
+        assert!(dry_run);
+
+        let (command, _) = parse(args("import cards.txt")).unwrap();
+        assert!(matches!(
+            command,
+            Command::Import {
+                kind: ImportKind::Lines(_),
+                ..
+            }
+        ));
+
+        let (command, _) = parse(args("import deck.apkg --category anki/stuff")).unwrap();
+        let Command::Import {
+            kind: ImportKind::Anki(category),
+            ..
+        } = command
+        else {
+            panic!()
+        };
+        assert_eq!(category, Category::from_joined("anki/stuff"));
+    }
+
+    #[test]
+    fn test_parse_export() {
+        let (command, _) = parse(args("export maths --output out.csv")).unwrap();
+        assert_eq!(
+            command,
+            Command::Export {
+                category: Category::from_joined("maths"),
+                export_format: Some(ExportFormat::Csv),
+                output: Some(PathBuf::from("out.csv")),
+            }
+        );
+
+        let (command, _) = parse(args("export --format anki")).unwrap();
+        assert_eq!(
+            command,
+            Command::Export {
+                category: Category::root(),
+                export_format: Some(ExportFormat::AnkiTsv),
+                output: None,
+            }
+        );
+
+        assert!(parse(args("export --output out.xyz")).is_err());
+        assert!(parse(args("export --format xml")).is_err());
+    }
+
+    #[test]
+    fn test_parse_optimize() {
+        let (command, _) = parse(args("optimize --dry-run")).unwrap();
+        assert_eq!(command, Command::Optimize { dry_run: true });
+        assert!(parse(args("optimize maths")).is_err());
+    }
+
+    #[test]
+    fn test_tsv_field() {
+        assert_eq!(tsv_field("a\tb\nc\\"), "a\\tb\\nc\\\\");
+    }
+}
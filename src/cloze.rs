@@ -0,0 +1,151 @@
+//! Cloze deletions, like `The capital of {{c1::France}} is {{c2::Paris::a city}}`.
+//!
+//! A card whose front has deletions is a cloze card. Every number is reviewed on its own with
+//! its own history, deletions sharing a number are hidden together. The part after a second
+//! `::` is a hint shown in place of the answer.
+
+use std::collections::BTreeSet;
+
+#[derive(Debug, PartialEq)]
+enum Part<'a> {
+    Text(&'a str),
+    Deletion {
+        number: u32,
+        answer: &'a str,
+        hint: Option<&'a str>,
+    },
+}
+
+/// Parses `c<number>::answer[::hint]}}` right after a `{{`, returning the deletion and the rest.
+fn deletion(s: &str) -> Option<(Part<'_>, &str)> {
+    let s = s.strip_prefix('c')?;
+    let digits = s.find(|c: char| !c.is_ascii_digit())?;
+    let number = s[..digits].parse().ok()?;
+    let s = s[digits..].strip_prefix("::")?;
+    let end = s.find("}}")?;
+    let (answer, hint) = match s[..end].split_once("::") {
+        Some((answer, hint)) => (answer, Some(hint)),
+        None => (&s[..end], None),
+    };
+    let part = Part::Deletion {
+        number,
+        answer,
+        hint,
+    };
+    Some((part, &s[end + 2..]))
+}
+
+/// Anything that doesn't look like a deletion is kept as text.
+fn parse(text: &str) -> Vec<Part<'_>> {
+    let mut parts = vec![];
+    let mut rest = text;
+    let mut searched = 0;
+    while let Some(start) = rest[searched..].find("{{").map(|i| i + searched) {
+        match deletion(&rest[start + 2..]) {
+            Some((part, after)) => {
+                if start > 0 {
+                    parts.push(Part::Text(&rest[..start]));
+                }
+                parts.push(part);
+                rest = after;
+                searched = 0;
+            }
+            None => searched = start + 2,
+        }
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest));
+    }
+    parts
+}
+
+/// The deletion numbers in the text, empty if it isn't a cloze.
+pub fn numbers(text: &str) -> BTreeSet<u32> {
+    parse(text)
+        .into_iter()
+        .filter_map(|part| match part {
+            Part::Deletion { number, .. } => Some(number),
+            Part::Text(_) => None,
+        })
+        .collect()
+}
+
+/// Where the reviews of a deletion are stored in the card.
+pub fn key(number: u32) -> String {
+    format!("c{number}")
+}
+
+fn render(text: &str, number: u32, hidden: bool) -> String {
+    let mut rendered = String::new();
+    for part in parse(text) {
+        match part {
+            Part::Text(text) => rendered.push_str(text),
+            Part::Deletion {
+                number: n, answer, ..
+            } if n != number => rendered.push_str(answer),
+            Part::Deletion { answer, hint, .. } => {
+                let shown = match (hidden, hint) {
+                    (false, _) => answer,
+                    (true, Some(hint)) => hint,
+                    (true, None) => "...",
+                };
+                rendered.push_str(&format!("[{shown}]"));
+            }
+        }
+    }
+    rendered
+}
+
+/// The question for one deletion, the other ones are shown as plain text.
+pub fn mask(text: &str, number: u32) -> String {
+    render(text, number, true)
+}
+
+/// The answer for one deletion, which stays in brackets so it stands out.
+pub fn reveal(text: &str, number: u32) -> String {
+    render(text, number, false)
+}
+
+/// What goes in the deletions with the number, for checking a typed answer.
+pub fn answers(text: &str, number: u32) -> String {
+    let answers: Vec<&str> = parse(text)
+        .into_iter()
+        .filter_map(|part| match part {
+            Part::Deletion {
+                number: n, answer, ..
+            } if n == number => Some(answer),
+            _ => None,
+        })
+        .collect();
+    answers.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "{{c1::Paris}} is the capital of {{c2::France::a country}}, {{c1::it}} has {c3} {{c4 rivers";
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(numbers(TEXT), BTreeSet::from([1, 2]));
+        assert!(numbers("no deletions {{here}}").is_empty());
+    }
+
+    #[test]
+    fn test_mask_and_reveal() {
+        assert_eq!(
+            mask(TEXT, 1),
+            "[...] is the capital of France, [...] has {c3} {{c4 rivers"
+        );
+        assert_eq!(
+            mask(TEXT, 2),
+            "Paris is the capital of [a country], it has {c3} {{c4 rivers"
+        );
+        assert_eq!(
+            reveal(TEXT, 2),
+            "Paris is the capital of [France], it has {c3} {{c4 rivers"
+        );
+        assert_eq!(answers(TEXT, 1), "Paris it");
+    }
+}
@@ -1,15 +1,16 @@
-use std::collections::HashSet;
 use std::fmt::Display;
 
-use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::time::{Duration, UNIX_EPOCH};
 
-use crate::card::{Card, CardCache, SavedCard};
+use serde::{Deserialize, Serialize};
+
+use crate::card::{CardCache, SavedCard};
+use crate::categories::Category;
 use crate::paths::get_cards_path;
 use crate::Id;
-use std::io::{self, BufRead, ErrorKind};
+use std::io;
 
 use std::time::SystemTime;
 
@@ -19,24 +20,91 @@ pub fn duration_to_days(dur: &Duration) -> f32 {
 
 type Filter = (String, Box<dyn FnMut(&SavedCard) -> bool>);
 
+/// One of the filters, in a form that can be saved in the config.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FilterKind {
+    Pending,
+    Finished,
+    Suspended,
+    Tag { tag: String },
+    MaxStrength { days: f32 },
+    MaxStability { days: f32 },
+    MaxRecall { recall: f32 },
+}
+
+/// A filter saved by name in the config, cards have to match everything in `include` and
+/// nothing in `exclude`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SavedFilter {
+    #[serde(default)]
+    pub include: Vec<FilterKind>,
+    #[serde(default)]
+    pub exclude: Vec<FilterKind>,
+}
+
+impl SavedFilter {
+    pub fn filters(&self) -> Filters {
+        let mut filters = Filters::default();
+        for kind in &self.include {
+            let filter = filters.filter(kind);
+            filters.insert_positive(filter);
+        }
+        for kind in &self.exclude {
+            let filter = filters.filter(kind);
+            filters.insert_negative(filter);
+        }
+        filters
+    }
+}
+
+impl Display for FilterKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Filters::default().filter(self).0)
+    }
+}
+
+impl SavedFilter {
+    /// What it lets through, excluded ones prefixed with "not".
+    pub fn labels(&self) -> Vec<String> {
+        let include = self.include.iter().map(|kind| kind.to_string());
+        let exclude = self.exclude.iter().map(|kind| format!("not {kind}"));
+        include.chain(exclude).collect()
+    }
+}
+
+impl Display for SavedFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let all = self.labels();
+        match all.is_empty() {
+            true => write!(f, "every card"),
+            false => write!(f, "{}", all.join(", ")),
+        }
+    }
+}
+
 #[derive(Default)]
-struct Filters {
+pub struct Filters {
     positive: Vec<Filter>,
     negative: Vec<Filter>,
 }
 
 impl Filters {
-    fn run<'a>(&mut self, cards: HashSet<&'a Id>, cache: &mut CardCache) -> HashSet<&'a Id> {
-        let mut accepted_cards = HashSet::new();
-        for card_id in cards {
-            let card = cache.get_ref(card_id);
-            if self.positive.iter_mut().all(|filter| filter.1(&card))
-                && !self.negative.iter_mut().any(|filter| filter.1(&card))
-            {
-                accepted_cards.insert(card_id);
-            }
-        }
-        accepted_cards
+    /// The cards that pass, in the order they came in.
+    pub fn run(&mut self, cards: Vec<Id>, cache: &mut CardCache) -> Vec<Id> {
+        cards
+            .into_iter()
+            .filter(|card_id| {
+                cache
+                    .try_get_ref(card_id)
+                    .is_some_and(|card| self.matches(&card))
+            })
+            .collect()
+    }
+
+    pub fn matches(&mut self, card: &SavedCard) -> bool {
+        self.positive.iter_mut().all(|filter| filter.1(card))
+            && !self.negative.iter_mut().any(|filter| filter.1(card))
     }
 
     fn insert_positive(&mut self, filter: Filter) {
@@ -46,6 +114,19 @@ impl Filters {
         self.negative.push(filter);
     }
 
+    fn filter(&self, kind: &FilterKind) -> Filter {
+        let days = |days: f32| Duration::from_secs_f32(days.max(0.) * 86400.);
+        match kind {
+            FilterKind::Pending => self.is_pending(),
+            FilterKind::Finished => self.is_finished(),
+            FilterKind::Suspended => self.is_suspended(),
+            FilterKind::Tag { tag } => self.has_tag(tag.clone()),
+            FilterKind::MaxStrength { days: max } => self.max_strength(days(*max)),
+            FilterKind::MaxStability { days: max } => self.max_stability(days(*max)),
+            FilterKind::MaxRecall { recall } => self.max_recall(*recall),
+        }
+    }
+
     fn is_pending(&self) -> Filter {
         let closure = move |card: &SavedCard| -> bool { card.is_pending() };
 
@@ -73,7 +154,7 @@ impl Filters {
     fn max_strength(&self, max_strength: Duration) -> Filter {
         let closure = move |card: &SavedCard| -> bool {
             if let Some(strength_rate) = card.strength() {
-                strength_rate > max_strength
+                strength_rate < max_strength
             } else {
                 false
             }
@@ -85,7 +166,7 @@ impl Filters {
     fn max_stability(&self, max_stability: Duration) -> Filter {
         let closure = move |card: &SavedCard| -> bool {
             if let Some(stability_rate) = card.stability() {
-                stability_rate > max_stability
+                stability_rate < max_stability
             } else {
                 false
             }
@@ -97,7 +178,7 @@ impl Filters {
     fn max_recall(&self, max_recall: f32) -> Filter {
         let closure = move |card: &SavedCard| -> bool {
             if let Some(recall_rate) = card.recall_rate() {
-                recall_rate > max_recall
+                recall_rate < max_recall
             } else {
                 false
             }
@@ -107,6 +188,40 @@ impl Filters {
     }
 }
 
+/// Where a review or the card list gets its cards from, a category or a saved filter.
+#[derive(Clone, Debug)]
+pub enum CardSource {
+    Category(Category),
+    Filter { name: String, filter: SavedFilter },
+}
+
+impl CardSource {
+    /// The category to collect the cards from, filters go through all of them.
+    pub fn category(&self) -> Category {
+        match self {
+            Self::Category(category) => category.clone(),
+            Self::Filter { .. } => Category::root(),
+        }
+    }
+
+    /// Leaves out the cards the filter doesn't let through, if it's a filter.
+    pub fn narrow(&self, cards: Vec<Id>, cache: &mut CardCache) -> Vec<Id> {
+        match self {
+            Self::Category(_) => cards,
+            Self::Filter { filter, .. } => filter.filters().run(cards, cache),
+        }
+    }
+}
+
+impl Display for CardSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Category(category) => write!(f, "{}", category.print_full()),
+            Self::Filter { name, .. } => write!(f, "filter: {name}"),
+        }
+    }
+}
+
 pub fn current_time() -> Duration {
     system_time_as_unix_time(SystemTime::now()) // + Duration::from_secs(86400)
 }
@@ -159,17 +274,10 @@ pub fn open_file_with_vim(path: &Path) -> io::Result<()> {
     if status.success() {
         Ok(())
     } else {
-        Err(io::Error::new(
-            ErrorKind::Other,
-            "Failed to open file with vim",
-        ))
+        Err(io::Error::other("Failed to open file with vim"))
     }
 }
 
-pub trait MenuItem: Display {
-    fn action(&self) -> Box<dyn FnMut() -> bool>;
-}
-
 /// Randomizing a vector.
 /// Not importing rand cause im trying to keep dependency-count low.
 pub fn randvec<T>(mut v: Vec<T>) -> Vec<T> {
@@ -209,16 +317,6 @@ fn open_folder_in_explorer(path: &Path) -> std::io::Result<()> {
     Ok(())
 }
 
-/// will generate a number between 0 and 100 and check that it's below the given percentage.
-/// so if you input '10', then ofc, 10% of the times it will return true as the number will be below 10
-pub fn within_percentage(percentage: u32) -> bool {
-    rand_int(100) < percentage
-}
-
-pub fn rand_int(max: u32) -> u32 {
-    current_time().as_millis() as u32 % max
-}
-
 pub fn get_last_modified(path: PathBuf) -> Duration {
     let metadata = std::fs::metadata(path).unwrap();
     let modified_time = metadata.modified().unwrap();
@@ -233,6 +331,82 @@ pub fn get_last_modified(path: PathBuf) -> Duration {
 mod tests {
     use super::*;
 
+    use crate::card::{Card, Grade, Review};
+
+    #[test]
+    fn test_saved_filter_toml() {
+        let config = r#"
+            [[weak.include]]
+            kind = "max_recall"
+            recall = 0.8
+
+            [[weak.exclude]]
+            kind = "tag"
+            tag = "easy"
+
+            [[weak.exclude]]
+            kind = "suspended"
+        "#;
+        let filters: std::collections::BTreeMap<String, SavedFilter> =
+            toml::from_str(config).unwrap();
+        let weak = &filters["weak"];
+        assert_eq!(weak.include, vec![FilterKind::MaxRecall { recall: 0.8 }]);
+        assert_eq!(
+            weak.to_string(),
+            "recall < 0.8, not includes tag:  easy, not is suspended"
+        );
+        // Saved along with the rest of the config.
+        let config = crate::config::Config {
+            filters: filters.clone(),
+            ..Default::default()
+        };
+        let written = toml::to_string(&config).unwrap();
+        let read: crate::config::Config = toml::from_str(&written).unwrap();
+        assert_eq!(read.filters, filters);
+    }
+
+    #[test]
+    fn test_filters_match() {
+        let _share = crate::paths::TempShare::new();
+        let mut cache = CardCache::new();
+        let mut save = |front: &str, reviewed: bool| {
+            let mut card = Card::new_simple(front.into(), "back".into());
+            card.meta.tags.insert("verbs".into());
+            if reviewed {
+                card.history.add_review(Review {
+                    timestamp: current_time(),
+                    grade: Grade::Some,
+                    ..Default::default()
+                });
+            }
+            card.save_new_card(&Category::root(), &mut cache)
+        };
+        let new = save("new", false);
+        let reviewed = save("reviewed", true);
+
+        let matches = |filter: SavedFilter| {
+            (
+                filter.filters().matches(&new),
+                filter.filters().matches(&reviewed),
+            )
+        };
+        let tagged = SavedFilter {
+            include: vec![FilterKind::Tag {
+                tag: "verbs".into(),
+            }],
+            exclude: vec![FilterKind::Pending],
+        };
+        assert_eq!(matches(tagged), (false, true));
+        // Just reviewed, so recall is about as high as it gets, and new cards have none.
+        let weak = |recall| SavedFilter {
+            include: vec![FilterKind::MaxRecall { recall }],
+            exclude: vec![],
+        };
+        assert_eq!(matches(weak(0.5)), (false, false));
+        assert_eq!(matches(weak(1.01)), (false, true));
+        assert_eq!(matches(SavedFilter::default()), (true, true));
+    }
+
     #[test]
     fn foo() {
         let input_vec = vec![1, 2, 3, 4, 5, 6, 7, 8, 9];
@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     fs::File,
     io::{Read, Write},
     path::PathBuf,
@@ -6,7 +7,10 @@ use std::{
 
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::{common::open_file_with_vim, paths::get_share_path};
+use crate::{
+    common::{open_file_with_vim, SavedFilter},
+    paths::get_share_path,
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 
@@ -19,11 +23,58 @@ pub struct Config {
         deserialize_with = "empty_string_to_option"
     )]
     pub git_remote: Option<String>,
+    /// Where to sync to instead of `git_remote`: a git url, the path of a (bare) repo, or a
+    /// plain folder like a USB stick, which is synced file by file.
+    #[serde(
+        default,
+        serialize_with = "option_string_to_empty_string",
+        deserialize_with = "empty_string_to_option"
+    )]
+    pub sync_target: Option<String>,
     #[serde(
         serialize_with = "option_string_to_empty_string",
         deserialize_with = "empty_string_to_option"
     )]
     pub gpt_key: Option<String>,
+    /// Column mapping for csv imports, like `front=1,back=2,tags=3`. Empty means guess from the header.
+    #[serde(
+        default,
+        serialize_with = "option_string_to_empty_string",
+        deserialize_with = "empty_string_to_option"
+    )]
+    pub import_mapping: Option<String>,
+    /// Longest time in seconds a review can be recorded as taking, so walking away from the
+    /// screen doesn't count as a slow answer.
+    #[serde(default = "default_review_time_cap")]
+    pub review_time_cap: u64,
+    /// Deleted cards are purged from the trash after this many days, 0 keeps them forever.
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32,
+    /// Weights for the fsrs scheduler, empty means the defaults. Written by `spekinew optimize`.
+    #[serde(default)]
+    pub fsrs_weights: Vec<f32>,
+    /// Scheduler to use per category, see the scheduler module. Unlisted categories use the classic one.
+    #[serde(default)]
+    pub schedulers: BTreeMap<String, String>,
+    /// Where answers are typed in and checked instead of graded by hand: categories like
+    /// `languages/spanish`, which include their subcategories, or tags like `#spelling`.
+    #[serde(default)]
+    pub typed_answers: Vec<String>,
+    /// How many answers to pick from in multiple choice reviews, the right one included.
+    #[serde(default = "default_choice_count")]
+    pub choice_count: usize,
+    /// Command that plays card audio, like `mpv --no-video`, the file or url is added at the
+    /// end. Empty means playing it with the built in player, if speki was built with one.
+    #[serde(
+        default,
+        serialize_with = "option_string_to_empty_string",
+        deserialize_with = "empty_string_to_option"
+    )]
+    pub audio_player: Option<String>,
+    /// Filters saved by name from the filters screen, usable instead of a category when
+    /// reviewing or viewing cards.
+    #[serde(default)]
+    pub filters: BTreeMap<String, SavedFilter>,
 }
 
 impl Config {
@@ -60,6 +111,8 @@ impl Config {
     pub fn load() -> std::io::Result<Config> {
         let mut file = match File::open(Self::config_path()) {
             Ok(file) => file,
+            // Tests share the checked in test_dir, so they don't leave a config in it.
+            Err(_) if cfg!(test) => return Ok(Self::default()),
             Err(_) => {
                 let _ =
                     std::fs::rename(Self::config_path(), get_share_path().join("invalid_config"));
@@ -82,11 +135,33 @@ impl Default for Config {
             show_images: true,
             download_media: true,
             git_remote: None,
+            sync_target: None,
             gpt_key: None,
+            import_mapping: None,
+            review_time_cap: default_review_time_cap(),
+            trash_retention_days: default_trash_retention_days(),
+            fsrs_weights: vec![],
+            schedulers: BTreeMap::new(),
+            typed_answers: vec![],
+            choice_count: default_choice_count(),
+            audio_player: None,
+            filters: BTreeMap::new(),
         }
     }
 }
 
+fn default_review_time_cap() -> u64 {
+    120
+}
+
+fn default_trash_retention_days() -> u32 {
+    30
+}
+
+fn default_choice_count() -> usize {
+    4
+}
+
 fn option_string_to_empty_string<S>(
     value: &Option<String>,
     serializer: S,
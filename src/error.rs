@@ -0,0 +1,102 @@
+//! The error type for loading cards, so one bad file doesn't take the whole app down.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The file isn't valid toml, or isn't shaped like a card.
+    Parse {
+        path: PathBuf,
+        message: String,
+        line: Option<usize>,
+    },
+    /// The path isn't in a category folder that exists.
+    NotInCategory(PathBuf),
+    /// Written by a newer speki, we don't know how to read it.
+    NewerSchema { path: PathBuf, version: u32 },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn io(path: &Path, source: std::io::Error) -> Self {
+        Self::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn parse(path: &Path, content: &str, e: toml::de::Error) -> Self {
+        let line = e.span().map(|span| {
+            content[..span.start.min(content.len())]
+                .matches('\n')
+                .count()
+                + 1
+        });
+        Self::Parse {
+            path: path.to_path_buf(),
+            message: e.message().to_string(),
+            line,
+        }
+    }
+
+    /// The file the error is about.
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Io { path, .. }
+            | Self::Parse { path, .. }
+            | Self::NotInCategory(path)
+            | Self::NewerSchema { path, .. } => path,
+        }
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Self::Parse { line, .. } => *line,
+            _ => None,
+        }
+    }
+
+    /// What went wrong, without the path.
+    pub fn reason(&self) -> String {
+        match self {
+            Self::Io { source, .. } => source.to_string(),
+            Self::Parse { message, .. } => message.clone(),
+            Self::NotInCategory(_) => "not in a category folder".to_string(),
+            Self::NewerSchema { version, .. } => format!(
+                "card schema {version} is newer than this speki knows ({}), update speki",
+                crate::migrate::SCHEMA_VERSION
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line() {
+            Some(line) => write!(f, "{}:{line}: {}", self.path().display(), self.reason()),
+            None => write!(f, "{}: {}", self.path().display(), self.reason()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_error_line() {
+        let content = "[front]\ntext = \"a\"\n[back\ntext = \"b\"\n";
+        let e = toml::from_str::<toml::Value>(content).unwrap_err();
+        let error = Error::parse(Path::new("card.toml"), content, e);
+        assert_eq!(error.line(), Some(3));
+        assert!(error.to_string().starts_with("card.toml:3: "));
+    }
+}
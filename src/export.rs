@@ -0,0 +1,222 @@
+//! Exporting every card under a category, for sharing decks with people who don't use speki.
+//!
+//! - csv: the same columns the csv importer understands, so it round-trips.
+//! - jsonl: one card per line with its full metadata and review history.
+//! - anki: a tab separated file with the header lines anki's text importer reads.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::card::{Card, SavedCard};
+use crate::categories::Category;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExportFormat {
+    Csv,
+    JsonLines,
+    AnkiTsv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "jsonl" | "json" => Ok(Self::JsonLines),
+            "anki" | "tsv" => Ok(Self::AnkiTsv),
+            other => Err(format!("unknown export format: {other}")),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonCard<'a> {
+    category: String,
+    #[serde(flatten)]
+    card: &'a Card,
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\t', " ")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+/// Anki side: html text, with the audio file as a sound tag.
+fn anki_field(side: &crate::card::Side) -> String {
+    let mut field = html_escape(&side.text);
+    if let Some(file) = side.audio.local_file() {
+        field.push_str(&format!("[sound:{file}]"));
+    }
+    field
+}
+
+fn write_card(
+    out: &mut impl Write,
+    format: ExportFormat,
+    category: &Category,
+    card: &Card,
+) -> io::Result<()> {
+    match format {
+        ExportFormat::Csv => {
+            let fields = [
+                card.front.text.clone(),
+                card.back.text.clone(),
+                card.meta.tags.iter().cloned().collect::<Vec<_>>().join(" "),
+                card.meta.priority.as_u32().to_string(),
+                category.joined(),
+                card.front
+                    .audio
+                    .local_file()
+                    .unwrap_or_default()
+                    .to_string(),
+                card.front.audio.url().unwrap_or_default().to_string(),
+            ];
+            let line: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+            writeln!(out, "{}", line.join(","))
+        }
+        ExportFormat::JsonLines => {
+            let json = JsonCard {
+                category: category.joined(),
+                card,
+            };
+            writeln!(out, "{}", serde_json::to_string(&json)?)
+        }
+        ExportFormat::AnkiTsv => {
+            let tags: Vec<String> = card
+                .meta
+                .tags
+                .iter()
+                .map(|tag| tag.replace(char::is_whitespace, "_"))
+                .collect();
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}",
+                anki_field(&card.front),
+                anki_field(&card.back),
+                tags.join(" "),
+                category.0.join("::").replace('\t', " ")
+            )
+        }
+    }
+}
+
+fn write_header(out: &mut impl Write, format: ExportFormat) -> io::Result<()> {
+    match format {
+        ExportFormat::Csv => writeln!(
+            out,
+            "front,back,tags,priority,category,audio_local,audio_url"
+        ),
+        ExportFormat::JsonLines => Ok(()),
+        ExportFormat::AnkiTsv => {
+            writeln!(out, "#separator:tab")?;
+            writeln!(out, "#html:true")?;
+            writeln!(out, "#tags column:3")?;
+            writeln!(out, "#deck column:4")
+        }
+    }
+}
+
+/// Writes the given cards, sorted by category and front text so exports diff nicely.
+pub fn export_cards(
+    out: &mut impl Write,
+    format: ExportFormat,
+    mut cards: Vec<(Category, Card)>,
+) -> io::Result<usize> {
+    cards.sort_by(|a, b| (&a.0, &a.1.front.text).cmp(&(&b.0, &b.1.front.text)));
+    write_header(out, format)?;
+    for (category, card) in &cards {
+        write_card(out, format, category, card)?;
+    }
+    Ok(cards.len())
+}
+
+/// Exports every card in the category and its subcategories, returns how many were written.
+pub fn export_category(
+    out: &mut impl Write,
+    format: ExportFormat,
+    category: &Category,
+) -> io::Result<usize> {
+    let cards = SavedCard::get_cards_from_category_recursively(category)
+        .into_iter()
+        .map(|card| (card.category().to_owned(), card.into_card()))
+        .collect();
+    export_cards(out, format, cards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{CsvImport, ImportOptions};
+    use std::path::Path;
+
+    fn cards() -> Vec<(Category, Card)> {
+        let mut card = Card::new_simple("what is \"x\",\nreally".into(), "a <b> & c".into());
+        card.meta.tags.insert("maths".into());
+        card.meta.tags.insert("hard one".into());
+        card.meta.priority = 80.into();
+        vec![
+            (Category::from_joined("maths/calculus"), card),
+            (
+                Category::root(),
+                Card::new_simple("plain".into(), "".into()),
+            ),
+        ]
+    }
+
+    fn export(format: ExportFormat) -> String {
+        let mut out = vec![];
+        export_cards(&mut out, format, cards()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_csv_roundtrip() {
+        let csv = export(ExportFormat::Csv);
+        let import = CsvImport::parse(&csv, &ImportOptions::for_path(Path::new("x.csv")));
+        assert!(import.errors.is_empty());
+
+        let originals = cards();
+        let (category, card) = &import.cards[1];
+        assert_eq!(category, &originals[0].0);
+        assert_eq!(card.front, originals[0].1.front);
+        assert_eq!(card.back, originals[0].1.back);
+        assert_eq!(card.meta.priority, originals[0].1.meta.priority);
+        assert!(card.meta.tags.contains("maths"));
+    }
+
+    #[test]
+    fn test_anki_tsv() {
+        let tsv = export(ExportFormat::AnkiTsv);
+        let lines: Vec<&str> = tsv.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[4], "plain\t\t\t");
+        assert_eq!(
+            lines[5],
+            "what is \"x\",<br>really\ta &lt;b&gt; &amp; c\thard_one maths\tmaths::calculus"
+        );
+    }
+
+    #[test]
+    fn test_jsonl() {
+        let jsonl = export(ExportFormat::JsonLines);
+        let first: serde_json::Value = serde_json::from_str(jsonl.lines().next().unwrap()).unwrap();
+        assert_eq!(first["category"], "");
+        assert_eq!(first["front"]["text"], "plain");
+        assert!(first["meta"]["id"].is_string());
+    }
+}
@@ -0,0 +1,325 @@
+//! Two-way sync with a plain folder, like a USB stick or a NAS mount, for when there's no git
+//! remote to push to.
+//!
+//! We remember what every file looked like after the last sync, so we can tell which side
+//! changed it. A file changed on one side is copied over (or deleted) on the other. When both
+//! sides changed a card, the two versions are merged with `Card::merge`, keeping the reviews
+//! from both and the other fields from whichever was modified last. Any other file that changed
+//! on both sides is settled by taking the newest one.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::card::Card;
+use crate::git::SyncReport;
+
+/// Local files that don't belong on other machines.
+const EXCLUDED: [&str; 5] = [
+    ".git",
+    "config.toml",
+    "index.toml",
+    "journal.toml",
+    "sync_state.toml",
+];
+
+#[derive(Serialize, Deserialize, Default)]
+struct SyncState {
+    /// The folder this state is for, syncing somewhere else starts over.
+    target: PathBuf,
+    /// Hash of every file right after the last sync, by its path relative to the share folder.
+    files: BTreeMap<String, String>,
+}
+
+impl SyncState {
+    fn load(path: &Path, target: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|content| toml::from_str::<Self>(&content).ok())
+            .filter(|state| state.target == target)
+            .unwrap_or_else(|| Self {
+                target: target.to_path_buf(),
+                files: BTreeMap::new(),
+            })
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, toml::to_string(self).unwrap())
+    }
+}
+
+/// FNV-1a, it only has to be stable between runs, which std's hasher doesn't promise.
+fn hash(content: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut BTreeSet<String>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let relative = path
+            .strip_prefix(root)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        if EXCLUDED.contains(&relative.as_str()) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else {
+            files.insert(relative);
+        }
+    }
+    Ok(())
+}
+
+fn list_files(root: &Path) -> io::Result<BTreeSet<String>> {
+    let mut files = BTreeSet::new();
+    collect_files(root, root, &mut files)?;
+    Ok(files)
+}
+
+struct Side {
+    content: Vec<u8>,
+    modified: SystemTime,
+}
+
+fn read_side(path: &Path) -> io::Result<Option<Side>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(Side {
+        content: std::fs::read(path)?,
+        modified: std::fs::metadata(path)?.modified()?,
+    }))
+}
+
+/// Writes the file with the given modification time, so both sides agree on how old it is.
+fn write_file(path: &Path, content: &[u8], modified: SystemTime) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)?;
+    std::fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(modified)
+}
+
+/// Both versions of a card merged, the newer one wins for everything but reviews and sets.
+fn merge_cards(local: &Side, remote: &Side) -> Option<Vec<u8>> {
+    let parse = |side: &Side| toml::from_str::<Card>(std::str::from_utf8(&side.content).ok()?).ok();
+    let (local_card, remote_card) = (parse(local)?, parse(remote)?);
+    let merged = if local.modified >= remote.modified {
+        local_card.merge(&remote_card, None)
+    } else {
+        remote_card.merge(&local_card, None)
+    };
+    toml::to_string(&merged).ok().map(String::into_bytes)
+}
+
+/// Syncs the share folder `local` with the folder `remote`. `state_path` is where we remember
+/// what the files looked like after this sync.
+pub fn sync_folder(local: &Path, remote: &Path, state_path: &Path) -> io::Result<SyncReport> {
+    if !remote.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} isn't there, is it mounted?", remote.display()),
+        ));
+    }
+
+    let mut state = SyncState::load(state_path, remote);
+    let mut report = SyncReport::default();
+
+    let mut paths = list_files(local)?;
+    paths.extend(list_files(remote)?);
+    paths.extend(state.files.keys().cloned());
+
+    for path in paths {
+        let (local_path, remote_path) = (local.join(&path), remote.join(&path));
+        let local_side = read_side(&local_path)?;
+        let remote_side = read_side(&remote_path)?;
+        let last = state.files.get(&path).map(String::as_str);
+        let local_hash = local_side.as_ref().map(|side| hash(&side.content));
+        let remote_hash = remote_side.as_ref().map(|side| hash(&side.content));
+
+        if local_hash == remote_hash {
+            match local_hash {
+                Some(hash) => state.files.insert(path, hash),
+                None => state.files.remove(&path),
+            };
+            continue;
+        }
+
+        let local_changed = local_hash.as_deref() != last;
+        let remote_changed = remote_hash.as_deref() != last;
+
+        let result = if !local_changed || !remote_changed {
+            // Only one side changed it, so that side wins.
+            let (from, to, count) = if remote_changed {
+                (remote_side, &local_path, &mut report.received)
+            } else {
+                (local_side, &remote_path, &mut report.sent)
+            };
+            *count += 1;
+            match from {
+                Some(side) => {
+                    write_file(to, &side.content, side.modified)?;
+                    Some(side.content)
+                }
+                None => {
+                    std::fs::remove_file(to)?;
+                    None
+                }
+            }
+        } else {
+            match (local_side, remote_side) {
+                (Some(local_side), Some(remote_side)) => {
+                    let is_card = path.starts_with("cards/") && path.ends_with(".toml");
+                    let merged = is_card
+                        .then(|| merge_cards(&local_side, &remote_side))
+                        .flatten();
+                    let content = match merged {
+                        Some(content) => {
+                            report.merged.push(PathBuf::from(&path));
+                            content
+                        }
+                        None => {
+                            report.kept_newest.push(PathBuf::from(&path));
+                            if local_side.modified >= remote_side.modified {
+                                local_side.content
+                            } else {
+                                remote_side.content
+                            }
+                        }
+                    };
+                    let now = SystemTime::now();
+                    write_file(&local_path, &content, now)?;
+                    write_file(&remote_path, &content, now)?;
+                    Some(content)
+                }
+                // Deleted on one side but changed on the other, the change wins.
+                (Some(side), None) => {
+                    write_file(&remote_path, &side.content, side.modified)?;
+                    report.sent += 1;
+                    Some(side.content)
+                }
+                (None, Some(side)) => {
+                    write_file(&local_path, &side.content, side.modified)?;
+                    report.received += 1;
+                    Some(side.content)
+                }
+                (None, None) => unreachable!("the hashes differ"),
+            }
+        };
+
+        match result {
+            Some(content) => state.files.insert(path, hash(&content)),
+            None => state.files.remove(&path),
+        };
+    }
+
+    state.save(state_path)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::Review;
+    use crate::paths::TempDir;
+    use std::time::Duration;
+
+    fn temp_dir(name: &str) -> TempDir {
+        TempDir::new(&format!("folder-{name}"))
+    }
+
+    fn write_card(dir: &Path, card: &Card) {
+        std::fs::create_dir_all(dir.join("cards")).unwrap();
+        std::fs::write(dir.join("cards/card.toml"), toml::to_string(card).unwrap()).unwrap();
+    }
+
+    fn read_card(dir: &Path) -> Card {
+        toml::from_str(&std::fs::read_to_string(dir.join("cards/card.toml")).unwrap()).unwrap()
+    }
+
+    fn review(secs: u64) -> Review {
+        Review {
+            timestamp: Duration::from_secs(secs),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_two_way_sync() {
+        let (laptop, desktop, stick) = (temp_dir("laptop"), temp_dir("desktop"), temp_dir("stick"));
+        let laptop_state = laptop.join("sync_state.toml");
+        let desktop_state = desktop.join("sync_state.toml");
+
+        std::fs::write(laptop.join("notes"), "from the laptop").unwrap();
+        std::fs::write(laptop.join("index.toml"), "local only").unwrap();
+        let report = sync_folder(&laptop, &stick, &laptop_state).unwrap();
+        assert_eq!(report.sent, 1);
+        assert!(!stick.join("index.toml").exists());
+
+        let report = sync_folder(&desktop, &stick, &desktop_state).unwrap();
+        assert_eq!(report.received, 1);
+        assert_eq!(
+            std::fs::read_to_string(desktop.join("notes")).unwrap(),
+            "from the laptop"
+        );
+
+        // A deletion travels too.
+        std::fs::remove_file(desktop.join("notes")).unwrap();
+        sync_folder(&desktop, &stick, &desktop_state).unwrap();
+        sync_folder(&laptop, &stick, &laptop_state).unwrap();
+        assert!(!laptop.join("notes").exists());
+
+        // Nothing changed, nothing to do.
+        let report = sync_folder(&laptop, &stick, &laptop_state).unwrap();
+        assert_eq!(report, SyncReport::default());
+    }
+
+    #[test]
+    fn test_cards_changed_on_both_sides_are_merged() {
+        let (laptop, desktop, stick) = (temp_dir("laptop"), temp_dir("desktop"), temp_dir("stick"));
+        let laptop_state = laptop.join("sync_state.toml");
+        let desktop_state = desktop.join("sync_state.toml");
+
+        let mut card = Card::new_simple("front".into(), "back".into());
+        write_card(&laptop, &card);
+        sync_folder(&laptop, &stick, &laptop_state).unwrap();
+        sync_folder(&desktop, &stick, &desktop_state).unwrap();
+
+        card.history.add_review(review(1));
+        write_card(&laptop, &card);
+        let mut other = read_card(&desktop);
+        other.history.add_review(review(2));
+        write_card(&desktop, &other);
+
+        sync_folder(&laptop, &stick, &laptop_state).unwrap();
+        let report = sync_folder(&desktop, &stick, &desktop_state).unwrap();
+        assert_eq!(report.merged, vec![PathBuf::from("cards/card.toml")]);
+
+        sync_folder(&laptop, &stick, &laptop_state).unwrap();
+        let merged = read_card(&laptop);
+        assert_eq!(merged.history, vec![review(1), review(2)].into());
+        assert_eq!(read_card(&desktop), merged);
+        assert_eq!(read_card(&stick), merged);
+    }
+
+    #[test]
+    fn test_missing_folder() {
+        let local = temp_dir("local");
+        let missing = local.join("not-mounted");
+        assert!(sync_folder(&local, &missing, &local.join("sync_state.toml")).is_err());
+    }
+}
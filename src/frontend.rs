@@ -3,23 +3,35 @@
 use std::collections::{BTreeSet, HashSet};
 use std::fmt::Display;
 use std::io::{stdout, Stdout};
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::card::{Card, CardCache, IsSuspended, Priority, ReviewType, Reviews, SavedCard};
+use crate::answer;
+use crate::audio;
+use crate::card::{
+    Card, CardCache, Grade, IsSuspended, Priority, ReviewItem, ReviewType, SavedCard, Stats,
+};
 use crate::categories::Category;
+use crate::choice;
 use crate::common::view_cards_in_explorer;
 use crate::common::{current_time, open_file_with_vim, randvec, truncate_string};
+use crate::common::{CardSource, FilterKind, SavedFilter};
 use crate::config::Config;
-use crate::git::git_save;
-use crate::paths::get_share_path;
+use crate::error::Error;
+use crate::git;
+use crate::images;
+use crate::import::{CsvImport, ImportOptions};
+use crate::journal;
+use crate::media::ImageSource;
+use crate::paths::{get_cards_path, get_import_csv, get_share_path};
+use crate::search::Query;
+use crate::trash::{self, TrashedCard};
 use crate::Id;
 
 use ascii_tree::write_tree;
 use ascii_tree::Tree::Node;
 
-fn card_options(stdout: &mut Stdout, card: Id, cache: &mut CardCache) {}
-
 fn view_all_cards(stdout: &mut Stdout, cache: &mut CardCache) {
     let cards = cache.all_ids();
     view_cards(stdout, cards, cache);
@@ -148,23 +160,15 @@ fn cards_as_string(cards: &Vec<SavedCard>) -> String {
 }
 
 fn print_stats(stdout: &mut Stdout, cache: &mut CardCache) {
-    let cards = SavedCard::load_all_cards();
-    let all_cards = cards.len();
-    let mut suspended = 0;
-    let mut finished = 0;
-    let mut pending = 0;
-    let mut strength = 0;
-    let mut reviews = 0;
-    let mut resolved = 0;
-
-    for card in cards {
-        pending += card.stability().is_none() as i32;
-        reviews += card.reviews().len();
-        finished += card.is_finished() as i32;
-        resolved += card.is_resolved(cache) as i32;
-        strength += (card.strength().unwrap_or_default().as_secs_f32() / 86400.).round() as i32;
-        suspended += card.is_suspended() as i32;
-    }
+    let Stats {
+        suspended,
+        finished,
+        pending,
+        reviews,
+        strength,
+        resolved,
+        total: all_cards,
+    } = Stats::new(cache);
 
     let output = format!("suspended: {suspended}\nfinished: {finished}\npending: {pending}\nreviews: {reviews}\nstrength: {strength}\nresolved: {resolved}\ntotal cards: {all_cards}");
     draw_message(stdout, output.as_str());
@@ -211,44 +215,15 @@ pub fn suspend_card(stdout: &mut Stdout, card: &Id, cache: &mut CardCache) {
 }
 
 pub fn health_check(stdout: &mut Stdout, cache: &mut CardCache) {
-    cache.refresh();
-    let all_cards = SavedCard::load_all_cards();
     move_upper_left(stdout);
 
-    for mut card in all_cards {
-        let _id = card.id().to_owned();
-        let dependencies = card.dependency_ids().to_owned();
-        let dependents = card.dependent_ids().to_owned();
-
-        for d in dependencies {
-            if !cache.exists(&d) {
-                println!("dependency removed!");
-                card.remove_dependency(&d, cache);
-            }
-        }
-
-        for d in dependents {
-            if !cache.exists(&d) {
-                println!("dependent removed!");
-                card.remove_dependent(&d, cache);
-            }
-        }
-
-        for dependency in card.dependency_ids() {
-            if !cache.exists(dependency) {
-                //      card._remove_dependency(&id, cache);
-            }
-
-            //let mut dependency = cache._get_owned(dependency);
-            //dependency.set_dependent(&id, cache);
-        }
-
-        for _dependent in card.dependent_ids() {
-            //let mut dependent = cache._get_owned(dependent);
-            //dependent.set_dependency(&id, cache);
+    for edge in cache.health_check() {
+        if edge.is_dependency {
+            println!("dependency removed!");
+        } else {
+            println!("dependent removed!");
         }
     }
-    cache.refresh();
 }
 
 pub fn clear_window(stdout: &mut Stdout) {
@@ -343,7 +318,7 @@ pub fn print_cool_graphs(stdout: &mut Stdout, cache: &mut CardCache) {
             let Some(mut time_passed)  = card.time_since_last_review() else {continue};
             time_passed += std::time::Duration::from_secs((86400 * days / 4).into());
             let Some(stability) = card.stability() else {continue};
-            if Reviews::calculate_recall_rate(&time_passed, &stability) < 0.9 {
+            if card.scheduler().recall_rate(time_passed, stability) < 0.9 {
                 count += 1;
             }
         }
@@ -418,17 +393,276 @@ fn import_stuff(cache: &mut CardCache) {
         return;
     }
     let category = Category::import_category();
-    let cards = Card::import_cards(import_path.as_path());
-
-    if let Some(cards) = cards {
-        for card in cards {
-            card.save_new_card(&category, cache);
+    // This runs before the screen is taken over, so the errors can go to stderr.
+    match CsvImport::read_lines(&import_path, &category) {
+        Ok(import) => {
+            for error in &import.errors {
+                eprintln!(
+                    "{}: line {}: {}",
+                    import_path.display(),
+                    error.line,
+                    error.message
+                );
+            }
+            import.save(cache);
         }
+        Err(e) => eprintln!("could not read {}: {e}", import_path.display()),
     }
     let to_path = get_share_path().join("imported.txt");
     std::fs::rename(import_path, to_path).unwrap();
 }
 
+fn import_csv(stdout: &mut Stdout, cache: &mut CardCache) {
+    let path = get_import_csv();
+    if !path.exists() {
+        draw_message(
+            stdout,
+            &format!("Put the cards to import in {}", path.display()),
+        );
+        return;
+    }
+
+    let mut options = ImportOptions::for_path(&path);
+    options.mapping = Config::load().unwrap().import_mapping;
+    let import = match CsvImport::read(&path, &options) {
+        Ok(import) => import,
+        Err(e) => {
+            draw_message(stdout, &format!("Failed to read {}: {e}", path.display()));
+            return;
+        }
+    };
+
+    draw_message(stdout, &import.summary());
+    if import.cards.is_empty() || !affirmative(stdout, "Import the cards?") {
+        return;
+    }
+
+    let qty = import.save(cache).len();
+    std::fs::rename(&path, path.with_file_name("imported.csv")).unwrap();
+    git::sync_in_background();
+    draw_message(stdout, &format!("Imported {qty} cards"));
+}
+
+fn restore_from_trash(stdout: &mut Stdout, cache: &mut CardCache) {
+    let trashed = trash::list();
+    let formatter = |trashed: &TrashedCard| {
+        format!(
+            "{}  ({})",
+            trashed.card.front.text,
+            Category::from_joined(&trashed.info.category).print_full()
+        )
+    };
+    let Some(picked) = pick_item_with_formatter(stdout, "Card to restore", &trashed, formatter)
+    else {
+        return;
+    };
+
+    let id = picked.card.meta.id;
+    let restored = journal::transaction("restore card", || trash::restore(&id, cache));
+    if restored.is_some() {
+        git::sync_in_background();
+        draw_message(stdout, "Card restored");
+    }
+}
+
+/// Asks for a line of text, None if escaped.
+fn ask(stdout: &mut Stdout, question: &str) -> Option<String> {
+    clear_window(stdout);
+    move_upper_left(stdout);
+    print!("{question} ");
+    stdout.flush().unwrap();
+    read_user_input(stdout).map(|(input, _)| input.trim().to_string())
+}
+
+fn ask_number(stdout: &mut Stdout, question: &str) -> Option<f32> {
+    let input = ask(stdout, question)?;
+    match input.parse() {
+        Ok(number) => Some(number),
+        Err(_) => {
+            draw_message(stdout, &format!("{input} isn't a number"));
+            None
+        }
+    }
+}
+
+fn choose_filter_kind(stdout: &mut Stdout, message: &str) -> Option<FilterKind> {
+    let kinds = vec![
+        "are pending",
+        "are finished",
+        "are suspended",
+        "have a tag",
+        "have a strength below",
+        "have a stability below",
+        "have a recall below",
+    ];
+    Some(match draw_menu(stdout, Some(message), kinds, true)? {
+        0 => FilterKind::Pending,
+        1 => FilterKind::Finished,
+        2 => FilterKind::Suspended,
+        3 => {
+            let tags: Vec<String> = Category::get_all_tags().into_iter().collect();
+            let tag = pick_item(stdout, "Tag", &tags)?.clone();
+            FilterKind::Tag { tag }
+        }
+        4 => FilterKind::MaxStrength {
+            days: ask_number(stdout, "Strength below how many days?")?,
+        },
+        5 => FilterKind::MaxStability {
+            days: ask_number(stdout, "Stability below how many days?")?,
+        },
+        6 => FilterKind::MaxRecall {
+            recall: ask_number(stdout, "Recall below what, from 0 to 1?")?,
+        },
+        _ => return None,
+    })
+}
+
+/// Puts a filter together from the ones in `common::Filters`, None if backed out of.
+fn build_filter(
+    stdout: &mut Stdout,
+    mut filter: SavedFilter,
+    cache: &mut CardCache,
+) -> Option<SavedFilter> {
+    loop {
+        let source = CardSource::Filter {
+            name: String::new(),
+            filter: filter.clone(),
+        };
+        let matching = source.narrow(cache.all_ids(), cache).len();
+        let message = format!("{matching} cards: {filter}");
+        let items = vec![
+            "Include cards that...",
+            "Exclude cards that...",
+            "Remove one",
+            "Save",
+        ];
+        match draw_menu(stdout, Some(&message), items, true)? {
+            0 => {
+                if let Some(kind) = choose_filter_kind(stdout, "Include cards that...") {
+                    filter.include.push(kind);
+                }
+            }
+            1 => {
+                if let Some(kind) = choose_filter_kind(stdout, "Exclude cards that...") {
+                    filter.exclude.push(kind);
+                }
+            }
+            2 => {
+                let labels = filter.labels();
+                let Some(picked) = pick_item(stdout, "Remove", &labels) else {
+                    continue;
+                };
+                let index = labels.iter().position(|label| label == picked).unwrap();
+                match index.checked_sub(filter.include.len()) {
+                    None => filter.include.remove(index),
+                    Some(index) => filter.exclude.remove(index),
+                };
+            }
+            3 => return Some(filter),
+            _ => {}
+        }
+    }
+}
+
+/// The filters saved in the config, to make new ones, change them or view their cards.
+fn saved_filters(stdout: &mut Stdout, cache: &mut CardCache) {
+    loop {
+        let mut config = Config::load().unwrap();
+        // None is for making a new one.
+        let mut items: Vec<Option<(String, SavedFilter)>> = vec![None];
+        items.extend(config.filters.clone().into_iter().map(Some));
+        let formatter = |item: &Option<(String, SavedFilter)>| match item {
+            None => "New filter".to_string(),
+            Some((name, filter)) => format!("{name}: {filter}"),
+        };
+        let Some(picked) = pick_item_with_formatter(stdout, "Filters", &items, formatter) else {
+            return;
+        };
+
+        match picked.clone() {
+            None => {
+                let Some(filter) = build_filter(stdout, SavedFilter::default(), cache) else {
+                    continue;
+                };
+                let Some(name) = ask(stdout, "Name of the filter:").filter(|name| !name.is_empty())
+                else {
+                    continue;
+                };
+                if config.filters.contains_key(&name)
+                    && !affirmative(stdout, &format!("Replace the filter called {name}?"))
+                {
+                    continue;
+                }
+                config.filters.insert(name, filter);
+            }
+            Some((name, filter)) => {
+                let source = CardSource::Filter {
+                    name: name.clone(),
+                    filter: filter.clone(),
+                };
+                match draw_menu(
+                    stdout,
+                    Some(&source.to_string()),
+                    vec!["View cards", "Edit", "Delete"],
+                    true,
+                ) {
+                    Some(0) => {
+                        let cards = source.narrow(cache.all_ids(), cache);
+                        view_cards(stdout, cards, cache);
+                        continue;
+                    }
+                    Some(1) => {
+                        let Some(filter) = build_filter(stdout, filter, cache) else {
+                            continue;
+                        };
+                        config.filters.insert(name, filter);
+                    }
+                    Some(2) if affirmative(stdout, "Delete the filter?") => {
+                        config.filters.remove(&name);
+                    }
+                    _ => continue,
+                }
+            }
+        }
+        if let Err(e) = config.save() {
+            draw_message(stdout, &format!("Couldn't save the config: {e}"));
+        }
+    }
+}
+
+/// Lists the files that didn't load as cards, picking one opens it in vim to fix it.
+fn broken_cards(stdout: &mut Stdout, cache: &mut CardCache) {
+    loop {
+        let cards_path = get_cards_path();
+        let describe = |error: &Error| {
+            let path = error.path();
+            let shown = path.strip_prefix(&cards_path).unwrap_or(path);
+            let reason = error.reason();
+            let reason = reason.lines().next().unwrap_or_default();
+            let label = match error.line() {
+                Some(line) => format!("{}  line {line}: {reason}", shown.display()),
+                None => format!("{}  {reason}", shown.display()),
+            };
+            (path.to_path_buf(), label)
+        };
+        let broken: Vec<(PathBuf, String)> = cache.broken().iter().map(describe).collect();
+        if broken.is_empty() {
+            draw_message(stdout, "No broken cards");
+            return;
+        }
+
+        let message = "Broken cards, they're left out until fixed";
+        let formatter = |(_, label): &(PathBuf, String)| label.clone();
+        let Some((path, _)) = pick_item_with_formatter(stdout, message, &broken, formatter) else {
+            return;
+        };
+        if path.is_file() {
+            let _ = open_file_with_vim(path);
+        }
+        cache.refresh();
+    }
+}
+
 pub fn run() {
     let mut cache = CardCache::new();
     import_stuff(&mut cache);
@@ -437,6 +671,10 @@ pub fn run() {
     let mut stdout = stdout();
     execute!(stdout, Hide).unwrap();
 
+    if !cache.broken().is_empty() {
+        broken_cards(&mut stdout, &mut cache);
+    }
+
     let menu_items = vec![
         "Add new cards",
         "Review cards",
@@ -450,47 +688,87 @@ pub fn run() {
         "health check",
         "stats",
         "filters",
+        "import csv",
+        "trash",
+        "broken cards",
     ];
 
-    while let Some(choice) = draw_menu(&mut stdout, None, menu_items.clone(), true) {
+    loop {
+        for problem in git::take_problems() {
+            draw_message(&mut stdout, &problem);
+        }
+        let status = git::status();
+        let Some(choice) = draw_menu(&mut stdout, status.as_deref(), menu_items.clone(), true)
+        else {
+            break;
+        };
         match choice {
             0 => {
                 let Some(category) =  choose_folder(&mut stdout, "Folder to add card to")  else {continue};
                 add_cards(&mut stdout, category, &mut cache);
-                let has_remote = Config::load().unwrap().git_remote.is_some();
-                let _ = std::thread::spawn(move || git_save(has_remote));
+                git::sync_in_background();
             }
             1 => {
-                let Some(revtype) = draw_menu(&mut stdout, None, vec!["Normal", "Pending", "Unfinished"], true) else {continue};
-
-                let Some(category) =  choose_folder(&mut stdout, "Choose review type") else {continue};
+                let Some(revtype) = draw_menu(
+                    &mut stdout,
+                    None,
+                    vec!["Normal", "Pending", "Unfinished", "Multiple choice"],
+                    true,
+                ) else {
+                    continue;
+                };
+
+                let Some(source) = choose_source(&mut stdout, "Choose review type") else {
+                    continue;
+                };
 
                 match revtype {
                     0 => {
                         review_cards(
                             &mut stdout,
-                            category.clone(),
+                            source.clone(),
                             Box::new(Category::get_review_cards),
+                            false,
                             &mut cache,
                         );
                         draw_message(&mut stdout, "now reviewing pending cards");
                         review_cards(
                             &mut stdout,
-                            category.clone(),
+                            source.clone(),
                             Box::new(Category::get_pending_cards),
+                            false,
                             &mut cache,
                         );
                     }
                     1 => {
                         review_cards(
                             &mut stdout,
-                            category.clone(),
+                            source.clone(),
                             Box::new(Category::get_pending_cards),
+                            false,
+                            &mut cache,
+                        );
+                    }
+                    3 => {
+                        // New cards first, it's mostly for getting to know a deck.
+                        review_cards(
+                            &mut stdout,
+                            source.clone(),
+                            Box::new(Category::get_pending_cards),
+                            true,
+                            &mut cache,
+                        );
+                        review_cards(
+                            &mut stdout,
+                            source.clone(),
+                            Box::new(Category::get_review_cards),
+                            true,
                             &mut cache,
                         );
                     }
                     2 => {
-                        let mut cards = get_following_unfinished_cards(&category, &mut cache);
+                        let cards = get_following_unfinished_cards(&source.category(), &mut cache);
+                        let mut cards = source.narrow(cards, &mut cache);
                         cards.sort_by_key(|card| {
                             cache.get_ref(card).get_unfinished_dependent_qty(&mut cache)
                         });
@@ -500,8 +778,7 @@ pub fn run() {
                     _ => continue,
                 }
 
-                let has_remote = Config::load().unwrap().git_remote.is_some();
-                let _ = std::thread::spawn(move || git_save(has_remote));
+                git::sync_in_background();
             }
             2 => view_cards_in_explorer(),
             3 => {
@@ -544,6 +821,10 @@ pub fn run() {
                 health_check(&mut stdout, &mut cache);
             }
             10 => print_stats(&mut stdout, &mut cache),
+            11 => saved_filters(&mut stdout, &mut cache),
+            12 => import_csv(&mut stdout, &mut cache),
+            13 => restore_from_trash(&mut stdout, &mut cache),
+            14 => broken_cards(&mut stdout, &mut cache),
             _ => {}
         };
     }
@@ -558,70 +839,87 @@ pub fn search_for_item(
     stdout: &mut Stdout,
     message: &str,
     excluded_cards: HashSet<Id>,
+    cache: &mut CardCache,
 ) -> Option<SavedCard> {
     let mut input = String::new();
-
-    let cards = SavedCard::load_all_cards();
     let mut index = 0;
+    let screen_height = crossterm::terminal::size().unwrap().1.saturating_sub(10);
+
+    // Only the ones that fit on the screen are looked up, the index has the rest.
+    let mut find = |search_term: &str| -> Result<Vec<Arc<SavedCard>>, String> {
+        let query = Query::parse(search_term)?;
+        Ok(cache
+            .search(&query)
+            .into_iter()
+            .filter(|id| !excluded_cards.contains(id))
+            .take(screen_height as usize + 1)
+            .map(|id| cache.get_ref(&id))
+            .collect())
+    };
 
-    let mut print_stuff = |search_term: &str, cards: Vec<&SavedCard>, index: &mut usize| {
-        clear_window(stdout);
-        //move_upper_left(stdout);
-        execute!(stdout, MoveTo(0, 0)).unwrap();
-        println!("{}", message);
-        println!("\t\t| {} |", search_term);
-        let screen_height = crossterm::terminal::size().unwrap().1 - 10;
-        *index = std::cmp::min(
-            std::cmp::min(*index, screen_height.into()),
-            cards.len().saturating_sub(1),
-        );
-        for (idx, card) in cards.iter().enumerate() {
-            move_far_left(stdout);
-
-            if idx == *index {
-                execute!(stdout, SetForegroundColor(crossterm::style::Color::Blue)).unwrap();
-                println!("> {}", card.front_text());
-                execute!(stdout, ResetColor).unwrap();
-            } else {
-                println!("  {}", card.front_text());
-            }
+    let mut print_stuff =
+        |search_term: &str, found: &Result<Vec<Arc<SavedCard>>, String>, index: &mut usize| {
+            clear_window(stdout);
+            //move_upper_left(stdout);
+            execute!(stdout, MoveTo(0, 0)).unwrap();
+            println!("{}", message);
+            println!("\t\t| {} |", search_term);
+            let cards = match found {
+                Ok(cards) => cards,
+                Err(e) => {
+                    move_far_left(stdout);
+                    println!("{}", e);
+                    return;
+                }
+            };
+            *index = std::cmp::min(
+                std::cmp::min(*index, screen_height.into()),
+                cards.len().saturating_sub(1),
+            );
+            for (idx, card) in cards.iter().enumerate() {
+                move_far_left(stdout);
+
+                if idx == *index {
+                    execute!(stdout, SetForegroundColor(crossterm::style::Color::Blue)).unwrap();
+                    println!("> {}", card.front_text());
+                    execute!(stdout, ResetColor).unwrap();
+                } else {
+                    println!("  {}", card.front_text());
+                }
 
-            if idx == screen_height.into() {
-                break;
+                if idx == screen_height as usize {
+                    break;
+                }
             }
-        }
-    };
+        };
 
+    let mut found = find(&input);
     loop {
         if let Event::Key(event) = read().unwrap() {
             match event.code {
                 KeyCode::Char(c) => {
                     input.push(c);
-                    let the_cards = SavedCard::search_in_cards(&input, &cards, &excluded_cards);
-                    print_stuff(&input, the_cards, &mut index);
+                    found = find(&input);
+                    print_stuff(&input, &found, &mut index);
                 }
                 KeyCode::Backspace if !input.is_empty() => {
                     input.pop();
-                    let the_cards = SavedCard::search_in_cards(&input, &cards, &excluded_cards);
-                    print_stuff(&input, the_cards, &mut index);
+                    found = find(&input);
+                    print_stuff(&input, &found, &mut index);
                 }
                 KeyCode::Enter => {
-                    let the_cards = SavedCard::search_in_cards(&input, &cards, &excluded_cards);
-                    if the_cards.is_empty() {
-                        return None;
-                    }
-                    return Some(the_cards[index].to_owned());
+                    return match &found {
+                        Ok(cards) => cards.get(index).map(|card| (**card).clone()),
+                        Err(_) => None,
+                    };
                 }
                 KeyCode::Down => {
                     index += 1;
-
-                    let the_cards = SavedCard::search_in_cards(&input, &cards, &excluded_cards);
-                    print_stuff(&input, the_cards, &mut index);
+                    print_stuff(&input, &found, &mut index);
                 }
                 KeyCode::Up => {
-                    let the_cards = SavedCard::search_in_cards(&input, &cards, &excluded_cards);
                     index = index.saturating_sub(1);
-                    print_stuff(&input, the_cards, &mut index);
+                    print_stuff(&input, &found, &mut index);
                 }
                 KeyCode::Esc => return None,
                 _ => {}
@@ -750,52 +1048,122 @@ pub enum SomeStatus {
     Break,
 }
 
-fn print_card_review_front(stdout: &mut Stdout, card: &Card, sound: bool) {
+fn print_card_review_front(
+    stdout: &mut Stdout,
+    card: &SavedCard,
+    item: ReviewItem,
+    show_images: bool,
+) {
     execute!(stdout, MoveTo(0, 1)).unwrap();
-    println!("{}", card.front.text);
-    if sound {
-        //  card.front.audio.play_audio();
+    println!("{}", card.review_front(item));
+    if show_images {
+        print_image(stdout, &card.review_sides(item).0.image);
     }
 }
 
-fn print_card_review_back(stdout: &mut Stdout, card: &Card, sound: bool) {
+fn print_card_review_back(
+    stdout: &mut Stdout,
+    card: &SavedCard,
+    item: ReviewItem,
+    show_images: bool,
+) {
     move_far_left(stdout);
     execute!(stdout, MoveDown(1)).unwrap();
     move_far_left(stdout);
     println!("------------------");
     execute!(stdout, MoveDown(1)).unwrap();
     move_far_left(stdout);
-    println!("{}", card.back.text);
+    println!("{}", card.review_back(item));
+    if show_images {
+        print_image(stdout, &card.review_sides(item).1.image);
+    }
     move_far_left(stdout);
+}
 
-    if sound {
-        //card.back.audio.play_audio();
-    }
+/// Draws the image below the text, in a third of the screen at most.
+fn print_image(stdout: &mut Stdout, image: &ImageSource) {
+    let Some(loaded) = image.path().and_then(|path| images::load(&path)) else {
+        return;
+    };
+    let (width, height) = terminal::size().unwrap();
+    let rendered = images::render(&loaded, images::Protocol::detect(), width, height / 3);
+    move_far_left(stdout);
+    write_string(stdout, &rendered);
+    stdout.flush().unwrap();
 }
 
 fn should_exit(key: &KeyCode) -> bool {
     matches!(key, KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('Q'))
 }
 
-fn print_card_for_review(stdout: &mut Stdout, card: &SavedCard, show_backside: bool, status: &str) {
+fn print_card_for_review(
+    stdout: &mut Stdout,
+    card: &SavedCard,
+    item: ReviewItem,
+    show_backside: bool,
+    show_images: bool,
+    status: &str,
+) {
     execute!(stdout, Clear(ClearType::All)).unwrap();
     update_status_bar(stdout, status);
-    print_card_review_front(stdout, card.card_as_ref(), true);
+    print_card_review_front(stdout, card, item, show_images);
     if show_backside {
-        print_card_review_back(stdout, card.card_as_ref(), true);
+        print_card_review_back(stdout, card, item, show_images);
     }
 }
 
 fn review_card(
     stdout: &mut Stdout,
     card_id: &Id,
+    item: ReviewItem,
     status: String,
     cache: &mut CardCache,
 ) -> SomeStatus {
     let mut show_backside = false;
+    let config = Config::load().unwrap();
+    // Time spent idle or in other menus would mess up the timing, so it's capped.
+    let cap = Duration::from_secs(config.review_time_cap);
+    let front_shown = Instant::now();
+    let mut revealed = None;
+    // Esc while typing falls back to grading by hand.
+    let mut typing = answer::is_typed(&cache.get_ref(card_id), &config);
+    let mut checked = None;
+    // Played on its own, the screen is redrawn all the time. No sound is no reason to stop.
+    let mut sink = audio::sink(&config);
+    let _ = audio::play(
+        &cache.get_ref(card_id).review_sides(item).0.audio,
+        sink.as_mut(),
+    );
     loop {
         let card = cache.get_ref(card_id);
-        print_card_for_review(stdout, &card, show_backside, status.as_str());
+        print_card_for_review(
+            stdout,
+            &card,
+            item,
+            show_backside,
+            config.show_images,
+            status.as_str(),
+        );
+        if typing && !show_backside {
+            move_far_left(stdout);
+            execute!(stdout, MoveDown(1)).unwrap();
+            move_far_left(stdout);
+            print!("answer: ");
+            stdout.flush().unwrap();
+            match read_user_input(stdout) {
+                Some((typed, _)) => {
+                    checked = Some(answer::check(&typed, &card.expected_answer(item)));
+                    show_backside = true;
+                    revealed.get_or_insert_with(Instant::now);
+                    let _ = audio::play(&card.review_sides(item).1.audio, sink.as_mut());
+                }
+                None => typing = false,
+            }
+            continue;
+        }
+        if let Some(checked) = &checked {
+            print_checked_answer(stdout, checked);
+        }
         let keycode = get_keycode();
         if edit_card(stdout, &keycode, card.clone(), cache) {
             continue;
@@ -821,23 +1189,55 @@ fn review_card(
             KeyCode::Char('q') => return SomeStatus::Break,
             KeyCode::Char('D') => {
                 if affirmative(stdout, "Delete card?") {
-                    cache.get_owned(card.id()).delete(cache);
+                    journal::transaction("delete card", || {
+                        cache.get_owned(card.id()).delete(cache)
+                    });
                     draw_message(stdout, "Card deleted");
                     break;
                 }
             }
-            KeyCode::Char(' ') => show_backside = true,
+            KeyCode::Char('u') | KeyCode::Char('U') => {
+                undo_redo(stdout, cache, keycode == KeyCode::Char('U'));
+                if !cache.exists(card_id) {
+                    return SomeStatus::Continue;
+                }
+            }
+            KeyCode::Char(' ') => {
+                if !show_backside {
+                    let _ = audio::play(&card.review_sides(item).1.audio, sink.as_mut());
+                }
+                show_backside = true;
+                revealed.get_or_insert_with(Instant::now);
+            }
+            KeyCode::Char('r') => {
+                let (front, back) = card.review_sides(item);
+                let side = if show_backside { back } else { front };
+                match audio::play(&side.audio, sink.as_mut()) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        draw_message(stdout, "No audio on this side");
+                    }
+                    Err(e) => {
+                        draw_message(stdout, &format!("Couldn't play the audio: {e}"));
+                    }
+                }
+            }
             KeyCode::Char('s') => break,
             KeyCode::Char('a') => {
                 add_card(stdout, &mut card.category().to_owned(), cache);
             }
             KeyCode::Char(c) if show_backside => match c.to_string().parse() {
                 Ok(grade) => {
-                    cache.get_owned(card_id).new_review(grade);
+                    grade_card(card_id, item, grade, front_shown, revealed, cap, cache);
                     return SomeStatus::Continue;
                 }
                 _ => continue,
             },
+            KeyCode::Enter if checked.is_some() => {
+                let grade = checked.take().unwrap().grade;
+                grade_card(card_id, item, grade, front_shown, revealed, cap, cache);
+                return SomeStatus::Continue;
+            }
             key if should_exit(&key) => return SomeStatus::Break,
             _ => continue,
         }
@@ -845,6 +1245,172 @@ fn review_card(
     SomeStatus::Continue
 }
 
+/// Picking the answer from a few, cards without enough to pick from are reviewed normally.
+fn review_multiple_choice(
+    stdout: &mut Stdout,
+    card_id: &Id,
+    item: ReviewItem,
+    status: String,
+    cache: &mut CardCache,
+) -> SomeStatus {
+    let config = Config::load().unwrap();
+    let card = cache.get_ref(card_id);
+    let (options, right) = choice::options(&card, item, cache, config.choice_count);
+    if options.is_empty() {
+        return review_card(stdout, card_id, item, status, cache);
+    }
+
+    execute!(stdout, Clear(ClearType::All)).unwrap();
+    update_status_bar(stdout, &status);
+    print_card_review_front(stdout, &card, item, config.show_images);
+    for (index, option) in options.iter().enumerate() {
+        move_far_left(stdout);
+        execute!(stdout, MoveDown(1)).unwrap();
+        move_far_left(stdout);
+        print!("{}. {}", index + 1, option);
+    }
+    stdout.flush().unwrap();
+
+    let mut sink = audio::sink(&config);
+    let _ = audio::play(&card.review_sides(item).0.audio, sink.as_mut());
+    let shown = Instant::now();
+    let picked = loop {
+        match get_keycode() {
+            KeyCode::Char('s') => return SomeStatus::Continue,
+            key if should_exit(&key) => return SomeStatus::Break,
+            KeyCode::Char(c) => match c.to_digit(10) {
+                Some(n) if (1..=options.len()).contains(&(n as usize)) => break n as usize - 1,
+                _ => continue,
+            },
+            _ => continue,
+        }
+    };
+    let latency = shown
+        .elapsed()
+        .min(Duration::from_secs(config.review_time_cap));
+    let grade = choice::grade(picked == right, latency);
+    journal::transaction("review card", || {
+        cache
+            .get_owned(card_id)
+            .new_review(item, grade, latency, Duration::default())
+    });
+
+    let _ = audio::play(&card.review_sides(item).1.audio, sink.as_mut());
+    let verdict = match picked == right {
+        true => "right!".to_string(),
+        false => format!("wrong, it was {}", right + 1),
+    };
+    draw_message(
+        stdout,
+        &format!(
+            "{}\n------------------\n{}\n\n{verdict}",
+            card.review_front(item),
+            card.review_back(item)
+        ),
+    );
+    SomeStatus::Continue
+}
+
+fn grade_card(
+    card_id: &Id,
+    item: ReviewItem,
+    grade: Grade,
+    front_shown: Instant,
+    revealed: Option<Instant>,
+    cap: Duration,
+    cache: &mut CardCache,
+) {
+    let revealed = revealed.unwrap_or(front_shown);
+    let time_spent = (revealed - front_shown).min(cap);
+    let time_to_grade = revealed.elapsed().min(cap);
+    journal::transaction("review card", || {
+        cache
+            .get_owned(card_id)
+            .new_review(item, grade, time_spent, time_to_grade)
+    });
+}
+
+/// The typed answer with the mistakes colored in, red for wrong or extra letters and the
+/// expected ones in green after them, then the suggested grade.
+fn print_checked_answer(stdout: &mut Stdout, checked: &answer::Checked) {
+    use crossterm::style::Color;
+    move_far_left(stdout);
+    execute!(stdout, MoveDown(1)).unwrap();
+    move_far_left(stdout);
+    print!("you typed: ");
+    for edit in &checked.diff {
+        let (typed, expected) = match *edit {
+            answer::Edit::Same(c) => (Some(c), None),
+            answer::Edit::Wrong { typed, expected } => (Some(typed), Some(expected)),
+            answer::Edit::Missing(c) => (None, Some(c)),
+            answer::Edit::Extra(c) => (Some(c), None),
+        };
+        let typed_color = match edit {
+            answer::Edit::Same(_) => Color::Reset,
+            _ => Color::Red,
+        };
+        if let Some(c) = typed {
+            execute!(stdout, SetForegroundColor(typed_color), Print(c)).unwrap();
+        }
+        if let Some(c) = expected {
+            execute!(stdout, SetForegroundColor(Color::Green), Print(c)).unwrap();
+        }
+    }
+    execute!(stdout, ResetColor).unwrap();
+    if checked.distance > 0 {
+        print!("  ({} off)", checked.distance);
+    }
+    move_far_left(stdout);
+    execute!(stdout, MoveDown(1)).unwrap();
+    move_far_left(stdout);
+    let (number, name) = match checked.grade {
+        Grade::None => (1, "none"),
+        Grade::Late => (2, "late"),
+        Grade::Some => (3, "some"),
+        Grade::Perfect => (4, "perfect"),
+    };
+    print!("suggested grade: {number} ({name}), enter to accept or 1-4 to pick another");
+    stdout.flush().unwrap();
+}
+
+/// Lists the commits that changed the card, picking one shows that version and offers to
+/// revert to it.
+fn card_history(stdout: &mut Stdout, card: &SavedCard, cache: &mut CardCache) {
+    let dir = get_share_path();
+    let versions = match git::history(&dir, &card.as_path()) {
+        Ok(versions) => versions,
+        Err(e) => {
+            draw_message(stdout, &format!("Couldn't read the history: {e}"));
+            return;
+        }
+    };
+    let formatter = |version: &git::Version| {
+        format!("{}  {}  {}", version.date, version.commit, version.message)
+    };
+    let Some(version) = pick_item_with_formatter(stdout, "Card history", &versions, formatter)
+    else {
+        return;
+    };
+
+    let old = git::show_version(&dir, version)
+        .ok()
+        .and_then(|content| toml::from_str::<Card>(&content).ok());
+    let Some(old) = old else {
+        draw_message(stdout, "Couldn't read that version of the card");
+        return;
+    };
+
+    let message = format!(
+        "{} {}\n{}\n-------------------\n{}",
+        version.date, version.message, old.front.text, old.back.text
+    );
+    draw_message(stdout, &message);
+    if affirmative(stdout, "Revert to this version?") {
+        journal::transaction("revert card", || cache.get_owned(card.id()).revert_to(old));
+        draw_message(stdout, "Card reverted");
+    }
+}
+
 /// Bool represents if any action was taken.
 pub fn edit_card(
     stdout: &mut Stdout,
@@ -854,81 +1420,122 @@ pub fn edit_card(
 ) -> bool {
     let mut excluded_cards = HashSet::new();
     excluded_cards.insert(card.id().to_owned());
-    match key {
-        KeyCode::Char('`') => {
-            let info = format!("{:?}", card.get_info(cache));
-            draw_message(stdout, info.as_str());
-        }
-        KeyCode::Char('p') => {
-            let ch = _get_char();
-            if let Ok(priority) = ch.try_into() {
-                cache.get_owned(card.id()).set_priority(priority);
+    // Only the keys that change something are an undo step, the others open screens that
+    // make their own.
+    let description = match key {
+        KeyCode::Char('p') | KeyCode::Char('P') => Some("change priority"),
+        KeyCode::Char('f') => Some("mark card finished"),
+        KeyCode::Char('S') => Some("suspend card"),
+        KeyCode::Char('g') => Some("add tag"),
+        KeyCode::Char('y') => Some("add dependency"),
+        KeyCode::Char('t') => Some("add dependent"),
+        KeyCode::Char('m') => Some("move card"),
+        KeyCode::Char('R') => Some("toggle reversible"),
+        KeyCode::Char('e') => Some("edit card"),
+        _ => None,
+    };
+    let handle = || {
+        match key {
+            KeyCode::Char('`') => {
+                let info = match card.get_info(cache) {
+                    Some(info) => info.to_string(),
+                    None => "not reviewed yet".to_string(),
+                };
+                draw_message(stdout, info.as_str());
             }
-        }
-
-        KeyCode::Char('P') => {
-            draw_message(stdout, "choose priority, from 0 to 100");
-            if let Some(input) = read_user_input(stdout) {
-                if let Ok(num) = input.0.trim().parse::<u32>() {
-                    let priority: Priority = num.into();
+            KeyCode::Char('p') => {
+                let ch = _get_char();
+                if let Ok(priority) = ch.try_into() {
                     cache.get_owned(card.id()).set_priority(priority);
                 }
             }
-        }
 
-        KeyCode::Char('f') => {
-            let mut thecard = cache.get_owned(card.id());
-            thecard.set_finished(true);
-        }
+            KeyCode::Char('P') => {
+                draw_message(stdout, "choose priority, from 0 to 100");
+                if let Some(input) = read_user_input(stdout) {
+                    if let Ok(num) = input.0.trim().parse::<u32>() {
+                        let priority: Priority = num.into();
+                        cache.get_owned(card.id()).set_priority(priority);
+                    }
+                }
+            }
 
-        KeyCode::Char('S') => suspend_card(stdout, card.id(), cache),
+            KeyCode::Char('f') => {
+                let mut thecard = cache.get_owned(card.id());
+                thecard.set_finished(true);
+            }
 
-        KeyCode::Char('g') => {
-            let tags = card.category().get_tags().into_iter().collect();
-            let tag = match pick_item(stdout, "Choose tag", &tags) {
-                Some(tag) => tag,
-                None => return true,
-            };
-            let mut thecard = cache.get_owned(card.id());
-            thecard.insert_tag(tag.to_owned());
-        }
+            KeyCode::Char('R') => {
+                let mut thecard = cache.get_owned(card.id());
+                let reversible = !thecard.is_reversible();
+                thecard.set_reversible(reversible);
+                let message = match reversible {
+                    true => "The card is now also reviewed from back to front",
+                    false => "The card is only reviewed from front to back now",
+                };
+                draw_message(stdout, message);
+            }
+
+            KeyCode::Char('S') => suspend_card(stdout, card.id(), cache),
 
-        KeyCode::Char('y') => {
-            if let Some(chosen_card) = search_for_item(stdout, "Add dependency", excluded_cards) {
-                cache
-                    .get_owned(card.id())
-                    .set_dependency(chosen_card.id(), cache);
-                cache.refresh();
+            KeyCode::Char('g') => {
+                let tags: Vec<String> = card.category().get_tags().into_iter().collect();
+                let tag = match pick_item(stdout, "Choose tag", &tags) {
+                    Some(tag) => tag,
+                    None => return true,
+                };
+                let mut thecard = cache.get_owned(card.id());
+                thecard.insert_tag(tag.to_owned());
             }
-        }
-        KeyCode::Char('t') => {
-            if let Some(chosen_card) = search_for_item(stdout, "Add dependent", excluded_cards) {
-                let info = cache
-                    .get_owned(card.id())
-                    .set_dependent(chosen_card.id(), cache);
-                if let Some(info) = info {
-                    draw_message(stdout, &info);
+
+            KeyCode::Char('y') => {
+                if let Some(chosen_card) =
+                    search_for_item(stdout, "Add dependency", excluded_cards, cache)
+                {
+                    cache
+                        .get_owned(card.id())
+                        .set_dependency(chosen_card.id(), cache);
+                    cache.refresh();
                 }
             }
-        }
-        KeyCode::Char('v') => {
-            view_dependencies(stdout, card.id(), cache);
-        }
-        KeyCode::Char('m') => {
-            let folder = match choose_folder(stdout, "Move card to...") {
-                Some(folder) => folder,
-                None => return true,
-            };
-
-            let moved_card = cache.get_owned(card.id()).move_card(&folder, cache);
-            cache.insert(moved_card);
-        }
-        KeyCode::Char('e') => {
-            card.edit_with_vim();
-        }
-        _ => return false,
+            KeyCode::Char('t') => {
+                if let Some(chosen_card) =
+                    search_for_item(stdout, "Add dependent", excluded_cards, cache)
+                {
+                    let info = cache
+                        .get_owned(card.id())
+                        .set_dependent(chosen_card.id(), cache);
+                    if let Some(info) = info {
+                        draw_message(stdout, &info);
+                    }
+                }
+            }
+            KeyCode::Char('v') => {
+                view_dependencies(stdout, card.id(), cache);
+            }
+            KeyCode::Char('m') => {
+                let folder = match choose_folder(stdout, "Move card to...") {
+                    Some(folder) => folder,
+                    None => return true,
+                };
+
+                let moved_card = cache.get_owned(card.id()).move_card(&folder, cache);
+                cache.insert(moved_card);
+            }
+            KeyCode::Char('e') => {
+                if let Err(e) = card.edit_with_vim() {
+                    draw_message(stdout, &format!("The card doesn't load anymore: {e}"));
+                }
+            }
+            KeyCode::Char('H') => card_history(stdout, &card, cache),
+            _ => return false,
+        };
+        true
     };
-    true
+    match description {
+        Some(description) => journal::transaction(description, handle),
+        None => handle(),
+    }
 }
 
 fn view_cards(stdout: &mut Stdout, mut cards: Vec<Id>, cache: &mut CardCache) {
@@ -1034,8 +1641,20 @@ fn view_cards(stdout: &mut Stdout, mut cards: Vec<Id>, cache: &mut CardCache) {
                     selected -= 1;
                 }
             }
+            KeyCode::Char('u') | KeyCode::Char('U') => {
+                undo_redo(stdout, cache, key_event.code == KeyCode::Char('U'));
+                cards.retain(|id| cache.exists(id));
+                if cards.is_empty() {
+                    draw_message(stdout, "No more cards");
+                    return;
+                }
+                selected = selected.min(cards.len() - 1);
+            }
             KeyCode::Char('D') => {
-                cache.get_owned(card.id()).delete(cache);
+                if !affirmative(stdout, "Delete card?") {
+                    continue;
+                }
+                journal::transaction("delete card", || cache.get_owned(card.id()).delete(cache));
                 draw_message(stdout, "Card deleted");
                 cards.remove(selected);
                 if cards.is_empty() {
@@ -1048,7 +1667,9 @@ fn view_cards(stdout: &mut Stdout, mut cards: Vec<Id>, cache: &mut CardCache) {
             }
             KeyCode::Char('s') => {}
             KeyCode::Char('/') => {
-                if let Some(thecard) = search_for_item(stdout, "find some card", excluded_cards) {
+                if let Some(thecard) =
+                    search_for_item(stdout, "find some card", excluded_cards, cache)
+                {
                     let mut idx = None;
                     for card in cards.iter().enumerate() {
                         if card.1 == thecard.id() {
@@ -1079,29 +1700,44 @@ fn get_following_unfinished_cards(category: &Category, cache: &mut CardCache) ->
     randvec(cards)
 }
 
-pub type CardsFromCategory = Box<dyn FnMut(&Category, &mut CardCache) -> Vec<Id>>;
+pub type CardsFromCategory = Box<dyn FnMut(&Category, &mut CardCache) -> Vec<(Id, ReviewItem)>>;
 
+/// Goes through the review items `get_cards` finds, a card can come up once per item.
 pub fn review_cards(
     stdout: &mut Stdout,
-    category: Category,
+    source: CardSource,
     mut get_cards: CardsFromCategory,
+    multiple_choice: bool,
     cache: &mut CardCache,
 ) {
-    let categories = category.get_following_categories();
-    let mut cards = BTreeSet::new();
+    let categories = source.category().get_following_categories();
+    let mut items = BTreeSet::new();
     for category in &categories {
-        cards.extend(get_cards(category, cache));
+        items.extend(get_cards(category, cache));
     }
 
-    let mut cards: Vec<Id> = cards.into_iter().collect();
-    cards.sort_by_key(|card| {
-        (cache.get_ref(card).expected_gain().unwrap_or_default() * 1000.) as i32
+    let ids: Vec<Id> = items.iter().map(|(card, _)| *card).collect();
+    let kept: HashSet<Id> = source.narrow(ids, cache).into_iter().collect();
+    let mut items: Vec<(Id, ReviewItem)> = items
+        .into_iter()
+        .filter(|(card, _)| kept.contains(card))
+        .collect();
+    items.sort_by_key(|(card, item)| {
+        (cache
+            .get_ref(card)
+            .expected_gain_of(*item)
+            .unwrap_or_default()
+            * 1000.) as i32
     });
-    cards.reverse();
+    items.reverse();
 
-    let cardqty = cards.len();
+    let cardqty = items.len();
 
-    for (index, card) in cards.into_iter().enumerate() {
+    for (index, (card, item)) in items.into_iter().enumerate() {
+        // Deleted while reviewing another of its items.
+        if !cache.exists(&card) {
+            continue;
+        }
         let info = cache.get_ref(&card).get_info(cache).unwrap_or_default();
         let status = format!(
             "{}/{}\t{}\t{}/{}/{}/{}/{}",
@@ -1114,14 +1750,15 @@ pub fn review_cards(
             (info.stability * 100.).round() / 100.,
             info.strength.round(),
         );
-        match {
-            match cache.get_ref(&card).get_review_type() {
-                ReviewType::Normal | ReviewType::Pending => {
-                    review_card(stdout, &card, status.clone(), cache)
-                }
-
-                ReviewType::Unfinished => continue,
+        match match cache.get_ref(&card).get_review_type() {
+            ReviewType::Normal | ReviewType::Pending if multiple_choice => {
+                review_multiple_choice(stdout, &card, item, status.clone(), cache)
+            }
+            ReviewType::Normal | ReviewType::Pending => {
+                review_card(stdout, &card, item, status.clone(), cache)
             }
+
+            ReviewType::Unfinished => continue,
         } {
             SomeStatus::Continue => {
                 continue;
@@ -1221,11 +1858,29 @@ fn choose_folder(stdout: &mut Stdout, message: &str) -> Option<Category> {
     .cloned()
 }
 
-fn pick_item<'a, T: Display>(
-    stdout: &mut Stdout,
-    message: &str,
-    items: &'a Vec<T>,
-) -> Option<&'a T> {
+/// Picks a category or one of the saved filters, which come after the categories.
+fn choose_source(stdout: &mut Stdout, message: &str) -> Option<CardSource> {
+    let filters = Config::load()
+        .map(|config| config.filters)
+        .unwrap_or_default();
+    let mut sources: Vec<CardSource> = Category::load_all()
+        .unwrap()
+        .into_iter()
+        .map(CardSource::Category)
+        .collect();
+    sources.extend(
+        filters
+            .into_iter()
+            .map(|(name, filter)| CardSource::Filter { name, filter }),
+    );
+    let formatter = |source: &CardSource| match source {
+        CardSource::Category(category) => category.print_it_with_depth(),
+        filter => filter.to_string(),
+    };
+    pick_item_with_formatter(stdout, message, &sources, formatter).cloned()
+}
+
+fn pick_item<'a, T: Display>(stdout: &mut Stdout, message: &str, items: &'a [T]) -> Option<&'a T> {
     let formatter = |item: &T| format!("{}", item);
     pick_item_with_formatter(stdout, message, items, formatter)
 }
@@ -1233,7 +1888,7 @@ fn pick_item<'a, T: Display>(
 fn pick_item_with_formatter<'a, T, F>(
     stdout: &mut Stdout,
     message: &str,
-    items: &'a Vec<T>,
+    items: &'a [T],
     formatter: F,
 ) -> Option<&'a T>
 where
@@ -1269,10 +1924,8 @@ where
                     selected = selected.saturating_sub(1);
                 }
                 KeyCode::Char('G') => selected = items.len() - 1,
-                KeyCode::Down | KeyCode::Char('j') => {
-                    if selected < items.len() - 1 {
-                        selected += 1;
-                    }
+                KeyCode::Down | KeyCode::Char('j') if selected < items.len() - 1 => {
+                    selected += 1;
                 }
                 KeyCode::Enter | KeyCode::Char(' ') => return Some(&items[selected]),
                 key if should_exit(&key) => return None,
@@ -1315,10 +1968,8 @@ fn draw_menu(
                 KeyCode::Up | KeyCode::Char('k') => {
                     selected = selected.saturating_sub(1);
                 }
-                KeyCode::Down | KeyCode::Char('j') => {
-                    if selected < items.len() - 1 {
-                        selected += 1;
-                    }
+                KeyCode::Down | KeyCode::Char('j') if selected < items.len() - 1 => {
+                    selected += 1;
                 }
                 KeyCode::Char('G') => selected = items.len() - 1,
                 KeyCode::Enter | KeyCode::Char(' ') => {
@@ -1339,17 +1990,19 @@ pub fn add_dependency(
     category: Option<&Category>,
     cache: &mut CardCache,
 ) -> Option<SavedCard> {
-    let mut card = cache.get_owned(card);
-    let category = category.unwrap_or_else(|| card.category());
-    let category = &mut category.to_owned();
-    let new_dependency = add_card(stdout, category, cache)?;
-    let info = card.set_dependency(new_dependency.id(), cache);
-
-    if let Some(info) = info {
-        draw_message(stdout, &info);
-    }
-    cache.refresh();
-    Some(new_dependency)
+    journal::transaction("add dependency", || {
+        let mut card = cache.get_owned(card);
+        let category = category.unwrap_or_else(|| card.category());
+        let category = &mut category.to_owned();
+        let new_dependency = add_card(stdout, category, cache)?;
+        let info = card.set_dependency(new_dependency.id(), cache);
+
+        if let Some(info) = info {
+            draw_message(stdout, &info);
+        }
+        cache.refresh();
+        Some(new_dependency)
+    })
 }
 
 pub fn add_dependent(
@@ -1358,14 +2011,38 @@ pub fn add_dependent(
     category: Option<&Category>,
     cache: &mut CardCache,
 ) -> Option<SavedCard> {
-    let mut card = cache.get_owned(card);
-    let mut category = category.cloned().unwrap_or_else(|| card.category().clone());
-    let new_dependent = add_card(stdout, &mut category, cache)?;
-    let info = card.set_dependent(new_dependent.id(), cache);
+    journal::transaction("add dependent", || {
+        let mut card = cache.get_owned(card);
+        let mut category = category.cloned().unwrap_or_else(|| card.category().clone());
+        let new_dependent = add_card(stdout, &mut category, cache)?;
+        let info = card.set_dependent(new_dependent.id(), cache);
+
+        if let Some(info) = info {
+            draw_message(stdout, &info);
+        }
+        cache.refresh();
+        Some(new_dependent)
+    })
+}
 
-    if let Some(info) = info {
-        draw_message(stdout, &info);
+/// Undoes or redoes the last change to the cards and says what it was.
+fn undo_redo(stdout: &mut Stdout, cache: &mut CardCache, redo: bool) {
+    let result = if redo {
+        journal::redo(cache)
+    } else {
+        journal::undo(cache)
+    };
+    if let Ok(Some(description)) = &result {
+        let verb = if redo { "redo" } else { "undo" };
+        git::note(git::Change::Other(format!("{verb}: {description}")));
     }
-    cache.refresh();
-    Some(new_dependent)
+    let message = match (result, redo) {
+        (Ok(Some(description)), false) => format!("Undid: {description}"),
+        (Ok(Some(description)), true) => format!("Redid: {description}"),
+        (Ok(None), false) => "Nothing to undo".to_string(),
+        (Ok(None), true) => "Nothing to redo".to_string(),
+        (Err(e), false) => format!("Couldn't undo: {e}"),
+        (Err(e), true) => format!("Couldn't redo: {e}"),
+    };
+    draw_message(stdout, &message);
 }
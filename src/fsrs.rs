@@ -0,0 +1,347 @@
+//! An FSRS-like scheduler, which tracks how difficult a card is on top of its stability.
+//!
+//! The formulas follow FSRS v4.5. Stability is still the time until recall drops to 90%, but
+//! forgetting follows a power curve instead of an exponential one. The 17 weights can be fitted
+//! to your own review history with `optimize`, which is what `spekinew optimize` does.
+//!
+//! Grades map to FSRS ratings like this: none and late are "again", some is "good" and perfect
+//! is "easy". A slow some is "hard".
+
+use std::time::Duration;
+
+use crate::card::{Grade, RecallRate, Review};
+use crate::config::Config;
+use crate::scheduler::{log_loss, MemoryState, Scheduler};
+
+pub const DEFAULT_WEIGHTS: [f32; 17] = [
+    0.4872, 1.4003, 3.7145, 13.8206, 5.1618, 1.2298, 0.8975, 0.031, 1.6474, 0.1367, 1.0461, 2.1072,
+    0.0793, 0.3246, 1.587, 0.2272, 2.8755,
+];
+
+/// Range each weight is kept within while optimizing, so the fit can't go off the rails.
+const BOUNDS: [(f32, f32); 17] = [
+    (0.1, 100.),
+    (0.1, 100.),
+    (0.1, 100.),
+    (0.1, 100.),
+    (1., 10.),
+    (0.1, 5.),
+    (0.1, 5.),
+    (0., 0.75),
+    (0., 4.),
+    (0., 0.8),
+    (0.01, 3.),
+    (0.5, 5.),
+    (0.01, 0.2),
+    (0.01, 0.9),
+    (0.01, 3.),
+    (0., 1.),
+    (1., 6.),
+];
+
+/// Fitting on fewer reviews than this is mostly fitting noise.
+const MIN_REVIEWS: usize = 50;
+const MAX_SWEEPS: usize = 200;
+
+const DECAY: f32 = -0.5;
+// Chosen so that recall is exactly 90% when the time passed equals the stability.
+const FACTOR: f32 = 19. / 81.;
+const DAY: f32 = 86400.;
+
+pub struct Fsrs {
+    weights: [f32; 17],
+}
+
+impl Default for Fsrs {
+    fn default() -> Self {
+        Self::new(DEFAULT_WEIGHTS)
+    }
+}
+
+impl Fsrs {
+    pub fn new(weights: [f32; 17]) -> Self {
+        Self { weights }
+    }
+
+    /// Uses the fitted weights from the config, or the defaults if there aren't any.
+    pub fn from_config(config: &Config) -> Self {
+        config
+            .fsrs_weights
+            .as_slice()
+            .try_into()
+            .map(Self::new)
+            .unwrap_or_default()
+    }
+
+    fn rating(review: &Review) -> f32 {
+        match review.grade {
+            Grade::None | Grade::Late => 1.,
+            Grade::Some if review.is_slow() => 2.,
+            Grade::Some => 3.,
+            Grade::Perfect => 4.,
+        }
+    }
+
+    fn initial_difficulty(&self, rating: f32) -> f32 {
+        let w = &self.weights;
+        (w[4] - w[5] * (rating - 3.)).clamp(1., 10.)
+    }
+
+    fn next_difficulty(&self, difficulty: f32, rating: f32) -> f32 {
+        let w = &self.weights;
+        let difficulty = difficulty - w[6] * (rating - 3.);
+        // Mean reversion towards the difficulty of a card you got right the first time.
+        (w[7] * self.initial_difficulty(3.) + (1. - w[7]) * difficulty).clamp(1., 10.)
+    }
+
+    fn retrievability(days_passed: f32, stability: f32) -> f32 {
+        (1. + FACTOR * days_passed / stability).powf(DECAY)
+    }
+
+    fn recall_stability(&self, difficulty: f32, stability: f32, recall: f32, rating: f32) -> f32 {
+        let w = &self.weights;
+        let hard_penalty = if rating == 2. { w[15] } else { 1. };
+        let easy_bonus = if rating == 4. { w[16] } else { 1. };
+        stability
+            * (w[8].exp()
+                * (11. - difficulty)
+                * stability.powf(-w[9])
+                * ((w[10] * (1. - recall)).exp() - 1.)
+                * hard_penalty
+                * easy_bonus
+                + 1.)
+    }
+
+    fn forget_stability(&self, difficulty: f32, stability: f32, recall: f32) -> f32 {
+        let w = &self.weights;
+        let new = w[11]
+            * difficulty.powf(-w[12])
+            * ((stability + 1.).powf(w[13]) - 1.)
+            * (w[14] * (1. - recall)).exp();
+        new.min(stability)
+    }
+}
+
+impl Scheduler for Fsrs {
+    fn name(&self) -> &'static str {
+        "fsrs"
+    }
+
+    fn next_state(
+        &self,
+        state: Option<MemoryState>,
+        review: &Review,
+        time_passed: Option<Duration>,
+    ) -> MemoryState {
+        let rating = Self::rating(review);
+
+        let (stability, difficulty) = match state {
+            None => (
+                self.weights[rating as usize - 1],
+                self.initial_difficulty(rating),
+            ),
+            Some(state) => {
+                let stability = state.stability.as_secs_f32() / DAY;
+                let difficulty = if state.difficulty == 0. {
+                    self.initial_difficulty(3.)
+                } else {
+                    state.difficulty
+                };
+                let days_passed = time_passed.unwrap_or_default().as_secs_f32() / DAY;
+                let recall = Self::retrievability(days_passed, stability);
+
+                let stability = if review.grade.is_correct() {
+                    self.recall_stability(difficulty, stability, recall, rating)
+                } else {
+                    self.forget_stability(difficulty, stability, recall)
+                };
+                (stability, self.next_difficulty(difficulty, rating))
+            }
+        };
+
+        MemoryState {
+            stability: Duration::from_secs_f32(stability.clamp(0.01, 36500.) * DAY),
+            difficulty,
+        }
+    }
+
+    fn recall_rate(&self, time_passed: Duration, stability: Duration) -> RecallRate {
+        Self::retrievability(
+            time_passed.as_secs_f32() / DAY,
+            stability.as_secs_f32() / DAY,
+        )
+    }
+
+    fn interval(&self, stability: Duration, target: RecallRate) -> Duration {
+        let target = target.clamp(0.01, 0.99);
+        stability.mul_f32((target.powf(1. / DECAY) - 1.) / FACTOR)
+    }
+}
+
+pub struct Fit {
+    pub weights: [f32; 17],
+    pub log_loss: f64,
+    pub reviews: usize,
+}
+
+fn fsrs_log_loss(weights: &[f32; 17], histories: &[Vec<Review>]) -> (f64, usize) {
+    log_loss(&Fsrs::new(*weights), histories.iter().map(Vec::as_slice))
+}
+
+/// Fits the weights to the review histories with a simple pattern search, starting from the
+/// defaults. None if there aren't enough reviews to say anything.
+pub fn optimize(histories: &[Vec<Review>]) -> Option<Fit> {
+    let mut weights = DEFAULT_WEIGHTS;
+    let (mut best, reviews) = fsrs_log_loss(&weights, histories);
+    if reviews < MIN_REVIEWS {
+        return None;
+    }
+
+    let mut steps = BOUNDS.map(|(low, high)| (high - low) * 0.1);
+
+    for _ in 0..MAX_SWEEPS {
+        let mut improved = false;
+
+        for (idx, (low, high)) in BOUNDS.iter().enumerate() {
+            for direction in [1., -1.] {
+                let mut candidate = weights;
+                candidate[idx] = (weights[idx] + direction * steps[idx]).clamp(*low, *high);
+                if candidate[idx] == weights[idx] {
+                    continue;
+                }
+
+                let (loss, _) = fsrs_log_loss(&candidate, histories);
+                if loss < best - 1e-9 {
+                    weights = candidate;
+                    best = loss;
+                    improved = true;
+                    break;
+                }
+            }
+        }
+
+        if !improved {
+            for step in steps.iter_mut() {
+                *step /= 2.;
+            }
+            let converged = steps
+                .iter()
+                .zip(BOUNDS)
+                .all(|(step, (low, high))| *step < (high - low) * 0.001);
+            if converged {
+                break;
+            }
+        }
+    }
+
+    Some(Fit {
+        weights,
+        log_loss: best,
+        reviews,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recall_at_stability() {
+        let stability = Duration::from_secs(86400 * 5);
+        let recall = Fsrs::default().recall_rate(stability, stability);
+        assert!((recall - 0.9).abs() < 0.0001);
+
+        let interval = Fsrs::default().interval(stability, 0.8);
+        let recall = Fsrs::default().recall_rate(interval, stability);
+        assert!((recall - 0.8).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_difficulty() {
+        let fsrs = Fsrs::default();
+        let day = Some(Duration::from_secs(86400));
+        let review = |grade| Review {
+            grade,
+            ..Default::default()
+        };
+        let first = fsrs.next_state(None, &review(Grade::Some), None);
+
+        let failed = fsrs.next_state(Some(first), &review(Grade::None), day);
+        assert!(failed.difficulty > first.difficulty);
+        assert!(failed.stability < first.stability);
+
+        let passed = fsrs.next_state(Some(first), &review(Grade::Perfect), day);
+        assert!(passed.difficulty < first.difficulty);
+        assert!(passed.stability > first.stability);
+    }
+
+    /// Reviews of cards that are forgotten much faster than the default weights expect.
+    fn fast_forgetting_histories() -> Vec<Vec<Review>> {
+        let truth = Fsrs::new({
+            let mut weights = DEFAULT_WEIGHTS;
+            weights[2] = 0.5;
+            weights[8] = 0.5;
+            weights
+        });
+
+        // Small LCG so the test is deterministic without pulling in rand.
+        let mut seed: u64 = 42;
+        let mut random = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as f32 / (1u64 << 31) as f32
+        };
+
+        (0..100)
+            .map(|_| {
+                let mut reviews = vec![];
+                let mut state = None;
+                let mut timestamp = Duration::default();
+                let mut time_passed = None;
+                for _ in 0..8 {
+                    let grade = match state {
+                        Some(MemoryState { stability, .. }) => {
+                            let recall = truth.recall_rate(time_passed.unwrap(), stability);
+                            if random() < recall {
+                                Grade::Some
+                            } else {
+                                Grade::None
+                            }
+                        }
+                        None => Grade::Some,
+                    };
+                    let review = Review {
+                        timestamp,
+                        grade,
+                        ..Default::default()
+                    };
+                    state = Some(truth.next_state(state, &review, time_passed));
+                    reviews.push(review);
+                    let wait = Duration::from_secs_f32(86400. * (0.5 + 4. * random()));
+                    timestamp += wait;
+                    time_passed = Some(wait);
+                }
+                reviews
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_optimize_improves_fit() {
+        let histories = fast_forgetting_histories();
+        let (default_loss, _) = fsrs_log_loss(&DEFAULT_WEIGHTS, &histories);
+        let fit = optimize(&histories).unwrap();
+
+        assert_eq!(fit.reviews, 700);
+        assert!(fit.log_loss < default_loss);
+        for (weight, (low, high)) in fit.weights.iter().zip(BOUNDS) {
+            assert!((low..=high).contains(weight));
+        }
+    }
+
+    #[test]
+    fn test_too_few_reviews() {
+        assert!(optimize(&fast_forgetting_histories()[..2]).is_none());
+    }
+}
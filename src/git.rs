@@ -1,49 +1,797 @@
-use std::process::Command;
+//! Syncs the share folder with a git remote.
+//!
+//! Local changes are committed, then the remote is merged in and the result pushed. When the
+//! same card was changed on two machines git can't merge the toml by itself, so conflicting
+//! card files are merged with `Card::merge` instead: reviews and tags from both sides are kept.
+//! Anything else that conflicts aborts the merge and shows up in the report.
+//!
+//! The git work itself is done by a `Backend`: the git binary by default, or libgit2 with the
+//! `native-git` feature. Either way the repo is opened at an explicit path and whatever branch
+//! is checked out is synced.
+//!
+//! Card operations `note` what they did, which becomes the message of the next commit, so
+//! `history` can show what happened to a card over time.
 
-use crate::paths::get_share_path;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-pub fn git_save(has_remote: bool) {
-    Command::new("git").args(["add", "."]).output().unwrap();
-    Command::new("git")
-        .args(["commit", "-m", "save"])
-        .output()
-        .unwrap();
+use crate::card::Card;
+use crate::categories::Category;
+use crate::common::truncate_string;
+use crate::config::Config;
+use crate::folder_sync::sync_folder;
+use crate::paths::{get_index_path, get_journal_path, get_share_path, get_sync_state_path};
 
-    if has_remote {
-        Command::new("git")
-            .args(["push", "-u", "origin", "main"])
-            .output()
-            .unwrap();
+#[derive(Debug)]
+#[cfg_attr(feature = "native-git", allow(dead_code))]
+pub enum GitError {
+    /// Couldn't run git at all, probably not installed.
+    Spawn(std::io::Error),
+    Failed {
+        args: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    Io(std::io::Error),
+    #[cfg(feature = "native-git")]
+    Native(git2::Error),
+}
+
+#[cfg(feature = "native-git")]
+impl From<git2::Error> for GitError {
+    fn from(e: git2::Error) -> Self {
+        Self::Native(e)
     }
 }
 
-pub fn git_pull() {
-    Command::new("git").args(["pull"]).output().unwrap();
+impl fmt::Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Spawn(e) => write!(f, "failed to run git: {e}"),
+            Self::Failed { args, code, stderr } => {
+                let code = code.map_or("signal".to_string(), |code| code.to_string());
+                write!(f, "git {args} failed ({code}): {}", stderr.trim())
+            }
+            Self::Io(e) => write!(f, "{e}"),
+            #[cfg(feature = "native-git")]
+            Self::Native(e) => write!(f, "git: {}", e.message()),
+        }
+    }
 }
 
-pub fn git_stuff(git_remote: &Option<String>) {
-    std::env::set_current_dir(get_share_path()).unwrap();
+/// What a sync did, so it can be shown instead of failing silently.
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncReport {
+    pub committed: bool,
+    pub pulled: bool,
+    pub pushed: bool,
+    /// Card files that conflicted and were merged.
+    pub merged: Vec<PathBuf>,
+    /// Files we couldn't merge, the merge was aborted so the remote changes aren't in yet.
+    pub conflicts: Vec<PathBuf>,
+    /// Files copied to and from a sync folder, deletions included.
+    pub sent: usize,
+    pub received: usize,
+    /// Files both we and the sync folder changed, where the newest one was kept.
+    pub kept_newest: Vec<PathBuf>,
+}
 
-    // Initiate git
-    Command::new("git").arg("init").output().unwrap();
+impl SyncReport {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
 
-    if let Some(git_remote) = git_remote {
-        // Check if the remote repository is already set
-        let remote_check_output = Command::new("git")
-            .args(["remote", "get-url", "origin"])
-            .output()
-            .unwrap();
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        if self.committed {
+            parts.push("committed".to_string());
+        }
+        if self.pulled {
+            parts.push("pulled".to_string());
+        }
+        if !self.merged.is_empty() {
+            parts.push(format!("merged {} cards", self.merged.len()));
+        }
+        if self.pushed {
+            parts.push("pushed".to_string());
+        }
+        if self.sent > 0 {
+            parts.push(format!("sent {} files", self.sent));
+        }
+        if self.received > 0 {
+            parts.push(format!("received {} files", self.received));
+        }
+        if parts.is_empty() {
+            parts.push("nothing to sync".to_string());
+        }
+        write!(f, "{}", parts.join(", "))?;
+
+        if !self.kept_newest.is_empty() {
+            write!(f, "\nchanged on both sides, kept the newest:")?;
+            for path in &self.kept_newest {
+                write!(f, "\n    {}", path.display())?;
+            }
+        }
+        if !self.is_clean() {
+            write!(f, "\ncouldn't merge, fix these by hand:")?;
+            for path in &self.conflicts {
+                write!(f, "\n    {}", path.display())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Something done to the cards, for the commit message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Add {
+        front: String,
+        category: Category,
+    },
+    Review {
+        front: String,
+        category: Category,
+    },
+    Move {
+        front: String,
+        from: Category,
+        to: Category,
+    },
+    Delete {
+        front: String,
+    },
+    Edit {
+        front: String,
+    },
+    Other(String),
+}
+
+static CHANGES: Mutex<Vec<Change>> = Mutex::new(vec![]);
+
+/// Remembers the change for the next commit.
+pub fn note(change: Change) {
+    CHANGES.lock().unwrap().push(change);
+}
+
+fn quoted(front: &str) -> String {
+    format!("'{}'", truncate_string(front.to_string(), 40))
+}
+
+/// Deepest category all of them are in.
+fn common_category<'a>(mut categories: impl Iterator<Item = &'a Category>) -> Category {
+    let Some(first) = categories.next() else {
+        return Category::default();
+    };
+    let mut common = first.0.clone();
+    for category in categories {
+        let shared = common
+            .iter()
+            .zip(&category.0)
+            .take_while(|(a, b)| a == b)
+            .count();
+        common.truncate(shared);
+    }
+    Category(common)
+}
+
+/// One line per kind of change, like "review: 12 cards in /maths/calculus". The first lines
+/// make up the subject, the rest of the message lists all of them.
+fn commit_message(changes: &[Change]) -> Option<String> {
+    let mut lines = vec![];
+
+    let adds: Vec<_> = changes
+        .iter()
+        .filter_map(|change| match change {
+            Change::Add { front, category } => Some((front, category)),
+            _ => None,
+        })
+        .collect();
+    let reviews: Vec<_> = changes
+        .iter()
+        .filter_map(|change| match change {
+            Change::Review { front, category } => Some((front, category)),
+            _ => None,
+        })
+        .collect();
+    for (verb, cards) in [("add", adds), ("review", reviews)] {
+        match cards.as_slice() {
+            [] => {}
+            [(front, _)] => lines.push(format!("{verb}: {}", quoted(front))),
+            cards => {
+                let category = common_category(cards.iter().map(|(_, category)| *category));
+                lines.push(format!(
+                    "{verb}: {} cards in {}",
+                    cards.len(),
+                    category.print_full()
+                ));
+            }
+        }
+    }
+
+    let mut moves = vec![];
+    let mut deletes = vec![];
+    let mut edits = vec![];
+    for change in changes {
+        match change {
+            Change::Move { front, from, to } => moves.push(format!(
+                "{} {} → {}",
+                quoted(front),
+                from.print_full(),
+                to.print_full()
+            )),
+            Change::Delete { front } => deletes.push(quoted(front)),
+            Change::Edit { front } => edits.push(quoted(front)),
+            Change::Other(line) => lines.push(line.clone()),
+            Change::Add { .. } | Change::Review { .. } => {}
+        }
+    }
+    for (verb, cards) in [("move", moves), ("delete", deletes), ("edit", edits)] {
+        match cards.as_slice() {
+            [] => {}
+            [card] => lines.push(format!("{verb}: {card}")),
+            cards => lines.push(format!("{verb}: {} cards", cards.len())),
+        }
+    }
+
+    let subject = match lines.as_slice() {
+        [] => return None,
+        [line] => return Some(line.clone()),
+        lines if lines.join("; ").chars().count() <= 72 => lines.join("; "),
+        [first, ..] => format!("{first}; ..."),
+    };
+    Some(format!("{subject}\n\n{}", lines.join("\n")))
+}
+
+/// Keeps machine-local files like the card index and undo journal out of the repo.
+fn ensure_gitignore(dir: &Path) {
+    let path = dir.join(".gitignore");
+    let mut content = std::fs::read_to_string(&path).unwrap_or_default();
+    let original_len = content.len();
+
+    for local in [get_index_path(), get_journal_path(), get_sync_state_path()] {
+        let name = local.file_name().unwrap().to_string_lossy().to_string();
+        if !content.lines().any(|line| line == name) {
+            if !content.is_empty() && !content.ends_with('\n') {
+                content.push('\n');
+            }
+            content.push_str(&name);
+            content.push('\n');
+        }
+    }
+
+    if content.len() != original_len {
+        std::fs::write(path, content).unwrap();
+    }
+}
+
+/// A file both sides changed, with its content at the merge base, ours and theirs.
+/// None if the file doesn't exist on that side.
+pub struct Conflict {
+    pub path: String,
+    pub base: Option<String>,
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+}
+
+pub enum Merge {
+    /// Nothing pushed to this branch yet.
+    NoRemoteBranch,
+    UpToDate,
+    Merged,
+    /// The merge is in progress until `finish_merge` or `abort_merge`.
+    Conflicts(Vec<Conflict>),
+}
+
+/// The git operations sync needs, from the git binary or from libgit2 with the `native-git`
+/// feature. Paths are relative to the repo.
+pub trait Backend {
+    /// Stages everything and commits it, false if there was nothing to commit.
+    fn commit_all(&self, message: &str) -> Result<bool, GitError>;
+    fn has_changes(&self) -> Result<bool, GitError>;
+    fn has_commits(&self) -> bool;
+    fn set_remote(&self, url: &str) -> Result<(), GitError>;
+    /// The checked out branch, which might not have any commits yet.
+    fn branch(&self) -> Result<String, GitError>;
+    fn fetch(&self) -> Result<(), GitError>;
+    /// Merges `origin/<branch>` into the checked out branch.
+    fn merge_remote(&self, branch: &str) -> Result<Merge, GitError>;
+    /// Writes the resolved files and commits the merge.
+    fn finish_merge(&self, resolved: &[(String, String)]) -> Result<(), GitError>;
+    fn abort_merge(&self) -> Result<(), GitError>;
+    fn push(&self, branch: &str) -> Result<(), GitError>;
+    fn history(&self, path: &str) -> Result<Vec<Version>, GitError>;
+    fn show_version(&self, version: &Version) -> Result<String, GitError>;
+}
+
+/// Opens the repo in `dir`, creating it if needed.
+pub fn open(dir: &Path) -> Result<Box<dyn Backend>, GitError> {
+    #[cfg(feature = "native-git")]
+    return Ok(Box::new(crate::git_native::Native::open(dir)?));
+    #[cfg(not(feature = "native-git"))]
+    Ok(Box::new(crate::git_cli::Cli::open(dir)?))
+}
+
+/// Card files are merged with `Card::merge`, anything else is left for the user. So are
+/// files in hidden folders like the trash, which hold more than the card.
+fn resolve(conflict: &Conflict) -> Option<String> {
+    let is_card = conflict.path.starts_with("cards/") && conflict.path.ends_with(".toml");
+    let is_hidden = conflict
+        .path
+        .split('/')
+        .any(|folder| folder.starts_with('_'));
+    if !is_card || is_hidden {
+        return None;
+    }
+    let parse = |content: &Option<String>| toml::from_str::<Card>(content.as_deref()?).ok();
+    let ours = parse(&conflict.ours)?;
+    let theirs = parse(&conflict.theirs)?;
+    let base = parse(&conflict.base);
+    toml::to_string(&ours.merge(&theirs, base.as_ref())).ok()
+}
+
+/// Commits local changes, merges in the remote and pushes the result.
+pub fn sync_with(
+    repo: &dyn Backend,
+    dir: &Path,
+    remote: Option<&str>,
+    message: &str,
+) -> Result<SyncReport, GitError> {
+    let mut report = SyncReport::default();
+    ensure_gitignore(dir);
+    report.committed = repo.commit_all(message)?;
+
+    let Some(remote) = remote else {
+        return Ok(report);
+    };
+    repo.set_remote(remote)?;
+    let branch = repo.branch()?;
+    repo.fetch()?;
+
+    match repo.merge_remote(&branch)? {
+        Merge::NoRemoteBranch | Merge::UpToDate => {}
+        Merge::Merged => report.pulled = true,
+        Merge::Conflicts(conflicts) => {
+            let mut resolved = vec![];
+            for conflict in conflicts {
+                match resolve(&conflict) {
+                    Some(content) => {
+                        report.merged.push(PathBuf::from(&conflict.path));
+                        resolved.push((conflict.path, content));
+                    }
+                    None => report.conflicts.push(PathBuf::from(conflict.path)),
+                }
+            }
+
+            if report.is_clean() {
+                repo.finish_merge(&resolved)?;
+                report.pulled = true;
+            } else {
+                report.merged.clear();
+                repo.abort_merge()?;
+            }
+        }
+    }
+
+    if report.is_clean() && repo.has_commits() {
+        repo.push(&branch)?;
+        report.pushed = true;
+    }
+    Ok(report)
+}
+
+/// A commit that changed a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Version {
+    pub commit: String,
+    pub date: String,
+    pub message: String,
+    /// Where the file was at that commit, relative to the repo.
+    pub path: PathBuf,
+}
+
+/// Commits that changed the file, newest first, following it across moves.
+pub fn history(dir: &Path, path: &Path) -> Result<Vec<Version>, GitError> {
+    let path = path.strip_prefix(dir).unwrap_or(path).to_string_lossy();
+    open(dir)?.history(&path)
+}
+
+/// The file as it was at that version.
+pub fn show_version(dir: &Path, version: &Version) -> Result<String, GitError> {
+    open(dir)?.show_version(version)
+}
+
+/// Where to sync to, from `sync_target` in the config or else `git_remote`.
+#[derive(Debug, PartialEq)]
+pub enum SyncTarget {
+    /// A url, or the path of a repo on this machine.
+    Git(String),
+    /// A plain folder, synced file by file.
+    Folder(PathBuf),
+}
+
+impl SyncTarget {
+    pub fn parse(target: &str) -> Self {
+        let is_url = target.contains("://") || (target.contains('@') && target.contains(':'));
+        if is_url {
+            return Self::Git(target.to_string());
+        }
 
-        if remote_check_output.status.success() {
-            git_pull();
+        let path = match target.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+            None => PathBuf::from(target),
+        };
+        let is_bare = path.join("HEAD").is_file() && path.join("objects").is_dir();
+        if is_bare || path.join(".git").exists() {
+            Self::Git(path.to_string_lossy().into_owned())
         } else {
-            // Set the remote repository
-            Command::new("git")
-                .args(["remote", "add", "origin", git_remote])
-                .output()
-                .unwrap();
+            Self::Folder(path)
         }
     }
 
-    git_save(git_remote.is_some());
+    pub fn from_config(config: &Config) -> Option<Self> {
+        match (&config.sync_target, config.read_git_remote()) {
+            (Some(target), _) => Some(Self::parse(target)),
+            (None, Some(remote)) => Some(Self::Git(remote.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+fn sync_target(
+    repo: &dyn Backend,
+    dir: &Path,
+    target: Option<&SyncTarget>,
+    message: &str,
+) -> Result<SyncReport, GitError> {
+    let Some(SyncTarget::Folder(folder)) = target else {
+        let remote = match target {
+            Some(SyncTarget::Git(remote)) => Some(remote.as_str()),
+            _ => None,
+        };
+        return sync_with(repo, dir, remote, message);
+    };
+
+    // Committing first keeps the history, the folder only has the latest version.
+    let mut report = sync_with(repo, dir, None, message)?;
+    let folder_report = sync_folder(dir, folder, &get_sync_state_path()).map_err(GitError::Io)?;
+    let received = format!(
+        "sync: received {} files from {}",
+        folder_report.received,
+        folder.display()
+    );
+    repo.commit_all(&received)?;
+
+    report.sent = folder_report.sent;
+    report.received = folder_report.received;
+    report.merged = folder_report.merged;
+    report.kept_newest = folder_report.kept_newest;
+    Ok(report)
+}
+
+/// Problems from background syncs, for the interface to show when it gets the chance.
+static PROBLEMS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+/// How the last background sync went, for the main menu.
+static STATUS: Mutex<Option<String>> = Mutex::new(None);
+
+/// Syncs the share folder with the target from the config.
+pub fn sync_share() -> Result<SyncReport, GitError> {
+    let target = Config::load()
+        .ok()
+        .and_then(|config| SyncTarget::from_config(&config));
+    let dir = get_share_path();
+    let repo = open(&dir)?;
+    let changes = std::mem::take(&mut *CHANGES.lock().unwrap());
+    let message = commit_message(&changes).unwrap_or_else(|| "save".to_string());
+    let result = sync_target(repo.as_ref(), &dir, target.as_ref(), &message);
+    let uncommitted = repo.has_changes().unwrap_or(false);
+    if result.is_err() && uncommitted {
+        // They belong in the next commit then.
+        let mut pending = CHANGES.lock().unwrap();
+        let later = std::mem::replace(&mut *pending, changes);
+        pending.extend(later);
+    }
+    result
+}
+
+pub fn sync_in_background() {
+    *STATUS.lock().unwrap() = Some("syncing...".to_string());
+    std::thread::spawn(|| {
+        let (status, problem) = match sync_share() {
+            Ok(report) if report.is_clean() => (format!("last sync: {report}"), None),
+            Ok(report) => (
+                "last sync: couldn't merge".to_string(),
+                Some(report.to_string()),
+            ),
+            Err(e) => (
+                "last sync failed".to_string(),
+                Some(format!("sync failed: {e}")),
+            ),
+        };
+        let status = status.lines().next().unwrap_or_default().to_string();
+        *STATUS.lock().unwrap() = Some(status);
+        PROBLEMS.lock().unwrap().extend(problem);
+    });
+}
+
+pub fn status() -> Option<String> {
+    STATUS.lock().unwrap().clone()
+}
+
+pub fn take_problems() -> Vec<String> {
+    std::mem::take(&mut *PROBLEMS.lock().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_cli::{git, Cli};
+    use crate::paths::TempDir;
+
+    type Opener = fn(&Path) -> Result<Box<dyn Backend>, GitError>;
+
+    /// Every backend that's compiled in, so they all get the same tests.
+    fn backends() -> Vec<Opener> {
+        vec![
+            |dir| Ok(Box::new(Cli::open(dir)?)),
+            #[cfg(feature = "native-git")]
+            |dir| Ok(Box::new(crate::git_native::Native::open(dir)?)),
+        ]
+    }
+
+    fn temp_dir(name: &str) -> TempDir {
+        TempDir::new(&format!("git-{name}"))
+    }
+
+    /// A repo with an identity, so commits work without a global git config.
+    fn repo(name: &str) -> TempDir {
+        let dir = temp_dir(name);
+        git(&dir, &["init", "-b", "main"]).unwrap();
+        git(&dir, &["config", "user.name", name]).unwrap();
+        git(&dir, &["config", "user.email", "speki@localhost"]).unwrap();
+        dir
+    }
+
+    fn bare_repo() -> TempDir {
+        let bare = temp_dir("bare");
+        git(&bare, &["init", "--bare", "-b", "main"]).unwrap();
+        bare
+    }
+
+    fn sync(open: Opener, dir: &Path, remote: &Path) -> SyncReport {
+        let repo = open(dir).unwrap();
+        sync_with(repo.as_ref(), dir, Some(remote.to_str().unwrap()), "save").unwrap()
+    }
+
+    fn write_card(dir: &Path, card: &Card) {
+        std::fs::create_dir_all(dir.join("cards")).unwrap();
+        std::fs::write(dir.join("cards/card.toml"), toml::to_string(card).unwrap()).unwrap();
+    }
+
+    fn read_card(dir: &Path) -> Card {
+        toml::from_str(&std::fs::read_to_string(dir.join("cards/card.toml")).unwrap()).unwrap()
+    }
+
+    fn review(secs: u64) -> crate::card::Review {
+        crate::card::Review {
+            timestamp: std::time::Duration::from_secs(secs),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sync_merges_card_conflicts() {
+        for open in backends() {
+            let bare = bare_repo();
+            let laptop = repo("laptop");
+            let desktop = repo("desktop");
+            sync(open, &laptop, &bare);
+            sync(open, &desktop, &bare);
+
+            let mut card = Card::new_simple("front".into(), "back".into());
+            write_card(&laptop, &card);
+            let report = sync(open, &laptop, &bare);
+            assert!(report.committed && report.pushed);
+            assert!(sync(open, &desktop, &bare).pulled);
+
+            card.history.add_review(review(1));
+            write_card(&laptop, &card);
+            let mut other = read_card(&desktop);
+            other.history.add_review(review(2));
+            other.meta.tags.insert("desktop".into());
+            write_card(&desktop, &other);
+
+            sync(open, &laptop, &bare);
+            let report = sync(open, &desktop, &bare);
+            assert_eq!(report.merged, vec![PathBuf::from("cards/card.toml")]);
+            assert!(report.is_clean() && report.pushed);
+
+            let merged = read_card(&desktop);
+            assert_eq!(merged.history, vec![review(1), review(2)].into());
+            assert!(merged.meta.tags.contains("desktop"));
+
+            sync(open, &laptop, &bare);
+            assert_eq!(read_card(&laptop), merged);
+        }
+    }
+
+    #[test]
+    fn test_resolve_leaves_hidden_folders() {
+        let card = toml::to_string(&Card::new_simple("front".into(), "back".into())).unwrap();
+        let conflict = |path: &str| Conflict {
+            path: path.to_string(),
+            base: None,
+            ours: Some(card.clone()),
+            theirs: Some(card.clone()),
+        };
+        assert!(resolve(&conflict("cards/maths/card.toml")).is_some());
+        assert!(resolve(&conflict("cards/_trash/card.toml")).is_none());
+    }
+
+    #[test]
+    fn test_sync_merges_non_ascii_paths() {
+        // Category folders come from deck names, which can be anything.
+        let path = "cards/español/café.toml";
+        let write = |dir: &Path, card: &Card| {
+            std::fs::create_dir_all(dir.join("cards/español")).unwrap();
+            std::fs::write(dir.join(path), toml::to_string(card).unwrap()).unwrap();
+        };
+        let read = |dir: &Path| -> Card {
+            toml::from_str(&std::fs::read_to_string(dir.join(path)).unwrap()).unwrap()
+        };
+
+        for open in backends() {
+            let bare = bare_repo();
+            let laptop = repo("laptop");
+            let desktop = repo("desktop");
+
+            let mut card = Card::new_simple("café".into(), "coffee".into());
+            write(&laptop, &card);
+            sync(open, &laptop, &bare);
+            assert!(sync(open, &desktop, &bare).pulled);
+
+            card.history.add_review(review(1));
+            write(&laptop, &card);
+            let mut other = read(&desktop);
+            other.history.add_review(review(2));
+            write(&desktop, &other);
+
+            sync(open, &laptop, &bare);
+            let report = sync(open, &desktop, &bare);
+            assert_eq!(report.merged, vec![PathBuf::from(path)]);
+            assert!(report.is_clean() && report.pushed);
+            assert_eq!(read(&desktop).history, vec![review(1), review(2)].into());
+        }
+    }
+
+    #[test]
+    fn test_sync_reports_unmergeable() {
+        for open in backends() {
+            let bare = bare_repo();
+            let laptop = repo("laptop");
+            let desktop = repo("desktop");
+            sync(open, &laptop, &bare);
+            sync(open, &desktop, &bare);
+
+            std::fs::write(laptop.join("notes"), "laptop").unwrap();
+            sync(open, &laptop, &bare);
+            std::fs::write(desktop.join("notes"), "desktop").unwrap();
+            let report = sync(open, &desktop, &bare);
+
+            assert_eq!(report.conflicts, vec![PathBuf::from("notes")]);
+            assert!(!report.pushed);
+            // The merge was aborted, so the local version is untouched.
+            assert_eq!(
+                std::fs::read_to_string(desktop.join("notes")).unwrap(),
+                "desktop"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sync_any_branch() {
+        for open in backends() {
+            let bare = bare_repo();
+            let dir = repo("branch");
+            git(&dir, &["checkout", "-b", "cards"]).unwrap();
+            write_card(&dir, &Card::new_simple("front".into(), "back".into()));
+            assert!(sync(open, &dir, &bare).pushed);
+            git(&bare, &["rev-parse", "--verify", "cards"]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_commit_message() {
+        let calculus = Category::from_joined("maths/calculus");
+        let review = |front: &str, category: &Category| Change::Review {
+            front: front.into(),
+            category: category.clone(),
+        };
+        assert_eq!(commit_message(&[]), None);
+        assert_eq!(
+            commit_message(&[review("what is a limit", &calculus)]).unwrap(),
+            "review: 'what is a limit'"
+        );
+
+        let changes = [
+            review("a", &calculus),
+            review("b", &Category::from_joined("maths/calculus/limits")),
+            Change::Add {
+                front: "what is a span".into(),
+                category: calculus.clone(),
+            },
+        ];
+        assert_eq!(
+            commit_message(&changes).unwrap(),
+            "add: 'what is a span'; review: 2 cards in /maths/calculus\n\n\
+             add: 'what is a span'\n\
+             review: 2 cards in /maths/calculus"
+        );
+    }
+
+    #[test]
+    fn test_history_follows_moves() {
+        for open in backends() {
+            let dir = repo("history");
+            let repo = open(&dir).unwrap();
+
+            let mut card = Card::new_simple("front".into(), "back".into());
+            write_card(&dir, &card);
+            repo.commit_all("add: 'front'").unwrap();
+            card.back.text = "better back".into();
+            write_card(&dir, &card);
+            repo.commit_all("edit: 'front'").unwrap();
+            std::fs::create_dir_all(dir.join("cards/maths")).unwrap();
+            std::fs::rename(
+                dir.join("cards/card.toml"),
+                dir.join("cards/maths/card.toml"),
+            )
+            .unwrap();
+            repo.commit_all("move: 'front' / → /maths").unwrap();
+
+            let versions = repo.history("cards/maths/card.toml").unwrap();
+            let messages: Vec<_> = versions.iter().map(|v| v.message.as_str()).collect();
+            assert_eq!(
+                messages,
+                ["move: 'front' / → /maths", "edit: 'front'", "add: 'front'"]
+            );
+            assert_eq!(versions[2].path, PathBuf::from("cards/card.toml"));
+
+            let old: Card = toml::from_str(&repo.show_version(&versions[2]).unwrap()).unwrap();
+            assert_eq!(old.back.text, "back");
+        }
+    }
+
+    #[test]
+    fn test_sync_target() {
+        let url = "git@github.com:someone/cards.git";
+        assert_eq!(SyncTarget::parse(url), SyncTarget::Git(url.to_string()));
+        let url = "https://github.com/someone/cards";
+        assert_eq!(SyncTarget::parse(url), SyncTarget::Git(url.to_string()));
+
+        let bare = bare_repo();
+        let path = bare.to_str().unwrap();
+        assert_eq!(SyncTarget::parse(path), SyncTarget::Git(path.to_string()));
+        let checkout = repo("checkout");
+        let path = checkout.to_str().unwrap();
+        assert_eq!(SyncTarget::parse(path), SyncTarget::Git(path.to_string()));
+
+        let stick = temp_dir("stick");
+        let path = stick.to_str().unwrap();
+        assert_eq!(
+            SyncTarget::parse(path),
+            SyncTarget::Folder(stick.to_path_buf())
+        );
+    }
+
+    #[test]
+    fn test_git_error() {
+        let dir = temp_dir("error");
+        let err = git(&dir, &["rev-parse", "HEAD"]).unwrap_err();
+        assert!(matches!(err, GitError::Failed { code: Some(_), .. }));
+    }
 }
@@ -0,0 +1,181 @@
+//! The sync backend that runs the git binary, the default without the `native-git` feature.
+#![cfg_attr(feature = "native-git", allow(dead_code))]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::git::{Backend, Conflict, GitError, Merge, Version};
+
+/// Paths come out unquoted and messages in English, so they can be read back.
+pub fn git(dir: &Path, args: &[&str]) -> Result<String, GitError> {
+    let output = Command::new("git")
+        .env("LC_ALL", "C")
+        .arg("-C")
+        .arg(dir)
+        .args(["-c", "core.quotePath=false"])
+        .args(args)
+        .output()
+        .map_err(GitError::Spawn)?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(GitError::Failed {
+            args: args.join(" "),
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
+
+/// Runs the git binary in the repo.
+pub struct Cli {
+    dir: PathBuf,
+}
+
+impl Cli {
+    pub fn open(dir: &Path) -> Result<Self, GitError> {
+        if !dir.join(".git").exists() {
+            git(dir, &["init"])?;
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String, GitError> {
+        git(&self.dir, args)
+    }
+
+    /// The version of a conflicted file at a merge stage: 1 is the base, 2 ours and 3 theirs.
+    fn stage(&self, stage: u8, path: &str) -> Option<String> {
+        self.git(&["show", &format!(":{stage}:{path}")]).ok()
+    }
+}
+
+impl Backend for Cli {
+    fn commit_all(&self, message: &str) -> Result<bool, GitError> {
+        self.git(&["add", "-A"])?;
+        if !self.has_changes()? {
+            return Ok(false);
+        }
+        self.git(&["commit", "-m", message])?;
+        Ok(true)
+    }
+
+    fn has_changes(&self) -> Result<bool, GitError> {
+        Ok(!self.git(&["status", "--porcelain"])?.trim().is_empty())
+    }
+
+    fn has_commits(&self) -> bool {
+        self.git(&["rev-parse", "--verify", "--quiet", "HEAD"])
+            .is_ok()
+    }
+
+    fn set_remote(&self, url: &str) -> Result<(), GitError> {
+        match self.git(&["remote", "get-url", "origin"]) {
+            Ok(current) if current.trim() == url => Ok(()),
+            Ok(_) => self.git(&["remote", "set-url", "origin", url]).map(|_| ()),
+            Err(_) => self.git(&["remote", "add", "origin", url]).map(|_| ()),
+        }
+    }
+
+    fn branch(&self) -> Result<String, GitError> {
+        Ok(self
+            .git(&["symbolic-ref", "--short", "HEAD"])?
+            .trim()
+            .to_string())
+    }
+
+    fn fetch(&self) -> Result<(), GitError> {
+        self.git(&["fetch", "origin"]).map(|_| ())
+    }
+
+    fn merge_remote(&self, branch: &str) -> Result<Merge, GitError> {
+        let remote_branch = format!("origin/{branch}");
+        if self
+            .git(&["rev-parse", "--verify", "--quiet", &remote_branch])
+            .is_err()
+        {
+            return Ok(Merge::NoRemoteBranch);
+        }
+
+        let head = || self.git(&["rev-parse", "--verify", "--quiet", "HEAD"]).ok();
+        let before = head();
+        let merge_error = match self.git(&[
+            "merge",
+            "--no-edit",
+            "--allow-unrelated-histories",
+            &remote_branch,
+        ]) {
+            Ok(_) if head() == before => return Ok(Merge::UpToDate),
+            Ok(_) => return Ok(Merge::Merged),
+            Err(e) => e,
+        };
+
+        let conflicted = self.git(&["diff", "--name-only", "-z", "--diff-filter=U"])?;
+        let conflicts: Vec<Conflict> = conflicted
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(|path| Conflict {
+                path: path.to_string(),
+                base: self.stage(1, path),
+                ours: self.stage(2, path),
+                theirs: self.stage(3, path),
+            })
+            .collect();
+        if conflicts.is_empty() {
+            // Failed for some other reason than conflicts.
+            return Err(merge_error);
+        }
+        Ok(Merge::Conflicts(conflicts))
+    }
+
+    fn finish_merge(&self, resolved: &[(String, String)]) -> Result<(), GitError> {
+        for (path, content) in resolved {
+            std::fs::write(self.dir.join(path), content).map_err(GitError::Io)?;
+            self.git(&["add", path])?;
+        }
+        self.git(&["commit", "--no-edit"]).map(|_| ())
+    }
+
+    fn abort_merge(&self) -> Result<(), GitError> {
+        self.git(&["merge", "--abort"]).map(|_| ())
+    }
+
+    fn push(&self, branch: &str) -> Result<(), GitError> {
+        self.git(&["push", "-u", "origin", branch]).map(|_| ())
+    }
+
+    fn history(&self, path: &str) -> Result<Vec<Version>, GitError> {
+        let log = self.git(&[
+            "log",
+            "--follow",
+            "--name-only",
+            "--date=short",
+            "--format=%x00%h%x09%ad%x09%s",
+            "--",
+            path,
+        ])?;
+
+        let versions = log
+            .split('\0')
+            .filter_map(|entry| {
+                let mut lines = entry.lines().filter(|line| !line.is_empty());
+                let mut header = lines.next()?.splitn(3, '\t');
+                Some(Version {
+                    commit: header.next()?.to_string(),
+                    date: header.next()?.to_string(),
+                    message: header.next().unwrap_or_default().to_string(),
+                    path: PathBuf::from(lines.next()?),
+                })
+            })
+            .collect();
+        Ok(versions)
+    }
+
+    fn show_version(&self, version: &Version) -> Result<String, GitError> {
+        let object = format!("{}:{}", version.commit, version.path.to_string_lossy());
+        self.git(&["show", &object])
+    }
+}
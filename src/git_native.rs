@@ -0,0 +1,325 @@
+//! The sync backend on top of libgit2, so sync doesn't need a git binary on the PATH.
+//!
+//! Credentials come from the ssh agent or git's credential helpers, like they would for the
+//! git binary.
+
+use std::path::{Path, PathBuf};
+
+use git2::{
+    build::CheckoutBuilder, Cred, CredentialType, DiffFindOptions, FetchOptions, IndexAddOption,
+    ObjectType, PushOptions, RemoteCallbacks, Repository, ResetType, Sort, StatusOptions,
+};
+
+use crate::git::{Backend, Conflict, GitError, Merge, Version};
+
+pub struct Native {
+    repo: Repository,
+}
+
+impl Native {
+    pub fn open(dir: &Path) -> Result<Self, GitError> {
+        let repo = match Repository::open(dir) {
+            Ok(repo) => repo,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => Repository::init(dir)?,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { repo })
+    }
+
+    fn workdir(&self) -> &Path {
+        self.repo.workdir().expect("sync repo isn't bare")
+    }
+
+    fn callbacks(&self) -> RemoteCallbacks<'_> {
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(|url, username, allowed| {
+            if allowed.contains(CredentialType::SSH_KEY) {
+                Cred::ssh_key_from_agent(username.unwrap_or("git"))
+            } else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                Cred::credential_helper(&self.repo.config()?, url, username)
+            } else {
+                Cred::default()
+            }
+        });
+        callbacks
+    }
+
+    fn blob(&self, entry: Option<&git2::IndexEntry>) -> Option<String> {
+        let blob = self.repo.find_blob(entry?.id).ok()?;
+        Some(String::from_utf8_lossy(blob.content()).into_owned())
+    }
+
+    /// Commits the index with HEAD and `others` as parents.
+    fn commit_index(&self, message: &str, others: &[&git2::Commit]) -> Result<(), GitError> {
+        let mut index = self.repo.index()?;
+        let tree = self.repo.find_tree(index.write_tree()?)?;
+        index.write()?;
+        let signature = self.repo.signature()?;
+
+        let head = self
+            .repo
+            .head()
+            .ok()
+            .and_then(|head| head.peel_to_commit().ok());
+        let mut parents: Vec<&git2::Commit> = head.iter().collect();
+        parents.extend(others);
+        self.repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )?;
+        Ok(())
+    }
+
+    fn remote_commit(&self, branch: &str) -> Option<git2::Commit<'_>> {
+        let reference = self
+            .repo
+            .find_reference(&format!("refs/remotes/origin/{branch}"))
+            .ok()?;
+        reference.peel_to_commit().ok()
+    }
+}
+
+/// Days since the unix epoch to a yyyy-mm-dd date, from Howard Hinnant's date algorithms.
+fn format_date(seconds: i64) -> String {
+    let days = seconds.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+impl Backend for Native {
+    fn commit_all(&self, message: &str) -> Result<bool, GitError> {
+        let mut index = self.repo.index()?;
+        index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+        index.update_all(["*"], None)?;
+        index.write()?;
+
+        let tree = index.write_tree()?;
+        let unchanged = self
+            .repo
+            .head()
+            .ok()
+            .and_then(|head| head.peel_to_tree().ok())
+            .is_some_and(|head| head.id() == tree);
+        if unchanged {
+            return Ok(false);
+        }
+        self.commit_index(message, &[])?;
+        Ok(true)
+    }
+
+    fn has_changes(&self) -> Result<bool, GitError> {
+        let mut options = StatusOptions::new();
+        options.include_untracked(true).include_ignored(false);
+        Ok(!self.repo.statuses(Some(&mut options))?.is_empty())
+    }
+
+    fn has_commits(&self) -> bool {
+        self.repo.head().is_ok()
+    }
+
+    fn set_remote(&self, url: &str) -> Result<(), GitError> {
+        match self.repo.find_remote("origin") {
+            Ok(remote) if remote.url() == Some(url) => {}
+            Ok(_) => self.repo.remote_set_url("origin", url)?,
+            Err(_) => {
+                self.repo.remote("origin", url)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn branch(&self) -> Result<String, GitError> {
+        // HEAD points at the branch even when it has no commits yet.
+        let head = self.repo.find_reference("HEAD")?;
+        let target = head.symbolic_target().unwrap_or("refs/heads/main");
+        Ok(target.trim_start_matches("refs/heads/").to_string())
+    }
+
+    fn fetch(&self) -> Result<(), GitError> {
+        let mut options = FetchOptions::new();
+        options.remote_callbacks(self.callbacks());
+        self.repo.find_remote("origin")?.fetch(
+            &["+refs/heads/*:refs/remotes/origin/*"],
+            Some(&mut options),
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn merge_remote(&self, branch: &str) -> Result<Merge, GitError> {
+        let Some(theirs) = self.remote_commit(branch) else {
+            return Ok(Merge::NoRemoteBranch);
+        };
+        let annotated = self.repo.find_annotated_commit(theirs.id())?;
+        let (analysis, _) = self.repo.merge_analysis(&[&annotated])?;
+
+        if analysis.is_up_to_date() {
+            return Ok(Merge::UpToDate);
+        }
+        if analysis.is_unborn() || analysis.is_fast_forward() {
+            self.repo
+                .checkout_tree(theirs.as_object(), Some(CheckoutBuilder::new().safe()))?;
+            self.repo.reference(
+                &format!("refs/heads/{branch}"),
+                theirs.id(),
+                true,
+                "fast-forward",
+            )?;
+            self.repo.set_head(&format!("refs/heads/{branch}"))?;
+            return Ok(Merge::Merged);
+        }
+
+        self.repo.merge(&[&annotated], None, None)?;
+        let index = self.repo.index()?;
+        if !index.has_conflicts() {
+            self.commit_index(&format!("Merge origin/{branch}"), &[&theirs])?;
+            self.repo.cleanup_state()?;
+            return Ok(Merge::Merged);
+        }
+
+        let conflicts = index
+            .conflicts()?
+            .filter_map(Result::ok)
+            .filter_map(|conflict| {
+                let entry = conflict.our.as_ref().or(conflict.their.as_ref())?;
+                Some(Conflict {
+                    path: String::from_utf8_lossy(&entry.path).into_owned(),
+                    base: self.blob(conflict.ancestor.as_ref()),
+                    ours: self.blob(conflict.our.as_ref()),
+                    theirs: self.blob(conflict.their.as_ref()),
+                })
+            })
+            .collect();
+        Ok(Merge::Conflicts(conflicts))
+    }
+
+    fn finish_merge(&self, resolved: &[(String, String)]) -> Result<(), GitError> {
+        let mut index = self.repo.index()?;
+        for (path, content) in resolved {
+            std::fs::write(self.workdir().join(path), content).map_err(GitError::Io)?;
+            index.add_path(Path::new(path))?;
+        }
+        index.write()?;
+
+        let merge_head = self.repo.find_reference("MERGE_HEAD")?.peel_to_commit()?;
+        let message = self.repo.message().unwrap_or_else(|_| "Merge".to_string());
+        self.commit_index(message.trim(), &[&merge_head])?;
+        self.repo.cleanup_state()?;
+        Ok(())
+    }
+
+    fn abort_merge(&self) -> Result<(), GitError> {
+        // Everything was committed before merging, so going back to HEAD loses nothing.
+        let head = self.repo.head()?.peel(ObjectType::Commit)?;
+        self.repo.reset(&head, ResetType::Hard, None)?;
+        self.repo.cleanup_state()?;
+        Ok(())
+    }
+
+    fn push(&self, branch: &str) -> Result<(), GitError> {
+        let mut rejected = None;
+        let mut callbacks = self.callbacks();
+        callbacks.push_update_reference(|_, status| {
+            rejected = status.map(String::from);
+            Ok(())
+        });
+        let mut options = PushOptions::new();
+        options.remote_callbacks(callbacks);
+
+        let refspec = format!("refs/heads/{branch}:refs/heads/{branch}");
+        self.repo
+            .find_remote("origin")?
+            .push(&[&refspec], Some(&mut options))?;
+        drop(options);
+        if let Some(reason) = rejected {
+            return Err(git2::Error::from_str(&format!("push rejected: {reason}")).into());
+        }
+
+        self.repo
+            .find_branch(branch, git2::BranchType::Local)?
+            .set_upstream(Some(&format!("origin/{branch}")))?;
+        Ok(())
+    }
+
+    fn history(&self, path: &str) -> Result<Vec<Version>, GitError> {
+        let mut path = PathBuf::from(path);
+        let mut versions = vec![];
+        if !self.has_commits() {
+            return Ok(versions);
+        }
+
+        let mut walk = self.repo.revwalk()?;
+        walk.push_head()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+
+        for id in walk {
+            let commit = self.repo.find_commit(id?)?;
+            let parent = commit
+                .parents()
+                .next()
+                .map(|parent| parent.tree())
+                .transpose()?;
+            let mut diff =
+                self.repo
+                    .diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+            diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+
+            let delta = diff
+                .deltas()
+                .find(|delta| delta.new_file().path() == Some(path.as_path()));
+            let Some(delta) = delta else {
+                continue;
+            };
+
+            versions.push(Version {
+                commit: commit
+                    .as_object()
+                    .short_id()?
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                date: format_date(commit.time().seconds()),
+                message: commit.summary().unwrap_or_default().to_string(),
+                path: path.clone(),
+            });
+            if let Some(old_path) = delta.old_file().path() {
+                path = old_path.to_path_buf();
+            }
+        }
+        Ok(versions)
+    }
+
+    fn show_version(&self, version: &Version) -> Result<String, GitError> {
+        let commit = self
+            .repo
+            .revparse_single(&version.commit)?
+            .peel_to_commit()?;
+        let entry = commit.tree()?.get_path(&version.path)?;
+        let blob = entry.to_object(&self.repo)?.peel_to_blob()?;
+        Ok(String::from_utf8_lossy(blob.content()).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951782400), "2000-02-29");
+        assert_eq!(format_date(1792195200), "2026-10-17");
+    }
+}
@@ -0,0 +1,246 @@
+//! Showing card images in the terminal.
+//!
+//! Kitty and sixel graphics draw the real image in terminals that have them, anywhere else
+//! it's drawn with half blocks, two pixels per character cell.
+
+use std::fmt::Write;
+use std::io::Cursor;
+use std::path::Path;
+
+use base64::Engine;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageOutputFormat, RgbaImage};
+
+/// Roughly how many pixels a character cell is, for sizing the graphics protocols.
+const CELL_WIDTH: u32 = 10;
+const CELL_HEIGHT: u32 = 20;
+
+/// Kitty wants the image sent in pieces of at most this much base64.
+const KITTY_CHUNK: usize = 4096;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Protocol {
+    Kitty,
+    Sixel,
+    HalfBlock,
+}
+
+impl Protocol {
+    /// Guessed from the environment, terminals don't have a reliable way to ask without
+    /// reading the answer back from stdin.
+    pub fn detect() -> Self {
+        let var = |name: &str| std::env::var(name).unwrap_or_default().to_lowercase();
+        let (term, program) = (var("TERM"), var("TERM_PROGRAM"));
+        if std::env::var_os("KITTY_WINDOW_ID").is_some()
+            || term.contains("kitty")
+            || ["wezterm", "ghostty"].contains(&program.as_str())
+        {
+            Self::Kitty
+        } else if term.contains("sixel")
+            || term.starts_with("foot")
+            || term.starts_with("mlterm")
+            || program == "iterm.app"
+        {
+            Self::Sixel
+        } else {
+            Self::HalfBlock
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Option<DynamicImage> {
+    image::open(path).ok()
+}
+
+/// Scaled down to fit in `width` by `height` pixels, keeping its shape. Never scaled up.
+fn fit(image: &DynamicImage, width: u32, height: u32) -> RgbaImage {
+    let image = if image.width() > width || image.height() > height {
+        image.resize(width.max(1), height.max(1), FilterType::Triangle)
+    } else {
+        image.clone()
+    };
+    image.to_rgba8()
+}
+
+/// The escape codes that draw the image in at most `columns` by `rows` cells.
+pub fn render(image: &DynamicImage, protocol: Protocol, columns: u16, rows: u16) -> String {
+    let (columns, rows) = (u32::from(columns), u32::from(rows));
+    match protocol {
+        Protocol::Kitty => kitty(&fit(image, columns * CELL_WIDTH, rows * CELL_HEIGHT)),
+        Protocol::Sixel => sixel(&fit(image, columns * CELL_WIDTH, rows * CELL_HEIGHT)),
+        Protocol::HalfBlock => half_blocks(&fit(image, columns, rows * 2)),
+    }
+}
+
+fn kitty(image: &RgbaImage) -> String {
+    let mut png = Cursor::new(vec![]);
+    image.write_to(&mut png, ImageOutputFormat::Png).unwrap();
+    let encoded = base64::engine::general_purpose::STANDARD.encode(png.into_inner());
+
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK).collect();
+    let mut out = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = usize::from(i + 1 < chunks.len());
+        let chunk = std::str::from_utf8(chunk).unwrap();
+        match i {
+            0 => write!(out, "\x1b_Ga=T,f=100,m={more};{chunk}\x1b\\"),
+            _ => write!(out, "\x1b_Gm={more};{chunk}\x1b\\"),
+        }
+        .unwrap();
+    }
+    out
+}
+
+/// Sixels only have a palette, colors are rounded to a 6x6x6 cube like the 256 color one.
+fn palette_index([r, g, b, _]: [u8; 4]) -> usize {
+    let level = |c: u8| (usize::from(c) * 5 + 127) / 255;
+    level(r) * 36 + level(g) * 6 + level(b)
+}
+
+fn sixel(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let mut out = format!("\x1bPq\"1;1;{width};{height}");
+    for i in 0..216 {
+        // Sixel colors are in percent.
+        let percent = |level: usize| level * 100 / 5;
+        write!(
+            out,
+            "#{i};2;{};{};{}",
+            percent(i / 36),
+            percent(i / 6 % 6),
+            percent(i % 6)
+        )
+        .unwrap();
+    }
+
+    // Each band is six pixel rows, drawn once per color that's in it.
+    for band in (0..height).step_by(6) {
+        let mut bits = vec![[0u8; 216]; width as usize];
+        let mut used = vec![false; 216];
+        for x in 0..width {
+            for dy in 0..6.min(height - band) {
+                let pixel = image.get_pixel(x, band + dy).0;
+                // Mostly transparent pixels are left out, so the background shows.
+                if pixel[3] < 128 {
+                    continue;
+                }
+                let color = palette_index(pixel);
+                bits[x as usize][color] |= 1 << dy;
+                used[color] = true;
+            }
+        }
+        for color in (0..216).filter(|color| used[*color]) {
+            write!(out, "#{color}").unwrap();
+            let row: Vec<char> = bits
+                .iter()
+                .map(|column| (63 + column[color]) as char)
+                .collect();
+            push_run_length(&mut out, &row);
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Repeats of the same sixel are written as `!<count><sixel>`.
+fn push_run_length(out: &mut String, row: &[char]) {
+    let mut i = 0;
+    while i < row.len() {
+        let run = row[i..].iter().take_while(|c| **c == row[i]).count();
+        match run {
+            1..=3 => out.extend(std::iter::repeat_n(row[i], run)),
+            _ => write!(out, "!{run}{}", row[i]).unwrap(),
+        }
+        i += run;
+    }
+}
+
+/// An upper half block per cell, colored with the top pixel and the bottom one behind it.
+fn half_blocks(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let mut out = String::new();
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let [r, g, b, _] = image.get_pixel(x, y).0;
+            write!(out, "\x1b[38;2;{r};{g};{b}m").unwrap();
+            match y + 1 < height {
+                true => {
+                    let [r, g, b, _] = image.get_pixel(x, y + 1).0;
+                    write!(out, "\x1b[48;2;{r};{g};{b}m▀").unwrap();
+                }
+                false => out.push_str("\x1b[49m▀"),
+            }
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// A 2x3 image that went through png, like one read from the media folder.
+    fn decoded() -> DynamicImage {
+        let mut image = RgbaImage::new(2, 3);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([0, 255, 0, 255]));
+        image.put_pixel(0, 1, Rgba([0, 0, 255, 255]));
+        image.put_pixel(1, 1, Rgba([255, 255, 255, 255]));
+        image.put_pixel(0, 2, Rgba([0, 0, 0, 255]));
+        image.put_pixel(1, 2, Rgba([0, 0, 0, 0]));
+        let mut png = Cursor::new(vec![]);
+        image.write_to(&mut png, ImageOutputFormat::Png).unwrap();
+        image::load_from_memory(png.get_ref()).unwrap()
+    }
+
+    #[test]
+    fn test_half_blocks() {
+        let rendered = render(&decoded(), Protocol::HalfBlock, 80, 24);
+        assert_eq!(
+            rendered,
+            "\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀\x1b[38;2;0;255;0m\x1b[48;2;255;255;255m▀\x1b[0m\n\
+             \x1b[38;2;0;0;0m\x1b[49m▀\x1b[38;2;0;0;0m\x1b[49m▀\x1b[0m\n"
+        );
+        // Too big for the space it's got, it's scaled down.
+        let rendered = render(&decoded(), Protocol::HalfBlock, 1, 1);
+        assert_eq!(rendered.matches('▀').count(), 1);
+    }
+
+    #[test]
+    fn test_sixel() {
+        let rendered = render(&decoded(), Protocol::Sixel, 80, 24);
+        assert!(rendered.starts_with("\x1bPq\"1;1;2;3#0;2;0;0;0#1;2;0;0;20"));
+        assert!(rendered.ends_with("-\x1b\\"));
+        // Red is only the top left pixel, black is the bottom left one and the transparent
+        // pixel is left out.
+        assert!(rendered.contains("#180@?$"));
+        assert!(rendered.contains("#0C?$"));
+        assert_eq!(palette_index([255, 0, 0, 255]), 180);
+    }
+
+    #[test]
+    fn test_run_length() {
+        let mut out = String::new();
+        push_run_length(&mut out, &['~', '~', '~', '~', '~', '?', '?']);
+        assert_eq!(out, "!5~??");
+    }
+
+    #[test]
+    fn test_kitty() {
+        let rendered = render(&decoded(), Protocol::Kitty, 80, 24);
+        assert!(rendered.starts_with("\x1b_Ga=T,f=100,m=0;"));
+        let payload = rendered
+            .strip_prefix("\x1b_Ga=T,f=100,m=0;")
+            .and_then(|rest| rest.strip_suffix("\x1b\\"))
+            .unwrap();
+        let png = base64::engine::general_purpose::STANDARD
+            .decode(payload)
+            .unwrap();
+        let image = image::load_from_memory(&png).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 3));
+    }
+}
@@ -0,0 +1,513 @@
+//! Importing cards from csv/tsv files.
+//!
+//! Columns are mapped to card fields with a spec like `front=1,back=2,tags=4`, where a column is
+//! either a 1-based number or the name of a column in the header row. Without a spec, a header
+//! row naming the fields directly is used if there is one, otherwise the first two columns are
+//! the front and back.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::card::{Card, CardCache, Meta, Priority, SavedCard, Side};
+use crate::categories::Category;
+use crate::media::AudioSource;
+
+#[derive(Ord, PartialOrd, Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Field {
+    Front,
+    Back,
+    Tags,
+    Priority,
+    Category,
+    AudioLocal,
+    AudioUrl,
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "front" => Ok(Self::Front),
+            "back" => Ok(Self::Back),
+            "tags" => Ok(Self::Tags),
+            "priority" => Ok(Self::Priority),
+            "category" => Ok(Self::Category),
+            "audio_local" => Ok(Self::AudioLocal),
+            "audio_url" => Ok(Self::AudioUrl),
+            other => Err(format!("unknown field: {other}")),
+        }
+    }
+}
+
+/// A field of a card, and the (0-based) column it's read from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ColumnMapping(BTreeMap<Field, usize>);
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self(BTreeMap::from([(Field::Front, 0), (Field::Back, 1)]))
+    }
+}
+
+impl ColumnMapping {
+    /// Parses a spec like `front=1,back=Answer`. Named columns are looked up in the header.
+    pub fn parse(spec: &str, header: Option<&[String]>) -> Result<Self, String> {
+        let mut mapping = BTreeMap::new();
+
+        for pair in spec.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (field, column) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected field=column, got: {pair}"))?;
+            let field: Field = field.parse()?;
+            let column = column.trim();
+
+            let column = match column.parse::<usize>() {
+                Ok(0) => return Err("columns are numbered from 1".to_string()),
+                Ok(num) => num - 1,
+                Err(_) => header
+                    .and_then(|header| header.iter().position(|name| name.trim() == column))
+                    .ok_or_else(|| format!("no column named: {column}"))?,
+            };
+            mapping.insert(field, column);
+        }
+
+        if !mapping.contains_key(&Field::Front) {
+            return Err("the front side needs to be mapped to a column".to_string());
+        }
+
+        Ok(Self(mapping))
+    }
+
+    /// Uses a header row whose column names are the field names themselves.
+    fn from_header(header: &[String]) -> Option<Self> {
+        let mapping: BTreeMap<Field, usize> = header
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, name)| Some((name.parse().ok()?, idx)))
+            .collect();
+
+        mapping.contains_key(&Field::Front).then_some(Self(mapping))
+    }
+
+    fn get<'a>(&self, field: Field, record: &'a [String]) -> Result<Option<&'a str>, String> {
+        match self.0.get(&field) {
+            None => Ok(None),
+            Some(idx) => match record.get(*idx) {
+                Some(value) => Ok(Some(value.as_str())),
+                None => Err(format!(
+                    "row has {} columns, {:?} is mapped to column {}",
+                    record.len(),
+                    field,
+                    idx + 1
+                )),
+            },
+        }
+    }
+
+    fn uses_names(spec: &str) -> bool {
+        spec.split(',')
+            .filter_map(|pair| pair.split_once('='))
+            .any(|(_, column)| column.trim().parse::<usize>().is_err())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportOptions {
+    pub delimiter: char,
+    pub mapping: Option<String>,
+    /// None means we guess, based on whether the first row names the fields.
+    pub has_header: Option<bool>,
+    /// Where cards go when there's no category column, or it's empty.
+    pub category: Category,
+}
+
+impl ImportOptions {
+    /// Tab separated if the file ends with .tsv, comma separated otherwise.
+    pub fn for_path(path: &Path) -> Self {
+        let delimiter = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("tsv") | Some("tab") => '\t',
+            _ => ',',
+        };
+
+        Self {
+            delimiter,
+            mapping: None,
+            has_header: None,
+            category: Category(vec!["imports".into()]),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RowError {
+    /// Line in the file where the row starts.
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug)]
+struct Record {
+    line: usize,
+    fields: Vec<String>,
+}
+
+/// Splits the input into records, quoted fields can contain delimiters, newlines and `""` escapes.
+fn parse_records(input: &str, delimiter: char) -> (Vec<Record>, Option<RowError>) {
+    let mut records = vec![];
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut quote_line = 1;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => {
+                in_quotes = true;
+                quote_line = line;
+            }
+            c if c == delimiter => fields.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                records.push(Record {
+                    line: record_line,
+                    fields: std::mem::take(&mut fields),
+                });
+                line += 1;
+                record_line = line;
+            }
+            c => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        let error = RowError {
+            line: quote_line,
+            message: "quoted field is never closed".to_string(),
+        };
+        return (records, Some(error));
+    }
+
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        records.push(Record {
+            line: record_line,
+            fields,
+        });
+    }
+
+    (records, None)
+}
+
+fn record_to_card(
+    fields: &[String],
+    mapping: &ColumnMapping,
+    default_category: &Category,
+) -> Result<(Category, Card), String> {
+    let front = mapping.get(Field::Front, fields)?.unwrap_or_default();
+    if front.trim().is_empty() {
+        return Err("front side is empty".to_string());
+    }
+    let back = mapping.get(Field::Back, fields)?.unwrap_or_default();
+
+    let tags: BTreeSet<String> = mapping
+        .get(Field::Tags, fields)?
+        .unwrap_or_default()
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect();
+
+    let priority = match mapping.get(Field::Priority, fields)?.map(str::trim) {
+        None | Some("") => Priority::default(),
+        Some(priority) => match priority.parse::<u32>() {
+            Ok(num) if num <= 100 => Priority::from(num),
+            _ => {
+                return Err(format!(
+                    "priority must be a number from 0 to 100: {priority}"
+                ))
+            }
+        },
+    };
+
+    let category = match mapping.get(Field::Category, fields)?.map(str::trim) {
+        None | Some("") => default_category.clone(),
+        Some(category) => Category::from_joined(category),
+    };
+
+    let non_empty = |s: Option<&str>| s.map(str::trim).filter(|s| !s.is_empty()).map(String::from);
+    let audio = AudioSource::new(
+        non_empty(mapping.get(Field::AudioLocal, fields)?),
+        non_empty(mapping.get(Field::AudioUrl, fields)?),
+    );
+
+    let card = Card::new(
+        Side {
+            text: front.to_string(),
+            audio,
+            ..Default::default()
+        },
+        Side {
+            text: back.to_string(),
+            ..Default::default()
+        },
+        Meta {
+            tags,
+            priority,
+            ..Default::default()
+        },
+    );
+
+    Ok((category, card))
+}
+
+/// The result of reading an import file, nothing is saved until you call `save`.
+#[derive(Debug)]
+pub struct CsvImport {
+    pub cards: Vec<(Category, Card)>,
+    pub errors: Vec<RowError>,
+    pub rows: usize,
+}
+
+impl CsvImport {
+    pub fn read(path: &Path, options: &ImportOptions) -> std::io::Result<Self> {
+        let input = std::fs::read_to_string(path)?;
+        Ok(Self::parse(&input, options))
+    }
+
+    pub fn parse(input: &str, options: &ImportOptions) -> Self {
+        let (mut records, parse_error) = parse_records(input, options.delimiter);
+        records.retain(|record| record.fields.iter().any(|field| !field.trim().is_empty()));
+        let mut errors: Vec<RowError> = parse_error.into_iter().collect();
+
+        let header = records.first().map(|record| record.fields.clone());
+        let auto_mapping = header.as_deref().and_then(ColumnMapping::from_header);
+
+        let has_header = options.has_header.unwrap_or(match &options.mapping {
+            Some(spec) => ColumnMapping::uses_names(spec),
+            None => auto_mapping.is_some(),
+        });
+
+        let mapping = match &options.mapping {
+            Some(spec) => {
+                let header = if has_header { header.as_deref() } else { None };
+                match ColumnMapping::parse(spec, header) {
+                    Ok(mapping) => mapping,
+                    Err(message) => {
+                        errors.insert(0, RowError { line: 0, message });
+                        return Self {
+                            cards: vec![],
+                            errors,
+                            rows: 0,
+                        };
+                    }
+                }
+            }
+            None if has_header => auto_mapping.unwrap_or_default(),
+            None => ColumnMapping::default(),
+        };
+
+        if has_header && !records.is_empty() {
+            records.remove(0);
+        }
+
+        let mut cards = vec![];
+        let rows = records.len();
+
+        for record in records {
+            match record_to_card(&record.fields, &mapping, &options.category) {
+                Ok(card) => cards.push(card),
+                Err(message) => errors.push(RowError {
+                    line: record.line,
+                    message,
+                }),
+            }
+        }
+
+        errors.sort_by_key(|error| error.line);
+
+        Self {
+            cards,
+            errors,
+            rows,
+        }
+    }
+
+    /// Reads a file with the front on one line and the back on the next.
+    pub fn read_lines(path: &Path, category: &Category) -> std::io::Result<Self> {
+        let input = std::fs::read_to_string(path)?;
+        Ok(Self::parse_lines(&input, category))
+    }
+
+    pub fn parse_lines(input: &str, category: &Category) -> Self {
+        let lines: Vec<&str> = input.lines().collect();
+        let mut cards = vec![];
+        let mut errors = vec![];
+
+        for (idx, pair) in lines.chunks(2).enumerate() {
+            match pair {
+                [front, back] => cards.push((
+                    category.clone(),
+                    Card::new_simple(front.to_string(), back.to_string()),
+                )),
+                _ => errors.push(RowError {
+                    line: idx * 2 + 1,
+                    message: "front has no back on the line after it".to_string(),
+                }),
+            }
+        }
+
+        Self {
+            cards,
+            errors,
+            rows: lines.len().div_ceil(2),
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut per_category: BTreeMap<String, usize> = BTreeMap::new();
+        for (category, _) in &self.cards {
+            *per_category.entry(category.print_full()).or_default() += 1;
+        }
+
+        let mut s = format!(
+            "rows: {}, cards: {}, errors: {}\n",
+            self.rows,
+            self.cards.len(),
+            self.errors.len()
+        );
+
+        for (category, qty) in per_category {
+            s.push_str(&format!("  {category}: {qty}\n"));
+        }
+
+        for error in &self.errors {
+            s.push_str(&format!("line {}: {}\n", error.line, error.message));
+        }
+        s
+    }
+
+    pub fn save(self, cache: &mut CardCache) -> Vec<SavedCard> {
+        self.cards
+            .into_iter()
+            .map(|(category, card)| card.save_new_card(&category, cache))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> ImportOptions {
+        ImportOptions::for_path(Path::new("import.csv"))
+    }
+
+    #[test]
+    fn test_quoted_fields() {
+        let input = "\"multi\nline\",\"has, comma\"\r\nplain,\"with \"\"quotes\"\"\"\n";
+        let (records, error) = parse_records(input, ',');
+        assert!(error.is_none());
+        let fields: Vec<_> = records.iter().map(|r| r.fields.clone()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                vec!["multi\nline".to_string(), "has, comma".to_string()],
+                vec!["plain".to_string(), "with \"quotes\"".to_string()],
+            ]
+        );
+        assert_eq!(records[1].line, 3);
+    }
+
+    #[test]
+    fn test_unclosed_quote() {
+        let (records, error) = parse_records("a,b\n\"oops,c\n", ',');
+        assert_eq!(records.len(), 1);
+        assert_eq!(error.unwrap().line, 2);
+    }
+
+    #[test]
+    fn test_header_mapping() {
+        let input = "back\tfront\ttags\tcategory\tpriority\n\
+                     hola\thello\tspanish greetings\tlanguages/spanish\t80\n\
+                     adios\tbye\t\t\t\n";
+        let mut options = options();
+        options.delimiter = '\t';
+        let import = CsvImport::parse(input, &options);
+
+        assert!(import.errors.is_empty());
+        assert_eq!(import.rows, 2);
+
+        let (category, card) = &import.cards[0];
+        assert_eq!(category, &Category::from_joined("languages/spanish"));
+        assert_eq!(card.front.text, "hello");
+        assert_eq!(card.back.text, "hola");
+        assert!(card.meta.tags.contains("greetings"));
+        assert_eq!(card.meta.priority, Priority::from(80));
+
+        assert_eq!(import.cards[1].0, options.category);
+    }
+
+    #[test]
+    fn test_mapping_spec() {
+        let input = "Question,Answer,Sound\nq1,a1,q1.mp3\nq2\n,a3,\n";
+        let mut options = options();
+        options.mapping = Some("front=Question,back=2,audio_local=Sound".into());
+        let import = CsvImport::parse(input, &options);
+
+        assert_eq!(import.rows, 3);
+        assert_eq!(import.cards.len(), 1);
+        assert_eq!(
+            import.cards[0].1.front.audio,
+            AudioSource::new(Some("q1.mp3".into()), None)
+        );
+        let lines: Vec<_> = import.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![3, 4]);
+    }
+
+    #[test]
+    fn test_lines() {
+        let category = Category(vec!["imports".into()]);
+        let import = CsvImport::parse_lines("hello\nhola\nbye\nadios\nleft over\n", &category);
+        assert_eq!(import.rows, 3);
+        assert_eq!(import.cards.len(), 2);
+        assert_eq!(import.cards[1].1.back.text, "adios");
+        assert_eq!(
+            import.errors,
+            vec![RowError {
+                line: 5,
+                message: "front has no back on the line after it".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_no_header() {
+        let import = CsvImport::parse("q,a\nq2,a2,extra\n", &options());
+        assert_eq!(import.cards.len(), 2);
+        assert!(ColumnMapping::parse("back=1", None).is_err());
+        assert!(ColumnMapping::parse("front=0", None).is_err());
+    }
+}
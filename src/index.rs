@@ -0,0 +1,323 @@
+//! Persistent mapping from card ids to the files they live in.
+//!
+//! Finding a card by id used to mean parsing every toml file under the cards folder.
+//! The index remembers where each card was last seen along with the file's mtime, so a
+//! lookup is usually a single `stat`. When a file has been moved or renamed outside the app,
+//! the index is rebuilt incrementally: only files whose path or mtime changed get parsed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::{get_last_modified, serde_duration_as_secs};
+use crate::paths::{get_cards_path, get_index_path};
+use crate::Id;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct IndexEntry {
+    // Relative to the cards folder, so the index survives the share path moving.
+    path: PathBuf,
+    #[serde(with = "serde_duration_as_secs")]
+    last_modified: Duration,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct IndexFile {
+    #[serde(default)]
+    cards: BTreeMap<Id, IndexEntry>,
+}
+
+/// Just enough of a card to know its id, cheaper than deserializing the whole thing.
+#[derive(Deserialize)]
+struct IdOnly {
+    meta: MetaIdOnly,
+}
+
+#[derive(Deserialize)]
+struct MetaIdOnly {
+    id: Id,
+}
+
+fn read_id(path: &Path) -> Option<Id> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let card: IdOnly = toml::from_str(&content).ok()?;
+    Some(card.meta.id)
+}
+
+pub struct CardIndex {
+    cards_path: PathBuf,
+    index_path: PathBuf,
+    entries: HashMap<Id, IndexEntry>,
+    /// Changed since it was last written, it's saved once on drop instead of on every change.
+    dirty: bool,
+}
+
+impl CardIndex {
+    pub fn load() -> Self {
+        Self::load_from(get_cards_path(), get_index_path())
+    }
+
+    fn load_from(cards_path: PathBuf, index_path: PathBuf) -> Self {
+        let entries = std::fs::read_to_string(&index_path)
+            .ok()
+            .and_then(|content| toml::from_str::<IndexFile>(&content).ok())
+            .unwrap_or_default()
+            .cards
+            .into_iter()
+            .collect();
+
+        Self {
+            cards_path,
+            index_path,
+            entries,
+            dirty: false,
+        }
+    }
+
+    /// Writes the index if anything changed since it was last written.
+    pub fn save(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        let file = IndexFile {
+            cards: self
+                .entries
+                .iter()
+                .map(|(id, entry)| (*id, entry.clone()))
+                .collect(),
+        };
+        let toml = toml::to_string(&file).unwrap();
+        // The index is only a cache, failing to write it just means a slower lookup next time.
+        let _ = std::fs::write(&self.index_path, toml);
+        self.dirty = false;
+    }
+
+    /// Finds the file of the given card, rebuilding the stale parts of the index if needed.
+    pub fn get_path(&mut self, id: &Id) -> Option<PathBuf> {
+        if let Some(path) = self.verified_path(id) {
+            return Some(path);
+        }
+
+        if self.rebuild() {
+            self.dirty = true;
+        }
+        self.verified_path(id)
+    }
+
+    /// Records where a card is stored, using the current mtime of the file.
+    pub fn insert(&mut self, id: Id, path: &Path) {
+        let Ok(relative) = path.strip_prefix(&self.cards_path) else {
+            return;
+        };
+        let entry = IndexEntry {
+            path: relative.to_path_buf(),
+            last_modified: get_last_modified(path.to_path_buf()),
+        };
+        if self.entries.get(&id) != Some(&entry) {
+            self.entries.insert(id, entry);
+            self.dirty = true;
+        }
+    }
+
+    pub fn remove(&mut self, id: &Id) {
+        if self.entries.remove(id).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// Replaces the whole index, used when all the cards have been loaded anyway.
+    pub fn replace_all(&mut self, cards: impl IntoIterator<Item = (Id, PathBuf)>) {
+        self.entries.clear();
+        for (id, path) in cards {
+            let Ok(relative) = path.strip_prefix(&self.cards_path) else {
+                continue;
+            };
+            let entry = IndexEntry {
+                path: relative.to_path_buf(),
+                last_modified: get_last_modified(path.clone()),
+            };
+            self.entries.insert(id, entry);
+        }
+        self.dirty = true;
+        self.save();
+    }
+
+    /// Returns the path of the card if the index entry still points at it.
+    /// If only the mtime changed, the file is re-read to check that it's still the same card.
+    fn verified_path(&mut self, id: &Id) -> Option<PathBuf> {
+        let entry = self.entries.get(id)?;
+        let path = self.cards_path.join(&entry.path);
+        if !path.is_file() {
+            return None;
+        }
+
+        let last_modified = get_last_modified(path.clone());
+        if last_modified == entry.last_modified {
+            return Some(path);
+        }
+
+        if read_id(&path).as_ref() != Some(id) {
+            return None;
+        }
+
+        self.entries.get_mut(id)?.last_modified = last_modified;
+        self.dirty = true;
+        Some(path)
+    }
+
+    /// Walks the cards folder and re-indexes the files that changed since last time.
+    /// Returns true if anything in the index changed.
+    fn rebuild(&mut self) -> bool {
+        let mut files = vec![];
+        Self::collect_card_files(&self.cards_path, &mut files);
+
+        let known: HashMap<PathBuf, (Id, Duration)> = self
+            .entries
+            .iter()
+            .map(|(id, entry)| (entry.path.clone(), (*id, entry.last_modified)))
+            .collect();
+
+        let mut entries = HashMap::with_capacity(files.len());
+
+        for path in files {
+            let Ok(relative) = path.strip_prefix(&self.cards_path) else {
+                continue;
+            };
+            let last_modified = get_last_modified(path.clone());
+
+            let id = match known.get(relative) {
+                Some((id, modified)) if *modified == last_modified => *id,
+                _ => match read_id(&path) {
+                    Some(id) => id,
+                    None => continue,
+                },
+            };
+
+            entries.insert(
+                id,
+                IndexEntry {
+                    path: relative.to_path_buf(),
+                    last_modified,
+                },
+            );
+        }
+
+        let changed = entries != self.entries;
+        self.entries = entries;
+        changed
+    }
+
+    /// Same rules as the category loading, folders starting with '_' are not part of the collection.
+    fn collect_card_files(dir: &Path, files: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                let hidden = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().starts_with('_'))
+                    .unwrap_or(false);
+                if !hidden {
+                    Self::collect_card_files(&path, files);
+                }
+            } else if path.extension().and_then(|s| s.to_str()) == Some("toml") {
+                files.push(path);
+            }
+        }
+    }
+}
+
+impl Drop for CardIndex {
+    fn drop(&mut self) {
+        self.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths::TempDir;
+    use std::str::FromStr;
+
+    fn copy_dir(from: &Path, to: &Path) {
+        std::fs::create_dir_all(to).unwrap();
+        for entry in std::fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let target = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                copy_dir(&path, &target);
+            } else {
+                std::fs::copy(&path, &target).unwrap();
+            }
+        }
+    }
+
+    /// A copy of the test cards and where their index goes, in a folder removed on drop.
+    fn temp_cards() -> (TempDir, PathBuf, PathBuf) {
+        let dir = TempDir::new("index");
+        let cards = dir.join("cards");
+        copy_dir(Path::new("./testing"), &cards);
+        let index_path = dir.join("index.toml");
+        (dir, cards, index_path)
+    }
+
+    #[test]
+    fn test_lookup_and_persist() {
+        let (_dir, cards, index_path) = temp_cards();
+        let id = Id::from_str("af618145-3da6-48f9-b45c-8d775fc8c7ee").unwrap();
+
+        let mut index = CardIndex::load_from(cards.clone(), index_path.clone());
+        let path = index.get_path(&id).unwrap();
+        assert_eq!(
+            path,
+            cards.join("maths/calculus/af618145-3da6-48f9-b45c-8d775fc8c7ee.toml")
+        );
+        drop(index);
+
+        let mut reloaded = CardIndex::load_from(cards, index_path);
+        assert_eq!(reloaded.entries.len(), 5);
+        assert_eq!(reloaded.verified_path(&id), Some(path));
+    }
+
+    #[test]
+    fn test_moved_card_is_found() {
+        let (_dir, cards, index_path) = temp_cards();
+        let id = Id::from_str("fecee832-e379-47a7-9208-e0a5fdf44464").unwrap();
+
+        let mut index = CardIndex::load_from(cards.clone(), index_path);
+        let old_path = index.get_path(&id).unwrap();
+
+        let new_path = cards.join("maths/renamed.toml");
+        std::fs::rename(&old_path, &new_path).unwrap();
+
+        assert_eq!(index.verified_path(&id), None);
+        assert_eq!(index.get_path(&id), Some(new_path));
+    }
+
+    #[test]
+    fn test_saved_once_on_drop() {
+        let (_dir, cards, index_path) = temp_cards();
+        let id = Id::from_str("8bc35fe2-f02b-4633-8f1b-306eb4e09cd2").unwrap();
+        let path = cards.join("maths/8bc35fe2-f02b-4633-8f1b-306eb4e09cd2.toml");
+
+        let mut index = CardIndex::load_from(cards.clone(), index_path.clone());
+        index.insert(id, &path);
+        assert!(!index_path.exists());
+        drop(index);
+
+        let mut reloaded = CardIndex::load_from(cards, index_path);
+        assert_eq!(reloaded.verified_path(&id), Some(path));
+    }
+
+    #[test]
+    fn test_missing_card() {
+        let (_dir, cards, index_path) = temp_cards();
+        let mut index = CardIndex::load_from(cards, index_path);
+        assert_eq!(index.get_path(&Id::new_v4()), None);
+    }
+}
@@ -0,0 +1,413 @@
+//! Undo and redo for changes to the cards.
+//!
+//! While a transaction is open, every card file that gets written or removed is recorded with
+//! its content before and after. Undoing a transaction puts the old content back exactly as it
+//! was, redoing puts the new content back. The journal is saved in the share folder, so you can
+//! still undo after a restart.
+//!
+//! If a file was changed by something else since (vim, a git pull), undoing would throw that
+//! away, so it's refused instead.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::card::{CardCache, SavedCard};
+use crate::paths::{get_cards_path, get_journal_path};
+use crate::Id;
+
+/// How many transactions we remember, the oldest ones are dropped after this.
+const MAX_TRANSACTIONS: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FileChange {
+    pub id: Id,
+    /// Relative to the cards folder.
+    pub path: PathBuf,
+    /// None means the file didn't exist.
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Transaction {
+    pub description: String,
+    pub changes: Vec<FileChange>,
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    /// The file doesn't look like it did right after the change anymore.
+    Conflict(PathBuf),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conflict(path) => {
+                write!(f, "{} was changed since, not touching it", path.display())
+            }
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for JournalError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+thread_local! {
+    static PENDING: RefCell<Option<Vec<FileChange>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f`, recording every card change it makes as one undoable transaction.
+/// Nested transactions become part of the outer one.
+pub fn transaction<T>(description: &str, f: impl FnOnce() -> T) -> T {
+    let nested = PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let nested = pending.is_some();
+        if !nested {
+            *pending = Some(vec![]);
+        }
+        nested
+    });
+
+    let result = f();
+
+    if !nested {
+        let changes = PENDING.with(|pending| pending.borrow_mut().take().unwrap_or_default());
+        if !changes.is_empty() {
+            let mut journal = Journal::load();
+            journal.push(Transaction {
+                description: description.to_string(),
+                changes,
+            });
+            journal.save();
+        }
+    }
+
+    result
+}
+
+/// Called whenever a card file is written or removed, does nothing outside of a transaction.
+pub fn record(id: Id, path: &Path, before: Option<String>, after: Option<String>) {
+    if before == after {
+        return;
+    }
+    let cards_path = get_cards_path();
+    let path = path.strip_prefix(&cards_path).unwrap_or(path).to_path_buf();
+
+    PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let Some(changes) = pending.as_mut() else {
+            return;
+        };
+
+        // Files written more than once in a transaction only need their first and last state.
+        match changes.iter().position(|change| change.path == path) {
+            Some(idx) => {
+                changes[idx].after = after;
+                if changes[idx].before == changes[idx].after {
+                    changes.remove(idx);
+                }
+            }
+            None => changes.push(FileChange {
+                id,
+                path,
+                before,
+                after,
+            }),
+        }
+    });
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct JournalFile {
+    #[serde(default)]
+    undo: Vec<Transaction>,
+    #[serde(default)]
+    redo: Vec<Transaction>,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    cards_path: PathBuf,
+    undo: Vec<Transaction>,
+    redo: Vec<Transaction>,
+}
+
+impl Journal {
+    pub fn load() -> Self {
+        Self::load_from(get_journal_path(), get_cards_path())
+    }
+
+    fn load_from(path: PathBuf, cards_path: PathBuf) -> Self {
+        let file: JournalFile = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            cards_path,
+            undo: file.undo,
+            redo: file.redo,
+        }
+    }
+
+    pub fn save(&self) {
+        let file = JournalFile {
+            undo: self.undo.clone(),
+            redo: self.redo.clone(),
+        };
+        let toml = toml::to_string(&file).unwrap();
+        // Losing the journal only means losing the undo history, not worth crashing over.
+        let _ = std::fs::write(&self.path, toml);
+    }
+
+    fn push(&mut self, transaction: Transaction) {
+        self.undo.push(transaction);
+        if self.undo.len() > MAX_TRANSACTIONS {
+            self.undo.remove(0);
+        }
+        // A new change means the undone ones can't be redone on top of it anymore.
+        self.redo.clear();
+    }
+
+    /// Sets every file in the changes to one side of it, after checking they're all on the
+    /// other side, so a conflict doesn't leave us half-applied.
+    fn apply(&self, changes: &[FileChange], undo: bool) -> Result<(), JournalError> {
+        let sides = |change: &FileChange| {
+            if undo {
+                (change.after.clone(), change.before.clone())
+            } else {
+                (change.before.clone(), change.after.clone())
+            }
+        };
+
+        for change in changes {
+            let path = self.cards_path.join(&change.path);
+            let current = std::fs::read_to_string(&path).ok();
+            if current != sides(change).0 {
+                return Err(JournalError::Conflict(path));
+            }
+        }
+
+        let mut ordered: Vec<&FileChange> = changes.iter().collect();
+        if undo {
+            ordered.reverse();
+        }
+
+        for change in ordered {
+            let path = self.cards_path.join(&change.path);
+            match sides(change).1 {
+                Some(content) => {
+                    if let Some(parent) = path.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::fs::write(&path, content)?;
+                }
+                None => {
+                    if path.exists() {
+                        std::fs::remove_file(&path)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reverts the last transaction, returns it so the cache can be updated.
+    fn undo_last(&mut self) -> Result<Option<Transaction>, JournalError> {
+        let Some(transaction) = self.undo.last() else {
+            return Ok(None);
+        };
+        self.apply(&transaction.changes, true)?;
+        let transaction = self.undo.pop().unwrap();
+        self.redo.push(transaction.clone());
+        self.save();
+        Ok(Some(transaction))
+    }
+
+    fn redo_last(&mut self) -> Result<Option<Transaction>, JournalError> {
+        let Some(transaction) = self.redo.last() else {
+            return Ok(None);
+        };
+        self.apply(&transaction.changes, false)?;
+        let transaction = self.redo.pop().unwrap();
+        self.undo.push(transaction.clone());
+        self.save();
+        Ok(Some(transaction))
+    }
+}
+
+/// Brings the cache in line with the files a transaction touched.
+fn update_cache(transaction: &Transaction, cache: &mut CardCache) {
+    let cards_path = get_cards_path();
+    // Cards in hidden folders like the trash aren't part of the collection.
+    let live = |change: &FileChange| {
+        let hidden = change
+            .path
+            .components()
+            .any(|part| part.as_os_str().to_string_lossy().starts_with('_'));
+        !hidden && cards_path.join(&change.path).exists()
+    };
+
+    for change in &transaction.changes {
+        let path = cards_path.join(&change.path);
+        if live(change) {
+            // A card that doesn't load anymore shows up with the broken cards instead.
+            if let Ok(card) = SavedCard::from_path(&path) {
+                cache.insert(card);
+            }
+        } else if !transaction
+            .changes
+            .iter()
+            .any(|other| other.id == change.id && live(other))
+        {
+            // A moved card is removed from one path and written to another, only forget
+            // about it if it doesn't exist anywhere anymore.
+            cache.remove(&change.id);
+        }
+    }
+}
+
+/// Undoes the last transaction, returns its description or None if there was nothing to undo.
+pub fn undo(cache: &mut CardCache) -> Result<Option<String>, JournalError> {
+    let transaction = Journal::load().undo_last()?;
+    Ok(transaction.map(|transaction| {
+        update_cache(&transaction, cache);
+        transaction.description
+    }))
+}
+
+pub fn redo(cache: &mut CardCache) -> Result<Option<String>, JournalError> {
+    let transaction = Journal::load().redo_last()?;
+    Ok(transaction.map(|transaction| {
+        update_cache(&transaction, cache);
+        transaction.description
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths::TempDir;
+
+    /// A journal for an empty cards folder, both in a folder removed on drop.
+    fn temp_journal() -> (TempDir, Journal) {
+        let dir = TempDir::new("journal");
+        let cards_path = dir.join("cards");
+        std::fs::create_dir_all(cards_path.join("maths")).unwrap();
+        let journal = Journal::load_from(dir.join("journal.toml"), cards_path);
+        (dir, journal)
+    }
+
+    fn change(path: &str, before: Option<&str>, after: Option<&str>) -> FileChange {
+        FileChange {
+            id: Id::new_v4(),
+            path: PathBuf::from(path),
+            before: before.map(String::from),
+            after: after.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let (_dir, mut journal) = temp_journal();
+        let edited = journal.cards_path.join("maths/a.toml");
+        let moved_to = journal.cards_path.join("b.toml");
+        std::fs::write(&edited, "new").unwrap();
+        std::fs::write(&moved_to, "moved").unwrap();
+
+        journal.push(Transaction {
+            description: "stuff".into(),
+            changes: vec![
+                change("maths/a.toml", Some("old"), Some("new")),
+                change("maths/c.toml", Some("moved"), None),
+                change("b.toml", None, Some("moved")),
+            ],
+        });
+
+        let undone = journal.undo_last().unwrap().unwrap();
+        assert_eq!(undone.description, "stuff");
+        assert_eq!(std::fs::read_to_string(&edited).unwrap(), "old");
+        assert!(!moved_to.exists());
+        let restored = journal.cards_path.join("maths/c.toml");
+        assert_eq!(std::fs::read_to_string(&restored).unwrap(), "moved");
+
+        // Survives a restart.
+        let mut journal = Journal::load_from(journal.path.clone(), journal.cards_path.clone());
+        assert!(journal.undo_last().unwrap().is_none());
+        journal.redo_last().unwrap().unwrap();
+        assert_eq!(std::fs::read_to_string(&edited).unwrap(), "new");
+        assert!(!restored.exists());
+        assert!(moved_to.exists());
+    }
+
+    #[test]
+    fn test_conflict_leaves_files_alone() {
+        let (_dir, mut journal) = temp_journal();
+        let first = journal.cards_path.join("maths/a.toml");
+        let second = journal.cards_path.join("maths/b.toml");
+        std::fs::write(&first, "new").unwrap();
+        std::fs::write(&second, "edited in vim").unwrap();
+
+        journal.push(Transaction {
+            description: "stuff".into(),
+            changes: vec![
+                change("maths/a.toml", Some("old"), Some("new")),
+                change("maths/b.toml", Some("old"), Some("new")),
+            ],
+        });
+
+        assert!(matches!(
+            journal.undo_last(),
+            Err(JournalError::Conflict(_))
+        ));
+        assert_eq!(std::fs::read_to_string(&first).unwrap(), "new");
+        assert_eq!(journal.undo.len(), 1);
+    }
+
+    #[test]
+    fn test_record_collapses_rewrites() {
+        let id = Id::new_v4();
+        let path = Path::new("/somewhere/card.toml");
+        let changes = |writes: &[(Option<&str>, Option<&str>)]| {
+            PENDING.with(|pending| *pending.borrow_mut() = Some(vec![]));
+            for (before, after) in writes {
+                record(id, path, before.map(String::from), after.map(String::from));
+            }
+            PENDING.with(|pending| pending.borrow_mut().take().unwrap())
+        };
+
+        let collapsed = changes(&[(Some("a"), Some("b")), (Some("b"), Some("c"))]);
+        assert_eq!(collapsed.len(), 1);
+        assert_eq!(collapsed[0].before.as_deref(), Some("a"));
+        assert_eq!(collapsed[0].after.as_deref(), Some("c"));
+
+        // Like adding a dependency that creates a cycle, which is then removed again.
+        assert!(changes(&[(Some("a"), Some("b")), (Some("b"), Some("a"))]).is_empty());
+    }
+
+    #[test]
+    fn test_new_change_clears_redo() {
+        let (_dir, mut journal) = temp_journal();
+        std::fs::write(journal.cards_path.join("a.toml"), "new").unwrap();
+        let transaction = Transaction {
+            description: "stuff".into(),
+            changes: vec![change("a.toml", Some("old"), Some("new"))],
+        };
+        journal.push(transaction.clone());
+        journal.undo_last().unwrap();
+        assert_eq!(journal.redo.len(), 1);
+
+        journal.push(transaction);
+        assert!(journal.redo.is_empty());
+    }
+}
@@ -1,18 +1,36 @@
-use config::Config;
-
 use frontend::run;
-use git::git_stuff;
 
 use uuid::Uuid;
 
+#[cfg(feature = "anki")]
+mod anki;
+mod answer;
+mod audio;
 mod card;
 mod categories;
+mod choice;
 mod cli;
+mod cloze;
 mod common;
 mod config;
+mod error;
+mod export;
+mod folder_sync;
 mod frontend;
+mod fsrs;
 mod git;
+mod git_cli;
+#[cfg(feature = "native-git")]
+mod git_native;
+mod images;
+mod import;
+mod index;
+mod journal;
 mod media;
+mod migrate;
+mod scheduler;
+mod search;
+mod trash;
 
 pub mod paths {
     use std::path::PathBuf;
@@ -25,6 +43,18 @@ pub mod paths {
         get_share_path().join("cards")
     }
 
+    pub fn get_index_path() -> PathBuf {
+        get_share_path().join("index.toml")
+    }
+
+    pub fn get_journal_path() -> PathBuf {
+        get_share_path().join("journal.toml")
+    }
+
+    pub fn get_sync_state_path() -> PathBuf {
+        get_share_path().join("sync_state.toml")
+    }
+
     pub fn get_media_path() -> PathBuf {
         get_share_path().join("media/")
     }
@@ -35,9 +65,76 @@ pub mod paths {
         home.join(".local/share/speki/")
     }
 
+    #[cfg(test)]
+    thread_local! {
+        static SHARE: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+    }
+
     #[cfg(test)]
     pub fn get_share_path() -> PathBuf {
-        PathBuf::from("./test_dir/")
+        SHARE
+            .with(|share| share.borrow().clone())
+            .unwrap_or_else(|| PathBuf::from("./test_dir/"))
+    }
+
+    /// A fresh folder for a test, removed again on drop.
+    #[cfg(test)]
+    pub struct TempDir(PathBuf);
+
+    #[cfg(test)]
+    impl TempDir {
+        pub fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("speki-{name}-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    #[cfg(test)]
+    impl std::ops::Deref for TempDir {
+        type Target = std::path::Path;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    #[cfg(test)]
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// An empty share folder for the test running on this thread, removed again on drop.
+    /// For tests that write cards, so they don't end up among the ones in test_dir.
+    #[cfg(test)]
+    pub struct TempShare {
+        _dir: TempDir,
+    }
+
+    #[cfg(test)]
+    impl TempShare {
+        pub fn new() -> Self {
+            let dir = TempDir::new("share");
+            std::fs::create_dir_all(dir.join("cards")).unwrap();
+            SHARE.with(|share| *share.borrow_mut() = Some(dir.to_path_buf()));
+            Self { _dir: dir }
+        }
+    }
+
+    #[cfg(test)]
+    impl Default for TempShare {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    #[cfg(test)]
+    impl Drop for TempShare {
+        fn drop(&mut self) {
+            SHARE.with(|share| *share.borrow_mut() = None);
+        }
     }
 }
 
@@ -46,8 +143,13 @@ type Id = Uuid;
 fn main() {
     std::fs::create_dir_all(paths::get_cards_path()).unwrap();
     std::fs::create_dir_all(paths::get_share_path().join("media/")).unwrap();
+    trash::purge_expired();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(args));
+    }
 
-    let config = Config::load().unwrap();
-    std::thread::spawn(move || git_stuff(config.read_git_remote()));
+    git::sync_in_background();
     run();
 }
@@ -1,10 +1,37 @@
-use std::{fs::File, io::BufReader, path::PathBuf};
-
-//use rodio::Source;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use crate::{config::Config, paths::get_media_path};
+use crate::card::{Card, CardCache};
+use crate::paths::get_media_path;
+use crate::trash;
+use crate::Id;
+
+/// An image on a card side, a file name in the media folder.
+#[derive(Ord, PartialOrd, Eq, PartialEq, Hash, Deserialize, Clone, Serialize, Debug, Default)]
+pub struct ImageSource {
+    #[serde(default, rename = "image", skip_serializing_if = "Option::is_none")]
+    local_name: Option<String>,
+}
+
+impl ImageSource {
+    pub fn new(local_name: Option<String>) -> Self {
+        Self { local_name }
+    }
+
+    pub fn local_file(&self) -> Option<&str> {
+        self.local_name.as_deref()
+    }
+
+    /// Where the image is, if the side has one and it's there.
+    pub fn path(&self) -> Option<PathBuf> {
+        let path = get_media_path().join(self.local_name.as_deref()?);
+        path.exists().then_some(path)
+    }
+}
 
 #[derive(Ord, PartialOrd, Eq, PartialEq, Hash, Deserialize, Clone, Serialize, Debug, Default)]
 pub struct AudioSource {
@@ -24,103 +51,195 @@ impl AudioSource {
         }
     }
 
-    /*
-
-    pub fn _play_audio(&mut self) -> Option<std::thread::JoinHandle<()>> {
-        if !Config::load().ok()?.play_audio {
-            return None;
-        }
-
-        let path = self.get_path()?;
+    pub fn local_file(&self) -> Option<&str> {
+        self.local_name.as_deref()
+    }
 
-        let handle = std::thread::spawn(move || {
-            let (_stream, stream_handle) = rodio::OutputStream::try_default().unwrap();
+    pub fn url(&self) -> Option<&str> {
+        self.url_backup.as_deref()
+    }
 
-            // Load a sound from a file
-            let file = BufReader::new(File::open(path).unwrap());
-            let source = rodio::Decoder::new(file).unwrap();
+    /// Points the side at a different file in the media folder.
+    #[cfg_attr(not(feature = "anki"), allow(dead_code))]
+    pub fn set_local_file(&mut self, name: String) {
+        self.local_name = Some(name);
+    }
+}
 
-            // Play the sound
-            stream_handle.play_raw(source.convert_samples()).unwrap();
+/// The media folder, where files are named after a hash of what's in them. The same file
+/// added twice is stored once, and two different files can't end up with the same name.
+pub struct MediaStore {
+    root: PathBuf,
+}
 
-            // Block the thread until audio is done.
-            std::thread::sleep(std::time::Duration::from_secs(30));
-        });
+/// Which cards use each media file.
+pub type References = BTreeMap<String, BTreeSet<Id>>;
 
-        Some(handle)
+impl MediaStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
     }
-    */
-}
 
-/*
-impl GetMedia for AudioSource {
-    fn local_name(&self) -> Option<String> {
-        self.local_name.clone()
+    pub fn open() -> Self {
+        Self::new(get_media_path())
     }
 
-    fn url_backup(&self) -> Option<String> {
-        self.url_backup.clone()
+    /// Stores the content, returns the name it's stored under. The extension is kept so
+    /// players and image decoders can tell what it is.
+    pub fn add_bytes(&self, content: &[u8], extension: Option<&str>) -> io::Result<String> {
+        let hash: String = Sha256::digest(content)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        let name = match extension.filter(|ext| !ext.is_empty()) {
+            Some(ext) => format!("{hash}.{}", ext.to_lowercase()),
+            None => hash,
+        };
+        let path = self.root.join(&name);
+        if !path.exists() {
+            std::fs::create_dir_all(&self.root)?;
+            // Written next to it first, so a half written file never has the right name.
+            let partial = self.root.join(format!(".{name}.partial"));
+            std::fs::write(&partial, content)?;
+            std::fs::rename(&partial, &path)?;
+        }
+        Ok(name)
     }
 
-    fn update_local(&mut self, name: &str) {
-        self.local_name = Some(name.into());
+    pub fn add(&self, file: &Path) -> io::Result<String> {
+        let extension = file.extension().and_then(|ext| ext.to_str());
+        self.add_bytes(&std::fs::read(file)?, extension)
     }
-}
 
-pub trait GetMedia {
-    fn local_name(&self) -> Option<String>;
-    fn url_backup(&self) -> Option<String>;
-    fn update_local(&mut self, name: &str);
+    /// Every file in the store, dotfiles like `.gitkeep` left out.
+    pub fn files(&self) -> io::Result<BTreeSet<String>> {
+        let entries = match std::fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeSet::new()),
+            Err(e) => return Err(e),
+        };
+        let mut files = BTreeSet::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_file() && !name.starts_with('.') {
+                files.insert(name);
+            }
+        }
+        Ok(files)
+    }
 
-    fn local_name_as_path(&self) -> Option<PathBuf> {
-        let local_file = self.local_name()?;
-        Some(get_media_path().join(local_file))
+    /// Files no card uses.
+    pub fn orphans(&self, references: &References) -> io::Result<Vec<String>> {
+        Ok(self
+            .files()?
+            .into_iter()
+            .filter(|name| !references.contains_key(name))
+            .collect())
     }
 
-    fn get_local_path(&self) -> Option<PathBuf> {
-        let local_name = self.local_name_as_path()?;
-        if local_name.exists() {
-            return Some(local_name);
+    /// Removes the files no card uses, or only lists them with `dry_run`.
+    pub fn collect_garbage(
+        &self,
+        references: &References,
+        dry_run: bool,
+    ) -> io::Result<Vec<String>> {
+        let orphans = self.orphans(references)?;
+        if !dry_run {
+            for name in &orphans {
+                std::fs::remove_file(self.root.join(name))?;
+            }
         }
-        None
+        Ok(orphans)
     }
+}
 
-    fn download_media(&mut self) -> Option<PathBuf> {
-        let url = self.url_backup()?;
-
-        let response = ureq::get(&url).call().unwrap();
-
-        let fname = url.rsplit('/').next().unwrap();
-        let path = get_media_path().join(fname);
-
-        let mut out = std::fs::File::create(&path).unwrap();
-
-        let mut reader = response.into_reader();
-        std::io::copy(&mut reader, &mut out).ok()?;
-
-        self.update_local(fname);
-
-        Some(path)
-    }
+/// What every card uses, the loaded ones and those in the trash.
+pub fn all_references(cache: &mut CardCache) -> References {
+    let loaded: Vec<_> = cache
+        .all_ids()
+        .iter()
+        .filter_map(|id| cache.try_get_ref(id))
+        .collect();
+    let trashed = trash::list();
+    references(
+        loaded
+            .iter()
+            .map(|card| card.card_as_ref())
+            .chain(trashed.iter().map(|trashed| &trashed.card)),
+    )
+}
 
-    fn get_path(&mut self) -> Option<PathBuf> {
-        self.get_local_path().or_else(|| self.download_media())
+/// The media files the cards point to. Pass the trashed cards too, or restoring one would
+/// bring it back without its media.
+pub fn references<'a>(cards: impl IntoIterator<Item = &'a Card>) -> References {
+    let mut references = References::new();
+    for card in cards {
+        for side in [&card.front, &card.back] {
+            let names = [side.audio.local_file(), side.image.local_file()];
+            for name in names.into_iter().flatten() {
+                references
+                    .entry(name.to_string())
+                    .or_default()
+                    .insert(card.meta.id);
+            }
+        }
     }
+    references
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::card::Side;
+    use crate::paths::TempDir;
+
+    /// A store in a folder that's removed when the guard is dropped.
+    fn temp_store() -> (TempDir, MediaStore) {
+        let share = TempDir::new("media");
+        let store = MediaStore::new(share.join("media"));
+        (share, store)
+    }
+
+    #[test]
+    fn test_add_deduplicates() {
+        let (_share, store) = temp_store();
+        let first = store.add_bytes(b"meow", Some("MP3")).unwrap();
+        let again = store.add_bytes(b"meow", Some("mp3")).unwrap();
+        let other = store.add_bytes(b"woof", Some("mp3")).unwrap();
+        let files = store.files().unwrap();
+
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+        assert!(first.ends_with(".mp3"));
+        assert_eq!(first.len(), 64 + ".mp3".len());
+        assert_eq!(files, BTreeSet::from([first, other]));
+    }
 
     #[test]
-    fn test_download_media() {
-        let mut media = AudioSource {
-            local_name: None,
-            url_backup: Some("https://tatoeba.org/en/audio/download/269099".into()),
+    fn test_collect_garbage() {
+        let (_share, store) = temp_store();
+        let used = store.add_bytes(b"meow", Some("mp3")).unwrap();
+        let unused = store.add_bytes(b"woof", Some("png")).unwrap();
+        std::fs::write(store.root.join(".gitkeep"), "").unwrap();
+
+        let mut card = Card::new_simple("cat".into(), "meow".into());
+        card.front = Side {
+            audio: AudioSource::new(Some(used.clone()), None),
+            ..card.front
         };
-        let path = media.get_path().unwrap();
-        assert_eq!(media.local_name.unwrap(), "269099");
-        std::fs::remove_file(path).unwrap();
+        let references = references([&card]);
+        assert_eq!(references[&used], BTreeSet::from([card.meta.id]));
+
+        let listed = store.collect_garbage(&references, true).unwrap();
+        assert_eq!(listed, vec![unused.clone()]);
+        assert!(store.root.join(&unused).exists());
+
+        let removed = store.collect_garbage(&references, false).unwrap();
+        let left = store.files().unwrap();
+        let gitkeep = store.root.join(".gitkeep").exists();
+        assert_eq!(removed, vec![unused]);
+        assert_eq!(left, BTreeSet::from([used]));
+        assert!(gitkeep);
     }
 }
-*/
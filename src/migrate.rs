@@ -0,0 +1,208 @@
+//! Card files carry a `schema_version`, older cards get upgraded in memory when they're loaded.
+//! The files themselves are upgraded when the card is saved again, or by `migrate_all`.
+//!
+//! To change how cards are stored, bump `SCHEMA_VERSION` and add a step to `MIGRATIONS` that
+//! takes a card from the previous version to the new one. Steps work on the raw toml, so they
+//! keep working however `Card` looks later on.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use toml::{Table, Value};
+
+use crate::card::Card;
+use crate::categories::Category;
+use crate::common::current_time;
+use crate::error::{Error, Result};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+struct Migration {
+    /// The version a card is at after this step.
+    to: u32,
+    /// Upgrades the card in place, returns what it changed.
+    apply: fn(&mut Table) -> Vec<String>,
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    to: 1,
+    apply: to_v1,
+}];
+
+/// Cleans up what piled up before there were versions.
+fn to_v1(card: &mut Table) -> Vec<String> {
+    let mut changes = vec![];
+    let Some(Value::Table(meta)) = card.get_mut("meta") else {
+        return changes;
+    };
+
+    if meta.remove("stability").is_some() {
+        changes.push("removed meta.stability, it's worked out from the reviews".to_string());
+    }
+
+    // Suspended used to be a bool, then got timestamps for suspending until a given time.
+    if let Some(Value::Integer(until)) = meta.get("suspended") {
+        let until = Duration::from_secs((*until).max(0) as u64);
+        if until < current_time() {
+            meta.insert("suspended".into(), Value::Boolean(false));
+            changes.push("unsuspended, the suspension ran out".to_string());
+        }
+    }
+
+    if !meta.contains_key("finished") {
+        meta.insert("finished".into(), Value::Boolean(true));
+        changes.push("set meta.finished, it was only true by default".to_string());
+    }
+
+    changes
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Migrated {
+    pub from: u32,
+    pub to: u32,
+    pub changes: Vec<String>,
+    /// The upgraded file.
+    pub content: String,
+}
+
+fn version(path: &Path, card: &Table) -> Result<u32> {
+    let version = match card.get("schema_version") {
+        None => 0,
+        Some(Value::Integer(version)) => u32::try_from(*version).unwrap_or(u32::MAX),
+        Some(_) => u32::MAX,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(Error::NewerSchema {
+            path: path.to_path_buf(),
+            version,
+        });
+    }
+    Ok(version)
+}
+
+/// Upgrades the contents of a card file, or None if it's already up to date.
+pub fn migrate(path: &Path, content: &str) -> Result<Option<Migrated>> {
+    let mut card: Table = toml::from_str(content).map_err(|e| Error::parse(path, content, e))?;
+    let from = version(path, &card)?;
+    if from == SCHEMA_VERSION {
+        return Ok(None);
+    }
+
+    let mut changes = vec![];
+    for migration in MIGRATIONS.iter().filter(|migration| migration.to > from) {
+        changes.extend((migration.apply)(&mut card));
+    }
+
+    // Going through `Card` checks the result and writes it the way cards are always written.
+    let mut upgraded: Card = Value::Table(card)
+        .try_into()
+        .map_err(|e| Error::parse(path, "", e))?;
+    upgraded.schema_version = SCHEMA_VERSION;
+
+    Ok(Some(Migrated {
+        from,
+        to: SCHEMA_VERSION,
+        changes,
+        content: toml::to_string(&upgraded).unwrap(),
+    }))
+}
+
+/// Migrates every card file, or only says what it would do with `dry_run`.
+/// The trash isn't touched, cards are migrated when they're restored.
+pub fn migrate_all(dry_run: bool) -> Vec<(PathBuf, Result<Migrated>)> {
+    let mut migrated = vec![];
+    let paths = Category::load_all()
+        .unwrap_or_default()
+        .into_iter()
+        .flat_map(|category| category.card_paths().unwrap_or_default());
+
+    for path in paths {
+        let result = std::fs::read_to_string(&path)
+            .map_err(|e| Error::io(&path, e))
+            .and_then(|content| migrate(&path, &content));
+        let result = match result {
+            Ok(None) => continue,
+            Ok(Some(done)) if !dry_run => std::fs::write(&path, &done.content)
+                .map(|_| done)
+                .map_err(|e| Error::io(&path, e)),
+            Ok(Some(done)) => Ok(done),
+            Err(e) => Err(e),
+        };
+        migrated.push((path, result));
+    }
+    migrated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixtures(dir: &Path, found: &mut Vec<PathBuf>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                fixtures(&path, found);
+            } else if path.extension().is_some_and(|ext| ext == "toml") {
+                found.push(path);
+            }
+        }
+    }
+
+    /// The fixtures are the oldest cards we have, all of them should still load.
+    #[test]
+    fn test_migrate_fixtures() {
+        let mut paths = vec![];
+        fixtures(Path::new("test_dir/cards"), &mut paths);
+        fixtures(Path::new("testing"), &mut paths);
+        paths.sort();
+        assert!(!paths.is_empty());
+
+        for path in paths {
+            let content = std::fs::read_to_string(&path).unwrap();
+            let migrated = migrate(&path, &content).unwrap().unwrap();
+            assert_eq!(migrated.to, SCHEMA_VERSION);
+            // Migrating twice does nothing.
+            assert_eq!(migrate(&path, &migrated.content).unwrap(), None);
+
+            let name = path
+                .with_extension("")
+                .to_string_lossy()
+                .replace(['/', ' '], "_");
+            let snapshot = format!(
+                "from {} to {}\n{}\n\n{}",
+                migrated.from,
+                migrated.to,
+                migrated.changes.join("\n"),
+                migrated.content
+            );
+            insta::assert_snapshot!(name, snapshot);
+        }
+    }
+
+    #[test]
+    fn test_loading_doesnt_write() {
+        let path = crate::paths::get_cards_path().join("finished.toml");
+        let before = std::fs::read_to_string(&path).unwrap();
+        let card = crate::card::SavedCard::from_path(&path).unwrap();
+        assert_eq!(card.card_as_ref().schema_version, SCHEMA_VERSION);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn test_newer_schema() {
+        let content = "schema_version = 999\n";
+        let err = migrate(Path::new("card.toml"), content).unwrap_err();
+        assert!(matches!(err, Error::NewerSchema { version: 999, .. }));
+    }
+
+    #[test]
+    fn test_expired_suspension() {
+        let mut card: Table =
+            toml::from_str("[meta]\nsuspended = 1000\nfinished = false\n").unwrap();
+        let changes = to_v1(&mut card);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(card["meta"]["suspended"], Value::Boolean(false));
+        assert_eq!(card["meta"]["finished"], Value::Boolean(false));
+    }
+}
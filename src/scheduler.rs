@@ -0,0 +1,287 @@
+//! How reviews turn into memory stability, recall predictions and due dates.
+//!
+//! The model speki has always used lives in `Classic`. Other schedulers can be picked per
+//! category in the config, like so:
+//!
+//! ```toml
+//! [schedulers]
+//! "" = "classic"
+//! "languages/japanese" = "fsrs"
+//! ```
+//!
+//! The most specific category wins, the empty string is the whole collection. `fsrs` uses the
+//! weights in `fsrs_weights`, which `spekinew optimize` fits to your review history.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::card::{Grade, RecallRate, Review};
+use crate::categories::Category;
+use crate::config::Config;
+use crate::fsrs::Fsrs;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryState {
+    pub stability: Duration,
+    /// From 1 to 10, schedulers that don't track difficulty leave it at 0.
+    pub difficulty: f32,
+}
+
+pub trait Scheduler: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Memory state after a review, `time_passed` is the time since the previous review
+    /// and `state` the one before it. Both are None for the first review.
+    /// The review also has how long it took to answer, see `Review::is_slow`.
+    fn next_state(
+        &self,
+        state: Option<MemoryState>,
+        review: &Review,
+        time_passed: Option<Duration>,
+    ) -> MemoryState;
+
+    /// Probability of recalling the card after `time_passed` since the last review.
+    fn recall_rate(&self, time_passed: Duration, stability: Duration) -> RecallRate;
+
+    /// How long after the last review the recall rate drops to `target`.
+    fn interval(&self, stability: Duration, target: RecallRate) -> Duration;
+
+    /// Memory state after all the reviews, None if there's no history or it's out of order.
+    fn memory_state(&self, reviews: &[Review]) -> Option<MemoryState> {
+        let first = reviews.first()?;
+        let mut state = self.next_state(None, first, None);
+        let mut prev_timestamp = first.timestamp;
+
+        for review in &reviews[1..] {
+            if prev_timestamp > review.timestamp {
+                return None;
+            }
+            let time_passed = review.timestamp - prev_timestamp;
+            state = self.next_state(Some(state), review, Some(time_passed));
+            prev_timestamp = review.timestamp;
+        }
+
+        Some(state)
+    }
+
+    fn stability(&self, reviews: &[Review]) -> Option<Duration> {
+        Some(self.memory_state(reviews)?.stability)
+    }
+}
+
+/// How well the scheduler predicted the outcome of each review from the ones before it,
+/// lower is better. Returns the average log-loss and how many reviews were predicted.
+pub fn log_loss<'a>(
+    scheduler: &dyn Scheduler,
+    histories: impl IntoIterator<Item = &'a [Review]>,
+) -> (f64, usize) {
+    let mut loss = 0.;
+    let mut count = 0;
+
+    for reviews in histories {
+        let mut state: Option<MemoryState> = None;
+        let mut prev_timestamp = None;
+
+        for review in reviews {
+            let time_passed = match prev_timestamp {
+                Some(prev) if prev > review.timestamp => break,
+                Some(prev) => Some(review.timestamp - prev),
+                None => None,
+            };
+
+            if let (Some(state), Some(time_passed)) = (state, time_passed) {
+                let recall = scheduler.recall_rate(time_passed, state.stability) as f64;
+                let recall = recall.clamp(0.0001, 0.9999);
+                loss -= if review.grade.is_correct() {
+                    recall.ln()
+                } else {
+                    (1. - recall).ln()
+                };
+                count += 1;
+            }
+
+            state = Some(scheduler.next_state(state, review, time_passed));
+            prev_timestamp = Some(review.timestamp);
+        }
+    }
+
+    if count == 0 {
+        (0., 0)
+    } else {
+        (loss / count as f64, count)
+    }
+}
+
+/// Exponential forgetting curve where stability is the time until recall drops to 90%.
+pub struct Classic;
+
+impl Classic {
+    fn grade_factor(review: &Review) -> f32 {
+        match review.grade {
+            Grade::None => 0.1,
+            Grade::Late => 0.25,
+            Grade::Some if review.is_slow() => 1.5,
+            Grade::Some => 2.,
+            Grade::Perfect => 3.,
+        }
+    }
+}
+
+impl Scheduler for Classic {
+    fn name(&self) -> &'static str {
+        "classic"
+    }
+
+    fn next_state(
+        &self,
+        state: Option<MemoryState>,
+        review: &Review,
+        time_passed: Option<Duration>,
+    ) -> MemoryState {
+        let grade_factor = Self::grade_factor(review);
+        let time_passed = time_passed.unwrap_or(Duration::from_secs(86400));
+        let current = state.map_or(Duration::from_secs(86400), |state| state.stability);
+
+        let stability = if grade_factor < 1.0 {
+            // the grade is wrong
+            time_passed.min(current).mul_f32(grade_factor)
+        } else {
+            // the grade is correct
+            let alternative_stability = time_passed.mul_f32(grade_factor);
+            if alternative_stability > current {
+                alternative_stability
+            } else {
+                let interpolation_ratio =
+                    time_passed.as_secs_f32() / current.as_secs_f32() * grade_factor;
+                current + Duration::from_secs_f32(current.as_secs_f32() * interpolation_ratio)
+            }
+        };
+
+        MemoryState {
+            stability,
+            difficulty: 0.,
+        }
+    }
+
+    fn recall_rate(&self, time_passed: Duration, stability: Duration) -> RecallRate {
+        let base: f32 = 0.9;
+        let ratio = time_passed.as_secs_f32() / stability.as_secs_f32();
+        (base.ln() * ratio).exp()
+    }
+
+    fn interval(&self, stability: Duration, target: RecallRate) -> Duration {
+        let base: f32 = 0.9;
+        stability.mul_f32(target.ln() / base.ln())
+    }
+}
+
+/// Looks up a scheduler by the name used in the config.
+pub fn by_name(name: &str, config: &Config) -> Option<Box<dyn Scheduler>> {
+    match name {
+        "classic" | "default" => Some(Box::new(Classic)),
+        "fsrs" => Some(Box::new(Fsrs::from_config(config))),
+        _ => None,
+    }
+}
+
+type Choices = Vec<(Category, Box<dyn Scheduler>)>;
+
+/// Most specific category first, so the first match is the one to use.
+fn resolve(config: &Config) -> Choices {
+    let mut choices: Choices = config
+        .schedulers
+        .iter()
+        .filter_map(|(category, name)| {
+            Some((
+                Category::from_joined(category),
+                by_name(name.trim(), config)?,
+            ))
+        })
+        .collect();
+    choices.sort_by_key(|(category, _)| std::cmp::Reverse(category.0.len()));
+    choices
+}
+
+fn pick<'a>(choices: &'a Choices, category: &Category) -> &'a dyn Scheduler {
+    choices
+        .iter()
+        .find(|(parent, _)| category.0.starts_with(&parent.0))
+        .map(|(_, scheduler)| scheduler.as_ref())
+        .unwrap_or(&Classic)
+}
+
+/// The scheduler configured for the category. The config is only read once, it's asked for a lot.
+pub fn for_category(category: &Category) -> &'static dyn Scheduler {
+    static CHOICES: OnceLock<Choices> = OnceLock::new();
+    let choices = CHOICES.get_or_init(|| {
+        Config::load()
+            .map(|config| resolve(&config))
+            .unwrap_or_default()
+    });
+    pick(choices, category)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_interval_matches_recall() {
+        let stability = Duration::from_secs(86400 * 10);
+        let interval = Classic.interval(stability, 0.8);
+        let recall = Classic.recall_rate(interval, stability);
+        assert!((recall - 0.8).abs() < 0.001);
+        assert_eq!(Classic.interval(stability, 0.9), stability);
+    }
+
+    #[test]
+    fn test_slow_answer_counts_less() {
+        let day = Duration::from_secs(86400);
+        let first = Review {
+            grade: Grade::Some,
+            ..Default::default()
+        };
+        let fast = Review {
+            timestamp: day * 3,
+            grade: Grade::Some,
+            time_spent: Duration::from_secs(3),
+            ..Default::default()
+        };
+        let slow = Review {
+            time_spent: Duration::from_secs(40),
+            ..fast.clone()
+        };
+
+        for scheduler in [&Classic as &dyn Scheduler, &Fsrs::default()] {
+            let fast = scheduler.stability(&[first.clone(), fast.clone()]).unwrap();
+            let slow = scheduler.stability(&[first.clone(), slow.clone()]).unwrap();
+            assert!(slow < fast, "{}", scheduler.name());
+        }
+    }
+
+    #[test]
+    fn test_pick_most_specific() {
+        let config = Config {
+            schedulers: BTreeMap::from([
+                ("".to_string(), "classic".to_string()),
+                ("maths".to_string(), "nonexistent".to_string()),
+                ("maths/calculus".to_string(), "fsrs".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let choices = resolve(&config);
+        assert_eq!(choices.len(), 2);
+        assert_eq!(choices[0].0, Category::from_joined("maths/calculus"));
+
+        let picked = pick(&choices, &Category::from_joined("maths/calculus/limits"));
+        assert_eq!(picked.name(), "fsrs");
+        let picked = pick(&choices, &Category::from_joined("maths"));
+        assert_eq!(picked.name(), "classic");
+        let empty = Choices::new();
+        assert_eq!(
+            pick(&empty, &Category::from_joined("maths")).name(),
+            "classic"
+        );
+    }
+}
@@ -0,0 +1,460 @@
+//! Finding cards, with an inverted index the card cache keeps up to date.
+//!
+//! A query is words and filters, all of which have to match:
+//!
+//! ```text
+//! capital tag:geography cat:europe front:paris finished:yes suspended:no recall<0.8 stability>=7
+//! ```
+//!
+//! Words match the start of words on either side of the card, `front:` only on the front.
+//! `cat:` includes subcategories, stability is in days, `-` in front of anything negates it,
+//! and values with spaces go in double quotes. Results are ranked by how well the words match.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::answer::normalize;
+use crate::card::SavedCard;
+use crate::categories::Category;
+use crate::Id;
+
+/// Matching the front counts for more than matching the back.
+const FRONT_WEIGHT: f32 = 2.;
+/// A word that only starts with the query word counts for less than the whole word.
+const PREFIX_WEIGHT: f32 = 0.5;
+
+fn words(text: &str) -> Vec<String> {
+    normalize(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+}
+
+impl Comparison {
+    fn holds(self, value: f32, than: f32) -> bool {
+        match self {
+            Self::Less => value < than,
+            Self::LessOrEqual => value <= than,
+            Self::Greater => value > than,
+            Self::GreaterOrEqual => value >= than,
+            Self::Equal => value == than,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Filter {
+    Word(String),
+    Front(String),
+    Tag(String),
+    Category(Category),
+    Finished(bool),
+    Suspended(bool),
+    Recall(Comparison, f32),
+    /// In days.
+    Stability(Comparison, f32),
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Query {
+    /// Each with whether it's negated.
+    filters: Vec<(Filter, bool)>,
+}
+
+/// Splits on whitespace, except inside double quotes.
+fn tokens(query: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+fn yes_or_no(key: &str, value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" | "true" | "y" | "1" => Ok(true),
+        "no" | "false" | "n" | "0" => Ok(false),
+        _ => Err(format!("{key}: takes yes or no, not {value}")),
+    }
+}
+
+/// `recall<0.8` and the like, None if the token isn't a comparison.
+fn comparison(token: &str) -> Option<Result<Filter, String>> {
+    let key_end = token.find(['<', '>', '='])?;
+    let (key, rest) = token.split_at(key_end);
+    let (comparison, value) = [
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+        ("=", Comparison::Equal),
+    ]
+    .into_iter()
+    .find_map(|(op, comparison)| rest.strip_prefix(op).map(|value| (comparison, value)))?;
+    let filter: fn(Comparison, f32) -> Filter = match key.to_lowercase().as_str() {
+        "recall" => Filter::Recall,
+        "stability" => Filter::Stability,
+        _ => return None,
+    };
+    Some(match value.parse() {
+        Ok(value) => Ok(filter(comparison, value)),
+        Err(_) => Err(format!("{key} has to be compared to a number, not {value}")),
+    })
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut filters = vec![];
+        for token in tokens(query) {
+            let (token, negated) = match token.strip_prefix('-') {
+                Some(rest) if !rest.is_empty() => (rest.to_string(), true),
+                _ => (token, false),
+            };
+            if let Some(filter) = comparison(&token) {
+                filters.push((filter?, negated));
+                continue;
+            }
+            let filter = match token.split_once(':') {
+                Some(("tag", tag)) => Filter::Tag(tag.to_lowercase()),
+                Some(("cat", category)) => Filter::Category(Category::from_joined(category)),
+                Some(("finished", value)) => Filter::Finished(yes_or_no("finished", value)?),
+                Some(("suspended", value)) => Filter::Suspended(yes_or_no("suspended", value)?),
+                Some(("front", text)) => {
+                    for word in words(text) {
+                        filters.push((Filter::Front(word), negated));
+                    }
+                    continue;
+                }
+                // Anything else is just text, like a time with a colon in it.
+                _ => {
+                    for word in words(&token) {
+                        filters.push((Filter::Word(word), negated));
+                    }
+                    continue;
+                }
+            };
+            filters.push((filter, negated));
+        }
+        Ok(Self { filters })
+    }
+
+    /// The words that rank the results, the ones that aren't negated.
+    fn ranking_words(&self) -> impl Iterator<Item = (&str, bool)> {
+        self.filters
+            .iter()
+            .filter_map(|(filter, negated)| match filter {
+                Filter::Word(word) if !negated => Some((word.as_str(), false)),
+                Filter::Front(word) if !negated => Some((word.as_str(), true)),
+                _ => None,
+            })
+    }
+}
+
+/// How often a word is on each side of a card.
+#[derive(Debug, Default, Clone, Copy)]
+struct Posting {
+    front: u16,
+    back: u16,
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+    postings: BTreeMap<String, HashMap<Id, Posting>>,
+    cards: HashMap<Id, Arc<SavedCard>>,
+}
+
+impl SearchIndex {
+    /// Adds the card, or updates it if it's in already.
+    pub fn insert(&mut self, card: Arc<SavedCard>) {
+        let id = *card.id();
+        self.remove(&id);
+        let card_ref = card.card_as_ref();
+        for (text, front) in [(&card_ref.front.text, true), (&card_ref.back.text, false)] {
+            for word in words(text) {
+                let posting = self
+                    .postings
+                    .entry(word)
+                    .or_default()
+                    .entry(id)
+                    .or_default();
+                match front {
+                    true => posting.front = posting.front.saturating_add(1),
+                    false => posting.back = posting.back.saturating_add(1),
+                }
+            }
+        }
+        self.cards.insert(id, card);
+    }
+
+    pub fn remove(&mut self, id: &Id) {
+        let Some(card) = self.cards.remove(id) else {
+            return;
+        };
+        let card = card.card_as_ref();
+        for word in words(&card.front.text)
+            .into_iter()
+            .chain(words(&card.back.text))
+        {
+            if let Some(postings) = self.postings.get_mut(&word) {
+                postings.remove(id);
+                if postings.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// The cards with a word starting with `prefix`, and how well they match it.
+    fn prefix_matches(&self, prefix: &str, front_only: bool) -> HashMap<Id, f32> {
+        let mut scores = HashMap::new();
+        let total = self.cards.len().max(1) as f32;
+        for (word, postings) in self.postings.range(prefix.to_string()..) {
+            if !word.starts_with(prefix) {
+                break;
+            }
+            // Rare words say more about a card than common ones.
+            let rarity = (1. + total / postings.len() as f32).ln();
+            let exact = if word == prefix { 1. } else { PREFIX_WEIGHT };
+            for (id, posting) in postings {
+                let back = if front_only { 0. } else { posting.back as f32 };
+                let hits = FRONT_WEIGHT * posting.front as f32 + back;
+                if hits > 0. {
+                    *scores.entry(*id).or_default() += hits * rarity * exact;
+                }
+            }
+        }
+        scores
+    }
+
+    fn matches(card: &SavedCard, filter: &Filter) -> bool {
+        let meta = &card.card_as_ref().meta;
+        match filter {
+            // Handled through the postings.
+            Filter::Word(_) | Filter::Front(_) => true,
+            Filter::Tag(tag) => meta.tags.iter().any(|t| t.to_lowercase() == *tag),
+            Filter::Category(category) => {
+                let of_card = card.category().0.iter().map(|c| c.to_lowercase());
+                let wanted = category.0.iter().map(|c| c.to_lowercase());
+                wanted.len() <= card.category().0.len() && of_card.zip(wanted).all(|(a, b)| a == b)
+            }
+            Filter::Finished(finished) => card.is_finished() == *finished,
+            Filter::Suspended(suspended) => card.is_suspended() == *suspended,
+            Filter::Recall(comparison, than) => card
+                .recall_rate()
+                .is_some_and(|recall| comparison.holds(recall, *than)),
+            Filter::Stability(comparison, days) => card
+                .stability()
+                .is_some_and(|stability| comparison.holds(stability.as_secs_f32() / 86400., *days)),
+        }
+    }
+
+    /// The cards matching the query, best match first.
+    pub fn search(&self, query: &Query) -> Vec<Id> {
+        // Start from the words, they narrow it down the most.
+        let mut scores: Option<HashMap<Id, f32>> = None;
+        for (word, front_only) in query.ranking_words() {
+            let matches = self.prefix_matches(word, front_only);
+            scores = Some(match scores {
+                None => matches,
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(id, score)| matches.get(&id).map(|more| (id, score + more)))
+                    .collect(),
+            });
+        }
+        let scores = scores.unwrap_or_else(|| self.cards.keys().map(|id| (*id, 0.)).collect());
+
+        // Negated words were left out of the ranking, so what they match is left out here.
+        let mut excluded: HashSet<Id> = HashSet::new();
+        for (filter, negated) in &query.filters {
+            if let (Filter::Word(word) | Filter::Front(word), true) = (filter, negated) {
+                let front_only = matches!(filter, Filter::Front(_));
+                excluded.extend(self.prefix_matches(word, front_only).into_keys());
+            }
+        }
+
+        let mut found: Vec<(&Arc<SavedCard>, f32)> = scores
+            .into_iter()
+            .filter(|(id, _)| !excluded.contains(id))
+            .filter_map(|(id, score)| Some((self.cards.get(&id)?, score)))
+            .filter(|(card, _)| {
+                query.filters.iter().all(|(filter, negated)| match filter {
+                    Filter::Word(_) | Filter::Front(_) => true,
+                    filter => Self::matches(card, filter) != *negated,
+                })
+            })
+            .collect();
+
+        found.sort_by(|(a, a_score), (b, b_score)| {
+            b_score
+                .partial_cmp(a_score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.front_text().cmp(b.front_text()))
+                .then_with(|| a.id().cmp(b.id()))
+        });
+        found.into_iter().map(|(card, _)| *card.id()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::card::{Card, CardLocation};
+    use crate::paths::{get_cards_path, TempShare};
+
+    /// Cards in a share of their own, only the category folders are written.
+    struct Deck {
+        _share: TempShare,
+    }
+
+    impl Deck {
+        fn new() -> Self {
+            Self {
+                _share: TempShare::new(),
+            }
+        }
+
+        fn card(&self, category: &str, front: &str, back: &str, tags: &[&str]) -> Arc<SavedCard> {
+            let mut card = Card::new_simple(front.into(), back.into());
+            card.meta.tags = tags.iter().map(|tag| tag.to_string()).collect();
+            let folder = get_cards_path().join(category);
+            std::fs::create_dir_all(&folder).unwrap();
+            let path = folder.join(format!("{}.toml", card.meta.id));
+            let location = CardLocation::new(&path).unwrap();
+            Arc::new(SavedCard::new(card, location, Duration::default()))
+        }
+    }
+
+    fn fronts(index: &SearchIndex, query: &str) -> Vec<String> {
+        let query = Query::parse(query).unwrap();
+        index
+            .search(&query)
+            .iter()
+            .map(|id| index.cards[id].front_text().to_string())
+            .collect()
+    }
+
+    fn index(deck: &Deck) -> SearchIndex {
+        let mut index = SearchIndex::default();
+        index.insert(deck.card("europe/france", "capital of France", "Paris", &["geo"]));
+        index.insert(deck.card("europe/spain", "capital of Spain", "Madrid", &["geo"]));
+        index.insert(deck.card(
+            "maths",
+            "derivative of x²",
+            "2x, like a capital letter",
+            &[],
+        ));
+        index.insert(deck.card("europe", "Paris is in", "France", &["Travel"]));
+        index
+    }
+
+    #[test]
+    fn test_query_parse() {
+        let query =
+            Query::parse(r#"Café -tag:geo cat:"guten tag" recall<=0.5 -finished:no"#).unwrap();
+        assert_eq!(
+            query.filters,
+            vec![
+                (Filter::Word("cafe".into()), false),
+                (Filter::Tag("geo".into()), true),
+                (Filter::Category(Category(vec!["guten tag".into()])), false),
+                (Filter::Recall(Comparison::LessOrEqual, 0.5), false),
+                (Filter::Finished(false), true),
+            ]
+        );
+        assert!(Query::parse("finished:maybe").is_err());
+        assert!(Query::parse("stability>soon").is_err());
+        assert_eq!(
+            Query::parse("at 12:30").unwrap().filters.len(),
+            3,
+            "unknown fields are just text"
+        );
+    }
+
+    #[test]
+    fn test_ranking() {
+        let deck = Deck::new();
+        let index = index(&deck);
+        // On the front beats on the back, whole words beat prefixes.
+        assert_eq!(
+            fronts(&index, "capital"),
+            vec!["capital of France", "capital of Spain", "derivative of x²"]
+        );
+        assert_eq!(fronts(&index, "paris")[0], "Paris is in");
+        assert_eq!(fronts(&index, "capital fra"), vec!["capital of France"]);
+        assert_eq!(fronts(&index, "front:paris"), vec!["Paris is in"]);
+    }
+
+    #[test]
+    fn test_filters() {
+        let deck = Deck::new();
+        let index = index(&deck);
+        assert_eq!(fronts(&index, "tag:travel"), vec!["Paris is in"]);
+        assert_eq!(fronts(&index, "cat:europe -tag:geo"), vec!["Paris is in"]);
+        assert_eq!(fronts(&index, "cat:Europe/France").len(), 1);
+        assert_eq!(fronts(&index, "capital -spain").len(), 2);
+        assert_eq!(fronts(&index, "finished:yes suspended:no").len(), 4);
+        // Never reviewed, so no recall to compare.
+        assert!(fronts(&index, "recall<1").is_empty());
+    }
+
+    #[test]
+    fn test_update_and_remove() {
+        let deck = Deck::new();
+        let mut index = index(&deck);
+        let id = index.search(&Query::parse("madrid").unwrap())[0];
+        let mut updated = Card::new_simple("capital of Spain".into(), "Barcelona? no".into());
+        updated.meta.id = id;
+        let location = CardLocation::new(&index.cards[&id].as_path()).unwrap();
+        let updated = SavedCard::new(updated, location, Duration::default());
+        index.insert(Arc::new(updated));
+        assert!(fronts(&index, "madrid").is_empty());
+        assert_eq!(fronts(&index, "barcelona"), vec!["capital of Spain"]);
+
+        index.remove(&id);
+        assert!(fronts(&index, "barcelona").is_empty());
+        assert!(!index.postings.contains_key("barcelona"));
+    }
+
+    #[test]
+    fn test_many_cards() {
+        let deck = Deck::new();
+        let mut index = SearchIndex::default();
+        for i in 0..10_000 {
+            let front = format!("question {i} about topic{}", i % 100);
+            index.insert(deck.card("bulk", &front, &format!("answer {i}"), &[]));
+        }
+        let found = |query: &str| index.search(&Query::parse(query).unwrap()).len();
+        assert_eq!(found("topic42 question"), 100);
+        // topic4 and topic40 to topic49.
+        assert_eq!(found("topic4"), 1100);
+        assert_eq!(found("question -topic4"), 8900);
+        assert_eq!(found("-topic4 -topic5"), 7800);
+    }
+}
@@ -0,0 +1,217 @@
+//! Deleted cards go to the hidden `_trash` category instead of disappearing.
+//!
+//! A trashed card is its normal toml with a `[trash]` table appended, saying where it was and
+//! which cards it was connected to, so restoring it puts both the card and its edges back.
+//! Cards that have been in the trash longer than `trash_retention_days` are purged on startup.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::card::{Card, CardCache, SavedCard};
+use crate::categories::Category;
+use crate::common::{current_time, serde_duration_as_secs};
+use crate::config::Config;
+use crate::git::{self, Change};
+use crate::journal;
+use crate::Id;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TrashInfo {
+    /// Joined category the card was deleted from.
+    pub category: String,
+    #[serde(with = "serde_duration_as_secs")]
+    pub deleted_at: Duration,
+    /// The edges to other cards at the time it was deleted.
+    pub dependencies: BTreeSet<Id>,
+    pub dependents: BTreeSet<Id>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TrashTable {
+    trash: TrashInfo,
+}
+
+pub struct TrashedCard {
+    pub card: Card,
+    pub info: TrashInfo,
+    pub path: PathBuf,
+}
+
+pub fn trash_category() -> Category {
+    Category(vec!["_trash".into()])
+}
+
+fn to_trash_file(card: &Card, info: &TrashInfo) -> String {
+    let card = toml::to_string(card).unwrap();
+    let trash = toml::to_string(&TrashTable {
+        trash: info.clone(),
+    })
+    .unwrap();
+    format!("{card}\n{trash}")
+}
+
+/// The card itself doesn't know about the trash table, so both are read from the same file.
+fn parse_trash_file(content: &str) -> Option<(Card, TrashInfo)> {
+    let card: Card = toml::from_str(content).ok()?;
+    let table: TrashTable = toml::from_str(content).ok()?;
+    Some((card, table.trash))
+}
+
+/// Writes the card to the trash, the caller removes the original.
+pub fn move_to_trash(card: &SavedCard) {
+    let info = TrashInfo {
+        category: card.category().joined(),
+        deleted_at: current_time(),
+        dependencies: card.dependency_ids().clone(),
+        dependents: card.dependent_ids().clone(),
+    };
+    let dir = trash_category().as_path();
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}.toml", card.id()));
+    let content = to_trash_file(card.card_as_ref(), &info);
+
+    let before = std::fs::read_to_string(&path).ok();
+    std::fs::write(&path, &content).unwrap();
+    journal::record(*card.id(), &path, before, Some(content));
+}
+
+fn list_in(dir: &Path) -> Vec<TrashedCard> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut cards: Vec<TrashedCard> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("toml"))
+        .filter_map(|path| {
+            let content = std::fs::read_to_string(&path).ok()?;
+            let (card, info) = parse_trash_file(&content)?;
+            Some(TrashedCard { card, info, path })
+        })
+        .collect();
+    cards.sort_by_key(|trashed| std::cmp::Reverse(trashed.info.deleted_at));
+    cards
+}
+
+/// Everything in the trash, most recently deleted first.
+pub fn list() -> Vec<TrashedCard> {
+    list_in(&trash_category().as_path())
+}
+
+/// Puts the card back where it was, and reconnects it to the cards it was connected to that
+/// still exist. Returns None if the card isn't in the trash.
+pub fn restore(id: &Id, cache: &mut CardCache) -> Option<SavedCard> {
+    let trashed = list()
+        .into_iter()
+        .find(|trashed| trashed.card.meta.id == *id)?;
+    let TrashedCard {
+        mut card,
+        info,
+        path,
+    } = trashed;
+
+    let dependencies: BTreeSet<Id> = info
+        .dependencies
+        .into_iter()
+        .filter(|id| cache.exists(id))
+        .collect();
+    let dependents: BTreeSet<Id> = info
+        .dependents
+        .into_iter()
+        .filter(|id| cache.exists(id))
+        .collect();
+    card.meta.dependencies = dependencies.clone();
+    card.meta.dependents = dependents.clone();
+
+    let content = std::fs::read_to_string(&path).ok();
+    std::fs::remove_file(&path).ok()?;
+    journal::record(*id, &path, content, None);
+
+    let category = Category::from_joined(&info.category);
+    git::note(Change::Other(format!("restore: '{}'", card.front.text)));
+    let restored = card.save_to(&category, cache);
+
+    for dependency in &dependencies {
+        let mut other = cache.get_owned(dependency);
+        other.insert_dependent_raw(*id);
+    }
+    for dependent in &dependents {
+        let mut other = cache.get_owned(dependent);
+        other.insert_dependency_raw(*id);
+    }
+    Some(restored)
+}
+
+fn purge_in(dir: &Path, retention: Duration, now: Duration) -> usize {
+    let mut purged = 0;
+    for trashed in list_in(dir) {
+        if now.saturating_sub(trashed.info.deleted_at) > retention
+            && std::fs::remove_file(&trashed.path).is_ok()
+        {
+            purged += 1;
+        }
+    }
+    purged
+}
+
+/// Permanently removes cards that have been in the trash for longer than the config allows.
+pub fn purge_expired() -> usize {
+    let Ok(config) = Config::load() else {
+        return 0;
+    };
+    if config.trash_retention_days == 0 {
+        return 0;
+    }
+    let retention = Duration::from_secs(config.trash_retention_days as u64 * 86400);
+    purge_in(&trash_category().as_path(), retention, current_time())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(deleted_at: u64) -> TrashInfo {
+        TrashInfo {
+            category: "maths/calculus".into(),
+            deleted_at: Duration::from_secs(deleted_at),
+            dependencies: BTreeSet::from([Id::new_v4()]),
+            dependents: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn test_trash_file_roundtrip() {
+        let mut card = Card::new_simple("front".into(), "back".into());
+        card.meta.dependencies = info(0).dependencies;
+        let info = info(1000);
+
+        let content = to_trash_file(&card, &info);
+        let (parsed_card, parsed_info) = parse_trash_file(&content).unwrap();
+        assert_eq!(parsed_card, card);
+        assert_eq!(parsed_info, info);
+
+        // Regular cards aren't trash files.
+        assert!(parse_trash_file(&toml::to_string(&card).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_purge() {
+        let dir = crate::paths::TempDir::new("trash");
+        let day = 86400;
+        for (name, deleted_at) in [("old", 0), ("new", 9 * day)] {
+            let card = Card::new_simple(name.into(), "".into());
+            let content = to_trash_file(&card, &info(deleted_at));
+            std::fs::write(dir.join(format!("{name}.toml")), content).unwrap();
+        }
+
+        let now = Duration::from_secs(10 * day);
+        assert_eq!(purge_in(&dir, Duration::from_secs(5 * day), now), 1);
+        let left = list_in(&dir);
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].card.front.text, "new");
+    }
+}